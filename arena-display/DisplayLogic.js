@@ -147,6 +147,14 @@ function updatePage_onWaitingOnPlayers(msgData)
 
 }
 
+//update page text with the seconds left in the countdown
+function update_onCountdownTick(msgData)
+{
+    severStatus = document.getElementById("curStatus");
+    severStatus.innerHTML = "<br>Server Status: Server starting soon" + 
+                            "<br>Seconds left: " + msgData.secondsLeft; 
+}
+
 //update page text to show game starting soon
 function update_onStartingSoon(msgData)
 {
@@ -585,6 +593,11 @@ function runBattlefieldDisplay(addrAndPort,width,height)
                             updateArena(battlefieldCanvas,"gameStartingSoon",width,height);
                         break; 
 
+                        case "countdownTick":
+                            //only the seconds left changed
+                            update_onCountdownTick(msgData);
+                        break; 
+
                         case "gameStarting":
                             //game is starting
                             //updated player data and move order.
@@ -6,6 +6,7 @@ import {
   RequestType,
   GameState,
   PlayerAction,
+  PROTOCOL_VERSION,
 } from "./protocol";
 import { AttackAdjacentPlayerState } from "./state/attackAdjacentPlayer";
 import { AttackPlayerState } from "./state/attackPlayer";
@@ -65,6 +66,16 @@ export class Algorithm {
    */
   private onMessage(msg: GameServerResponse): void {
     switch (msg.type) {
+      // Stop if the server speaks a different protocol version
+      case ResponseType.GameStarting:
+        if (msg.protocolVersion !== PROTOCOL_VERSION) {
+          console.error(
+            `Protocol version mismatch: server uses ${msg.protocolVersion}, client expects ${PROTOCOL_VERSION}`
+          );
+          this.ws.close();
+        }
+        break;
+
       // Register for the next game once the game ends
       case ResponseType.GameEnded: {
         this.isKilled = false;
@@ -1,5 +1,8 @@
 export type Uuid = string;
 
+// Must match the "protocolVersion" sent by the server
+export const PROTOCOL_VERSION = 2;
+
 export interface JWTPlayerData {
   name: string;
 }
@@ -144,6 +147,7 @@ export interface GameStarting {
   type: ResponseType.GameStarting;
   players: Record<Uuid, JWTPlayerData>;
   playerOrder: Uuid[];
+  protocolVersion: number;
 }
 
 export interface GameInitialized {
@@ -151,6 +155,7 @@ export interface GameInitialized {
   gameState: GameState;
   ticksLeft: number;
   secondsPerTick: number;
+  protocolVersion: number;
 }
 
 export interface NextState {
@@ -1,7 +1,11 @@
 export type Uuid = string;
 
+// Must match the "protocolVersion" sent by the server
+export const PROTOCOL_VERSION = 2;
+
 export interface JWTPlayerData {
   name: string;
+  isBot?: boolean;
 }
 
 // ================================
@@ -16,6 +20,13 @@ export enum RequestType {
   DropWeapon = "dropWeapon",
   GetServerState = "getServerState",
   GetRegisteredPlayers = "getRegisteredPlayers",
+  GetTimeRemaining = "getTimeRemaining",
+  GetGameConfig = "getGameConfig",
+  GetMyState = "getMyState",
+  GetMyActions = "getMyActions",
+  GetGameState = "getGameState",
+  GetRecentResults = "getRecentResults",
+  SpectatePlayer = "spectatePlayer",
 }
 
 export type GameServerRequest =
@@ -23,7 +34,14 @@ export type GameServerRequest =
   | UnregisterMessage
   | PlayerAction
   | GetServerStateRequest
-  | GetRegisteredPlayersRequest;
+  | GetRegisteredPlayersRequest
+  | GetTimeRemainingRequest
+  | GetGameConfigRequest
+  | GetMyStateRequest
+  | GetMyActionsRequest
+  | GetGameStateRequest
+  | GetRecentResultsRequest
+  | SpectatePlayerRequest;
 
 export interface RegisterMessage {
   type: RequestType.Register;
@@ -61,10 +79,49 @@ export enum Direction {
 
 export interface GetServerStateRequest {
   type: RequestType.GetServerState;
+  requestId?: string;
 }
 
 export interface GetRegisteredPlayersRequest {
   type: RequestType.GetRegisteredPlayers;
+  requestId?: string;
+}
+
+export interface GetTimeRemainingRequest {
+  type: RequestType.GetTimeRemaining;
+  requestId?: string;
+}
+
+export interface GetGameConfigRequest {
+  type: RequestType.GetGameConfig;
+  requestId?: string;
+}
+
+export interface GetMyStateRequest {
+  type: RequestType.GetMyState;
+  requestId?: string;
+}
+
+export interface GetMyActionsRequest {
+  type: RequestType.GetMyActions;
+  requestId?: string;
+}
+
+export interface GetGameStateRequest {
+  type: RequestType.GetGameState;
+  requestId?: string;
+}
+
+export interface GetRecentResultsRequest {
+  type: RequestType.GetRecentResults;
+  requestId?: string;
+  limit?: number;
+}
+
+export interface SpectatePlayerRequest {
+  type: RequestType.SpectatePlayer;
+  requestId?: string;
+  id: Uuid | null;
 }
 
 // ================================
@@ -75,26 +132,56 @@ export enum ResponseType {
   Error = "error",
   WaitingOnPlayers = "waitingOnPlayers",
   GameStartingSoon = "gameStartingSoon",
+  CountdownTick = "countdownTick",
+  PlayerRegistered = "playerRegistered",
+  PlayerUnregistered = "playerUnregistered",
   GameStarting = "gameStarting",
+  WaitingOnConnections = "waitingOnConnections",
   GameInitialized = "init",
   NextState = "nextState",
+  Delta = "delta",
   PlayerKilled = "playerKilled",
   GameEnded = "gameEnded",
+  ServerShutdown = "serverShutdown",
   GetServerState = "serverState",
   GetRegisteredPlayers = "registeredPlayers",
+  GetTimeRemaining = "timeRemaining",
+  GetGameConfig = "gameConfig",
+  GetMyState = "myState",
+  GetMyActions = "myActions",
+  GetGameState = "gameState",
+  GetRecentResults = "recentResults",
+  Spectating = "spectating",
+  ActionAccepted = "actionAccepted",
+  ReconnectToken = "reconnectToken",
 }
 
 export type GameServerResponse =
   | ErrorResponse
   | WaitingOnPlayers
   | GameStartingSoon
+  | CountdownTick
+  | PlayerRegistered
+  | PlayerUnregistered
   | GameStarting
+  | WaitingOnConnections
   | GameInitialized
   | NextState
+  | Delta
   | PlayerKilled
   | GameEnded
+  | ServerShutdown
   | GetServerStateResponse
-  | GetRegisteredPlayersResponse;
+  | GetRegisteredPlayersResponse
+  | GetTimeRemainingResponse
+  | GetGameConfigResponse
+  | GetMyStateResponse
+  | GetMyActionsResponse
+  | GetGameStateResponse
+  | GetRecentResultsResponse
+  | SpectatingResponse
+  | ActionAccepted
+  | ReconnectToken;
 
 export interface ErrorResponse {
   type: ResponseType.Error;
@@ -104,6 +191,9 @@ export interface ErrorResponse {
   // Only included in a debug build of the game server
   //  Used by the game server developers for additional debugging
   developerNotes?: string;
+
+  // Suggested number of milliseconds to wait before trying again (only for some errors)
+  retryAfterMs?: number;
 }
 
 export enum GlobalErrorCode {
@@ -123,6 +213,13 @@ export enum GlobalErrorCode {
   FailedToUnregister,
   AlreadyConnected,
   CannotSendAction,
+  EngineNotCrashed,
+  RateLimited,
+  MessageTooLarge,
+  InvalidReconnectToken,
+  GameEngineTimeout,
+  NotEnoughPlayers,
+  NotInMatch,
 }
 
 export interface WaitingOnPlayers {
@@ -140,42 +237,100 @@ export interface GameStartingSoon {
   secondsLeft: number;
 }
 
+export interface CountdownTick {
+  type: ResponseType.CountdownTick;
+  secondsLeft: number;
+}
+
+export interface PlayerRegistered {
+  type: ResponseType.PlayerRegistered;
+  id: Uuid;
+  data: JWTPlayerData;
+}
+
+export interface PlayerUnregistered {
+  type: ResponseType.PlayerUnregistered;
+  id: Uuid;
+}
+
 export interface GameStarting {
   type: ResponseType.GameStarting;
+  matchId: Uuid;
   players: Record<Uuid, JWTPlayerData>;
   playerOrder: Uuid[];
+  protocolVersion: number;
+}
+
+export interface WaitingOnConnections {
+  type: ResponseType.WaitingOnConnections;
+  missingPlayers: Uuid[];
+  secondsLeft: number;
 }
 
 export interface GameInitialized {
   type: ResponseType.GameInitialized;
+  matchId: Uuid;
   gameState: GameState;
   ticksLeft: number;
   secondsPerTick: number;
+  playerOrder: Uuid[];
+  startedAt: string; // RFC 3339 timestamp
+  protocolVersion: number;
 }
 
 export interface NextState {
   type: ResponseType.NextState;
+  matchId: Uuid;
   gameState: GameState;
   actionsTaken: Record<Uuid, PlayerAction>;
+  currentTick: number;
   ticksLeft: number;
   secondsPerTick: number;
+  stateHash?: string; // 16 hex digits, left out if the hash can't be computed
+}
+
+export interface Delta {
+  type: ResponseType.Delta;
+  matchId: Uuid;
+  changes: GameStateDelta;
+  actionsTaken: Record<Uuid, PlayerAction>;
+  currentTick: number;
+  ticksLeft: number;
+  secondsPerTick: number;
+  stateHash?: string; // 16 hex digits, left out if the hash can't be computed
 }
 
 export interface PlayerKilled {
   type: ResponseType.PlayerKilled;
+  matchId: Uuid;
   id: Uuid;
 }
 
 export interface GameEnded {
   type: ResponseType.GameEnded;
+  matchId: Uuid;
   winners: Uuid[];
   gameState: GameState;
   actionsTaken: Record<Uuid, PlayerAction>;
+  eliminationOrder: Uuid[];
+  stats: Record<Uuid, PlayerStats>;
+}
+
+export interface PlayerStats {
+  ticksSurvived: number;
+  actionsTaken: number;
+}
+
+export interface ServerShutdown {
+  type: ResponseType.ServerShutdown;
+  reason: string;
 }
 
 export interface GetServerStateResponse {
   type: ResponseType.GetServerState;
+  requestId?: string;
   state: ServerState;
+  matchId?: Uuid; // Only while a game is in progress
 }
 
 export enum ServerState {
@@ -187,10 +342,99 @@ export enum ServerState {
 
 export interface GetRegisteredPlayersResponse {
   type: ResponseType.GetRegisteredPlayers;
+  requestId?: string;
   players: Record<Uuid, JWTPlayerData>;
   playerOrder?: Uuid[];
 }
 
+export interface GetTimeRemainingResponse {
+  type: ResponseType.GetTimeRemaining;
+  requestId?: string;
+  ticksLeft: number;
+  secondsPerTick: number;
+}
+
+export interface GetGameConfigResponse {
+  type: ResponseType.GetGameConfig;
+  requestId?: string;
+  minPlayersNeeded: number;
+  maxPlayersAllowed: number;
+  ticksPerGame: number;
+  secondsPerTick: number;
+  arenaWidth: number;
+  arenaHeight: number;
+  protocolVersion: number;
+}
+
+export interface GetMyStateResponse {
+  type: ResponseType.GetMyState;
+  requestId?: string;
+  matchId?: Uuid; // Only while a game is in progress
+  player: Position<PlayerDetails> | null;
+}
+
+export interface GetMyActionsResponse {
+  type: ResponseType.GetMyActions;
+  requestId?: string;
+  actions: SentAction[]; // Oldest first
+}
+
+export type SentAction = PlayerAction & { tick: number }; // currentTick when the action was received
+
+export interface GetGameStateResponse {
+  type: ResponseType.GetGameState;
+  requestId?: string;
+  serverState: ServerState;
+  matchId?: Uuid; // Only while a game is in progress
+  gameState: GameState | null;
+}
+
+export interface GetRecentResultsResponse {
+  type: ResponseType.GetRecentResults;
+  requestId?: string;
+  matches: MatchResult[];
+}
+
+export interface MatchResult {
+  matchId: Uuid;
+  winners: Uuid[];
+  players: Record<Uuid, JWTPlayerData>;
+  endedAt: string; // RFC 3339 timestamp
+}
+
+export interface SpectatingResponse {
+  type: ResponseType.Spectating;
+  requestId?: string;
+  id: Uuid | null;
+  playerView: boolean; // True if the game sends this player a filtered view
+}
+
+export interface ReconnectToken {
+  type: ResponseType.ReconnectToken;
+  token: string; // Use with the /api/v1/reconnect route
+}
+
+export interface ActionAccepted {
+  type: ResponseType.ActionAccepted;
+  tag?: string;
+}
+
+// ================================
+//          HTTP Routes
+// ================================
+
+// GET /api/v1/state
+export interface GameStateSnapshot {
+  state: ServerState;
+  registration?: WaitingOnPlayers | GameStartingSoon; // Only during registration
+  matchId?: Uuid; // Only while a game is in progress
+  gameState?: GameState; // Only after the game is initialized
+  ticksLeft?: number;
+  secondsPerTick?: number;
+}
+
+// GET /api/v1/players -> GetRegisteredPlayersResponse
+
 // ================================
 //      Game State Object
 // ================================
@@ -201,6 +445,15 @@ export interface GameState {
   items: Position<Item>[]; // Unused right now
 }
 
+// Only the fields that changed since the last game state
+export interface GameStateDelta {
+  playfield?: PlayfieldObject[][];
+  players?: Record<Uuid, Position<PlayerDetails>>; // Players added or changed
+  removedPlayers?: Uuid[];
+  weapons?: Position<Weapon>[];
+  items?: Position<Item>[];
+}
+
 // Has a (row, col) position in the playfield
 export type Position<T> = T & {
   row: number;
@@ -225,6 +478,7 @@ export enum WeaponType {
 }
 
 export interface LaserGunWeapon {
+  id: string;
   type: WeaponType.LaserGun;
   ammo: number;
   damage: number;
@@ -27,7 +27,7 @@ end
 -- ====================================================
 function Init(ctx, players)
   playerOrder = players
-  pf, pfRows, pfCols = libPlayfield.newPlayfield(10, 10)
+  pf, pfRows, pfCols = libPlayfield.newPlayfield(ctx:getArenaSize())
 
   -- Insert players into random positions in the playfield
   playerDetails = {}
@@ -57,7 +57,7 @@ function Init(ctx, players)
 
   -- Reset all negative playfield spaces to 0
   for row = 1, #pf do
-    for col = 1, #pf do
+    for col = 1, #pf[row] do
       if pf[row][col] == -1 then
         pf[row][col] = libPlayfield.BLANK
       end
@@ -96,6 +96,18 @@ function Update(ctx, actions)
   return memoizeGameState()
 end
 
+-- ====================================================
+--  Restore routine - Receives a saved game state
+--    Only used by "test_game_code --load-snapshot"
+-- ====================================================
+function LoadState(ctx, state)
+  playerOrder = ctx:getPlayerOrder()
+  pf = state.playfield
+  pfRows, pfCols = ctx:getArenaSize()
+  playerDetails = state.players
+  weapons = state.weapons
+end
+
 -- Convert a direction string ("up", "down", "left", "right") to (Δrow, Δcol)
 local function directionToDeltas(direction)
   local deltaRow, deltaCol = 0, 0
@@ -3,9 +3,18 @@
 --
 local Module = {}
 
+-- Every weapon needs a unique string ID
+local nextID = 1
+local function newID()
+  local id = tostring(nextID)
+  nextID = nextID + 1
+  return id
+end
+
 -- Make a new laser gun weapon
 function Module.NewLaserGun(row, col)
   return {
+    id = newID(),
     type = "laserGun",
     row = row,
     col = col,
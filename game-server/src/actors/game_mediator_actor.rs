@@ -1,13 +1,65 @@
 use actix::prelude::*;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
 use std::sync::mpsc::Sender;
 use std::time::Duration;
+use tokio::sync::mpsc::Sender as ActionSender;
 use uuid::Uuid;
 
-use crate::actors::{mediator_messages::*, shared_messages::*, ViewerActor, WebsocketActor};
+use crate::actors::{
+  mediator_messages::*,
+  replay_recorder::ReplayRecorder,
+  shared_messages::*,
+  websocket_messages::{ActionRejected, KickUnregisteredPlayer, ReplaceConnection},
+  ViewerActor, WebsocketActor,
+};
 use crate::config;
-use crate::game::ServerState;
+use crate::game::{ActionWeights, ServerState, MIN_PLAYERS_PER_GAME};
 use crate::jwt::JWTPlayerData;
+use crate::metrics::Metrics;
+use crate::protocol::{MatchResult, Player, PlayerAction, PlayerStats, RegistrationUpdateEnum, ToBytestring};
+
+/// Number of finished games to remember for the recent results query
+const MAX_RECENT_RESULTS: usize = 10;
+
+/// Clients are disconnected after this many broadcasts in a row are dropped because their mailbox is full
+const MAX_DROPPED_MESSAGES: u32 = 10;
+
+/// Settings for the game mediator, normally loaded from the environment variables
+///   Can be filled in directly to run a mediator with custom settings
+#[derive(Debug, Clone)]
+pub struct MediatorConfig {
+  pub min_players_needed: usize,
+  pub max_players_allowed: usize,
+  pub lobby_wait_secs: u32,
+  pub start_when_full: bool,
+  pub bot_fill_secs: Option<u32>, // Only set if empty lobby slots should be filled with bots
+  pub connect_grace_secs: u32,
+  pub unregister_on_disconnect: bool,
+  pub reconnect_replaces_old: bool,
+  pub use_delta_updates: bool,
+  pub registration_deltas: bool,
+  pub replay_dir: Option<PathBuf>, // Only set if every match should be recorded to a replay file
+}
+
+impl MediatorConfig {
+  /// Load the mediator settings from the environment variables
+  pub fn from_env() -> Self {
+    Self {
+      min_players_needed: config::get_min_players_needed(),
+      max_players_allowed: config::get_max_players_allowed(),
+      lobby_wait_secs: config::get_lobby_wait_time_seconds(),
+      start_when_full: config::start_when_full(),
+      bot_fill_secs: config::fill_with_bots().then(config::get_bot_fill_seconds),
+      connect_grace_secs: config::get_connect_grace_seconds(),
+      unregister_on_disconnect: config::unregister_on_disconnect(),
+      reconnect_replaces_old: config::reconnect_replaces_old(),
+      use_delta_updates: config::use_delta_updates(),
+      registration_deltas: config::use_registration_deltas(),
+      replay_dir: config::record_replays().then(config::get_replay_dir),
+    }
+  }
+}
 
 /// Actor that facilitates communication between the websocket actors and the game engine
 pub struct GameMediatorActor {
@@ -15,19 +67,61 @@ pub struct GameMediatorActor {
   registered: HashMap<Uuid, JWTPlayerData>, // Stores ID and other player data
   actors: HashMap<Uuid, Addr<WebsocketActor>>,
   viewers: HashSet<Addr<ViewerActor>>,
+  dropped_player_messages: HashMap<Uuid, u32>, // Broadcasts dropped in a row for each slow client
+  dropped_viewer_messages: HashMap<Addr<ViewerActor>, u32>,
   player_order: Option<Vec<Uuid>>,
-  send_start_game: Sender<Vec<Uuid>>,
+  match_id: Option<Uuid>, // Only set while a game is in progress
+  send_start_game: Sender<(Uuid, Vec<Uuid>, HashMap<Uuid, JWTPlayerData>)>,
+  send_player_actions: ActionSender<(Uuid, PlayerAction)>, // Only used for the bot actions
   min_players_needed: usize,
   max_players_allowed: usize,
   lobby_wait_secs: u32,
+  start_when_full: bool,
   secs_left: u32,
+  registration_timer: Option<SpawnHandle>,
+  bot_fill_secs: Option<u32>, // Only set if empty lobby slots should be filled with bots
+  bot_secs_left: u32,
+  bots_alive: HashSet<Uuid>,
+  players_killed: Vec<Uuid>, // In the order they were killed this round
+  player_stats: HashMap<Uuid, PlayerStats>,
+  connect_grace_secs: u32,
+  grace_secs_left: Option<u32>, // Only set while waiting for players to connect before starting the engine
+  unregister_on_disconnect: bool,
+  reconnect_replaces_old: bool,
+  reconnect_nonces: HashMap<Uuid, Uuid>, // Player ID to the only reconnection token still valid
+  use_delta_updates: bool,
+  latest_state: Option<NextState>, // Last full state, used as the baseline for delta updates
+  registration_deltas: bool,
+  recent_results: VecDeque<MatchResult>, // Newest first
+  replay_dir: Option<PathBuf>,
+  replay: Option<ReplayRecorder>, // Only set while recording a game in progress
+  metrics: Metrics,
 }
 
 impl GameMediatorActor {
-  /// Construct a new game mediator actor with the given channel
-  pub fn new(send_start_game: Sender<Vec<Uuid>>) -> Self {
-    let min_players_needed = config::get_min_players_needed();
-    let mut max_players_allowed = config::get_max_players_allowed();
+  /// Construct a new game mediator actor with the given channel, using the settings from the environment
+  pub fn new(
+    send_start_game: Sender<(Uuid, Vec<Uuid>, HashMap<Uuid, JWTPlayerData>)>,
+    send_player_actions: ActionSender<(Uuid, PlayerAction)>,
+    metrics: Metrics,
+  ) -> Self {
+    Self::new_with_config(
+      send_start_game,
+      send_player_actions,
+      metrics,
+      MediatorConfig::from_env(),
+    )
+  }
+
+  /// Construct a new game mediator actor with the given channel and settings
+  pub fn new_with_config(
+    send_start_game: Sender<(Uuid, Vec<Uuid>, HashMap<Uuid, JWTPlayerData>)>,
+    send_player_actions: ActionSender<(Uuid, PlayerAction)>,
+    metrics: Metrics,
+    settings: MediatorConfig,
+  ) -> Self {
+    let min_players_needed = settings.min_players_needed;
+    let mut max_players_allowed = settings.max_players_allowed;
 
     if max_players_allowed < min_players_needed {
       log::warn!(
@@ -39,57 +133,176 @@ impl GameMediatorActor {
       max_players_allowed = min_players_needed;
     }
 
-    let lobby_wait_secs = config::get_lobby_wait_time_seconds();
+    let lobby_wait_secs = settings.lobby_wait_secs;
+    let bot_fill_secs = settings.bot_fill_secs;
 
     Self {
       server_state: ServerState::Registration,
       registered: HashMap::new(),
       actors: HashMap::new(),
       viewers: HashSet::new(),
+      dropped_player_messages: HashMap::new(),
+      dropped_viewer_messages: HashMap::new(),
       player_order: None,
+      match_id: None,
       send_start_game,
+      send_player_actions,
       min_players_needed,
       max_players_allowed,
       lobby_wait_secs,
+      start_when_full: settings.start_when_full,
       secs_left: lobby_wait_secs,
+      registration_timer: None,
+      bot_fill_secs,
+      bot_secs_left: bot_fill_secs.unwrap_or_default(),
+      bots_alive: HashSet::new(),
+      players_killed: Vec::new(),
+      player_stats: HashMap::new(),
+      connect_grace_secs: settings.connect_grace_secs,
+      grace_secs_left: None,
+      unregister_on_disconnect: settings.unregister_on_disconnect,
+      reconnect_replaces_old: settings.reconnect_replaces_old,
+      reconnect_nonces: HashMap::new(),
+      use_delta_updates: settings.use_delta_updates,
+      latest_state: None,
+      registration_deltas: settings.registration_deltas,
+      recent_results: VecDeque::with_capacity(MAX_RECENT_RESULTS),
+      replay_dir: settings.replay_dir,
+      replay: None,
+      metrics,
     }
   }
 
   /// Broadcast a message - Should accept a type that can be easily cloned
-  fn broadcast_all<M>(&self, data: M)
+  fn broadcast_all<M>(&mut self, data: M)
   where
     M: Clone + Message + Send + 'static,
     <M as actix::Message>::Result: Send,
     WebsocketActor: Handler<M>,
     ViewerActor: Handler<M>,
   {
-    // Send to all actors
-    for (_, actor) in self.actors.iter() {
-      actor.do_send(data.clone());
+    self.broadcast_player_views(data, |data, _| data.clone());
+  }
+
+  /// Broadcast a game update where each player might only be allowed to see part of the game state
+  ///   Viewers always get the full update
+  fn broadcast_player_views<M>(&mut self, data: M, view_for: impl Fn(&M, &Uuid) -> M)
+  where
+    M: Clone + Message + Send + 'static,
+    <M as actix::Message>::Result: Send,
+    WebsocketActor: Handler<M>,
+    ViewerActor: Handler<M>,
+  {
+    for (player_id, actor) in self.actors.iter() {
+      let dropped = self.dropped_player_messages.entry(*player_id).or_default();
+      if Self::try_send_or_drop(actor, view_for(&data, player_id), dropped, &self.metrics) {
+        log::warn!(player_id:% = player_id; "Player mailbox keeps overflowing, disconnecting...");
+        actor.do_send::<ConnectionTooSlow>(ConnectionTooSlow);
+      }
     }
 
-    // Also send to all viewers
     for viewer in self.viewers.iter() {
-      viewer.do_send(data.clone());
+      let dropped = self.dropped_viewer_messages.entry(viewer.clone()).or_default();
+      if Self::try_send_or_drop(viewer, data.clone(), dropped, &self.metrics) {
+        log::warn!("Viewer mailbox keeps overflowing, disconnecting...");
+        viewer.do_send::<ConnectionTooSlow>(ConnectionTooSlow);
+      }
+    }
+  }
+
+  /// Send a message without going over the mailbox capacity, so a slow client can't use unbounded memory
+  ///   Returns true once the client has dropped too many messages in a row and should be disconnected
+  fn try_send_or_drop<A, M>(addr: &Addr<A>, data: M, dropped: &mut u32, metrics: &Metrics) -> bool
+  where
+    A: Actor + Handler<M>,
+    A::Context: actix::dev::ToEnvelope<A, M>,
+    M: Message + Send + 'static,
+    M::Result: Send,
+  {
+    match addr.try_send(data) {
+      Ok(()) => {
+        *dropped = 0;
+        false
+      },
+      Err(SendError::Full(_)) => {
+        metrics.messages_dropped.inc();
+        *dropped += 1;
+        *dropped == MAX_DROPPED_MESSAGES
+      },
+      Err(SendError::Closed(_)) => false, // Cleaned up when the actor disconnects
     }
   }
 
+  /// Start recording a new match, if replays are enabled
+  fn start_replay(&mut self, match_id: Uuid) {
+    let replay_dir = match self.replay_dir.as_ref() {
+      Some(replay_dir) => replay_dir,
+      None => return,
+    };
+
+    self.replay = match ReplayRecorder::create(replay_dir, match_id) {
+      Ok(replay) => Some(replay),
+      Err(e) => {
+        log::warn!(match_id:% = match_id; "Failed to create replay file in '{}': {}", replay_dir.display(), e);
+        None
+      },
+    };
+  }
+
+  /// Append a broadcast message to the replay of the current match
+  ///   Recording stops for the rest of the match if the file can't be written
+  fn record_replay(&mut self, message: &impl ToBytestring) {
+    if let Some(replay) = self.replay.as_mut() {
+      if let Err(e) = replay.record(message) {
+        log::warn!(
+          "Failed to write replay file '{}', stopping the recording: {}",
+          replay.get_path().display(),
+          e
+        );
+        self.replay = None;
+      }
+    }
+  }
+
+  /// Close the replay of the current match
+  ///   Dropping the recorder lets the writer thread flush the file and log the result in the background
+  fn finish_replay(&mut self) {
+    self.replay = None;
+  }
+
   /// Send an update with the latest registration details
-  fn broadcast_registration_update(&self) {
+  fn broadcast_registration_update(&mut self) {
+    let update = self.get_registration_update();
+    self.broadcast_all(update);
+  }
+
+  /// Send a single player joining or leaving the lobby
+  ///   The full details are still sent if registration deltas are off, or if the countdown just started or stopped
+  fn broadcast_registration_change(&mut self, change: RegistrationUpdate, had_enough_players: bool) {
+    let has_enough_players = self.registered.len() >= self.min_players_needed;
+    if self.registration_deltas && had_enough_players == has_enough_players {
+      self.broadcast_all(change);
+    } else {
+      self.broadcast_registration_update();
+    }
+  }
+
+  /// Full registration details, including the list of registered players
+  fn get_registration_update(&self) -> RegistrationUpdate {
     if self.registered.len() < self.min_players_needed {
-      self.broadcast_all(RegistrationUpdate::waiting_on_players(
+      RegistrationUpdate::waiting_on_players(
         self.registered.clone(),
         self.min_players_needed,
         self.max_players_allowed,
-      ));
+      )
     } else {
-      self.broadcast_all(RegistrationUpdate::game_starting_soon(
+      RegistrationUpdate::game_starting_soon(
         self.registered.clone(),
         self.min_players_needed,
         self.max_players_allowed,
         self.secs_left,
-      ));
-    };
+      )
+    }
   }
 }
 
@@ -100,7 +313,7 @@ impl Actor for GameMediatorActor {
   type Context = Context<Self>;
 
   fn started(&mut self, ctx: &mut Self::Context) {
-    ctx.run_interval(Duration::from_secs(1), |this, _ctx| this.tick_registration_update());
+    self.restart_registration_timer(ctx);
   }
 }
 
@@ -108,15 +321,33 @@ impl Actor for GameMediatorActor {
 // Handle registration "tick" logic
 //
 impl GameMediatorActor {
+  /// (Re)start the one second timer for the registration updates
+  ///   Restarting makes sure a full second passes before the lobby countdown is decremented
+  fn restart_registration_timer(&mut self, ctx: &mut <Self as Actor>::Context) {
+    if let Some(handle) = self.registration_timer.take() {
+      ctx.cancel_future(handle);
+    }
+
+    self.registration_timer =
+      Some(ctx.run_interval(Duration::from_secs(1), |this, _ctx| this.tick_registration_update()));
+  }
+
   /// Run once every second to update the registration state
   fn tick_registration_update(&mut self) {
+    if let Some(ref mut grace_secs_left) = self.grace_secs_left {
+      *grace_secs_left = grace_secs_left.saturating_sub(1);
+      return self.try_start_game_engine();
+    }
+
     if self.server_state != ServerState::Registration {
       return;
     }
 
+    self.remove_bots_without_players();
     if self.registered.len() < self.min_players_needed {
-      return;
+      return self.tick_bot_fill();
     }
+    self.bot_secs_left = self.bot_fill_secs.unwrap_or_default();
 
     // Count down the number of seconds
     self.secs_left -= 1;
@@ -125,21 +356,179 @@ impl GameMediatorActor {
       return self.start_game();
     }
 
-    // Send an update that the game is starting soon...
+    // Only the countdown changed, so there's no need to send the full player list again
+    self.broadcast_all(RegistrationUpdate::countdown_tick(self.secs_left));
+  }
+
+  /// Count down to filling the empty lobby slots with bots (if enabled)
+  ///   The countdown only runs while at least one real player is waiting
+  fn tick_bot_fill(&mut self) {
+    let bot_fill_secs = match self.bot_fill_secs {
+      Some(bot_fill_secs) => bot_fill_secs,
+      None => return,
+    };
+
+    if !self.registered.values().any(|data| !data.is_bot()) {
+      self.bot_secs_left = bot_fill_secs;
+      return;
+    }
+
+    self.bot_secs_left = self.bot_secs_left.saturating_sub(1);
+    if self.bot_secs_left > 0 {
+      return;
+    }
+
+    // Add just enough bots to reach the minimum number of players
+    let num_bots = self.registered.values().filter(|data| data.is_bot()).count();
+    let bots_needed = self.min_players_needed.saturating_sub(self.registered.len());
+    log::info!("Not enough players registered in time, adding {} bot(s)", bots_needed);
+    for i in 0..bots_needed {
+      let bot_data = JWTPlayerData::new(format!("Bot {}", num_bots + i + 1)).with_bot_flag(true);
+      self.registered.insert(Uuid::new_v4(), bot_data);
+    }
+    self.metrics.registered_players.set(self.registered.len() as i64);
+
+    // Now run the normal lobby countdown
+    self.secs_left = self.lobby_wait_secs;
+    if self.start_when_full && self.registered.len() >= self.max_players_allowed {
+      self.secs_left = 0;
+      self.broadcast_registration_update();
+      return self.start_game();
+    }
+
+    self.broadcast_registration_update();
+  }
+
+  /// There is no point in a game with only bots
+  fn remove_bots_without_players(&mut self) {
+    if self.registered.is_empty() || self.registered.values().any(|data| !data.is_bot()) {
+      return;
+    }
+
+    log::info!("All players have left, removing the bots from the lobby");
+    self.registered.clear();
+    self.metrics.registered_players.set(0);
     self.broadcast_registration_update();
   }
 
+  /// Winners first, then any other survivors (both in player order), then the players in the reverse order they were killed
+  ///   Survivors can only lose if the Lua code ends the game early
+  fn get_elimination_order(&self, winners: &HashSet<Uuid>) -> Vec<Uuid> {
+    let player_order = self.player_order.iter().flatten();
+    let (winners, survivors): (Vec<_>, Vec<_>) = player_order
+      .filter(|id| !self.players_killed.contains(id))
+      .partition(|id| winners.contains(id));
+
+    (winners.into_iter())
+      .chain(survivors)
+      .chain(self.players_killed.iter().rev())
+      .copied()
+      .collect()
+  }
+
+  /// Update the statistics for every player after a game tick
+  ///   Killed players are already in the list, since the engine sends PlayerKilled before the next state
+  fn record_tick_stats(&mut self, actions_taken: &HashMap<Uuid, PlayerAction>) {
+    for (player_id, stats) in self.player_stats.iter_mut() {
+      let survived = !self.players_killed.contains(player_id);
+      stats.record_tick(survived, actions_taken.contains_key(player_id));
+    }
+  }
+
+  /// Bots never connect to the server, so their random actions go straight to the game engine
+  fn send_bot_actions(&self) {
+    let mut rng = rand::thread_rng();
+    for bot_id in self.bots_alive.iter() {
+      let action = ActionWeights::default().pick_action(&mut rng);
+      self.send_player_actions.try_send((*bot_id, action)).ok();
+    }
+  }
+
   fn start_game(&mut self) {
+    // Only possible if the mediator was configured with a minimum below two players
+    if self.registered.len() < MIN_PLAYERS_PER_GAME {
+      return self.cancel_game(self.registered.len());
+    }
+
     // Pick a random order for the players
-    let player_order: Vec<_> = self.registered.iter().map(|(id, _)| id.clone()).collect();
+    let player_order: Vec<_> = self.registered.keys().copied().collect();
+    let match_id = Uuid::new_v4();
     self.player_order = Some(player_order.clone());
+    self.match_id = Some(match_id);
+    self.players_killed.clear();
+    self.player_stats = player_order.iter().map(|id| (*id, PlayerStats::default())).collect();
+    self.bots_alive = (self.registered.iter())
+      .filter(|(_, data)| data.is_bot())
+      .map(|(id, _)| *id)
+      .collect();
     self.server_state = ServerState::Initializing;
+    self.metrics.games_started.inc();
+
+    // Kick any connected players that never registered, so they don't get the game updates
+    let registered = &self.registered;
+    self.actors.retain(|player_id, actor| {
+      let is_registered = registered.contains_key(player_id);
+      if !is_registered {
+        actor.do_send(KickUnregisteredPlayer);
+      }
+      is_registered
+    });
+    self.metrics.connected_players.set(self.actors.len() as i64);
 
     // Notify all players that game is starting
-    self.broadcast_all(GameStarting::new(self.registered.clone(), player_order.clone()));
+    let game_starting = GameStarting::new(match_id, self.registered.clone(), player_order);
+    self.start_replay(match_id);
+    self.record_replay(&game_starting);
+    self.broadcast_all(game_starting);
+
+    // Give any disconnected players a chance to reconnect before the game engine starts
+    self.grace_secs_left = Some(self.connect_grace_secs);
+    self.try_start_game_engine();
+  }
 
-    // Send the message for the game engine to start
-    self.send_start_game.send(player_order).ok();
+  /// Go back to registration without running the round, keeping the registered players
+  fn cancel_game(&mut self, num_players: usize) {
+    log::warn!("Only {} player(s) in the game, returning to registration", num_players);
+    self.player_order = None;
+    self.match_id = None;
+    self.grace_secs_left = None;
+    self.bots_alive.clear();
+    self.player_stats.clear();
+    self.secs_left = self.lobby_wait_secs;
+    self.server_state = ServerState::Registration;
+    self.finish_replay();
+    self.broadcast_all(GameCancelled { num_players });
+    self.broadcast_registration_update();
+  }
+
+  /// Send the message for the game engine to start once all registered players are connected,
+  ///   or once the grace period runs out
+  fn try_start_game_engine(&mut self) {
+    let grace_secs_left = match self.grace_secs_left {
+      Some(grace_secs_left) => grace_secs_left,
+      None => return,
+    };
+
+    // Bots never connect, so don't wait for them
+    let missing_players: Vec<_> = self
+      .registered
+      .iter()
+      .filter(|(id, data)| !data.is_bot() && !self.actors.contains_key(id))
+      .map(|(id, _)| *id)
+      .collect();
+
+    if grace_secs_left > 0 && !missing_players.is_empty() {
+      return self.broadcast_all(RegistrationUpdate::waiting_on_connections(
+        missing_players,
+        grace_secs_left,
+      ));
+    }
+
+    self.grace_secs_left = None;
+    if let (Some(match_id), Some(ref player_order)) = (self.match_id, &self.player_order) {
+      let start_game = (match_id, player_order.clone(), self.registered.clone());
+      self.send_start_game.send(start_game).ok();
+    }
   }
 }
 
@@ -147,17 +536,56 @@ impl Handler<Connect> for GameMediatorActor {
   type Result = ConnectResponse;
 
   fn handle(&mut self, Connect(player_id, addr): Connect, _: &mut Self::Context) -> Self::Result {
-    if self.actors.contains_key(&player_id) {
-      return ConnectResponse::AlreadyConnected;
-    }
-
     if !self.server_state.can_change_registration() && !self.registered.contains_key(&player_id) {
       return ConnectResponse::NotRegistered;
     }
 
+    // Only replace the old connection once the new one is sure to be accepted
+    if let Some(old_addr) = self.actors.get(&player_id) {
+      if !self.reconnect_replaces_old {
+        return ConnectResponse::AlreadyConnected;
+      }
+
+      // The stale actor closes itself after handing over its state
+      log::info!(player_id:% = player_id; "Player reconnected, replacing the old websocket connection");
+      old_addr.do_send(ReplaceConnection(addr.clone()));
+    }
+
+    // Players joining in the middle of the game need a full state before any deltas
+    if self.use_delta_updates {
+      if let Some(ref latest_state) = self.latest_state {
+        addr.do_send(latest_state.view_for(&player_id));
+      }
+    }
+
+    // The countdown ticks don't include the player list, so new connections need the full details
+    if self.server_state == ServerState::Registration {
+      addr.do_send(self.get_registration_update());
+    }
+
     self.actors.insert(player_id, addr);
+    self.metrics.connected_players.set(self.actors.len() as i64);
+
+    // Might be the last player the game is waiting on
+    self.try_start_game_engine();
 
-    ConnectResponse::Ok(self.server_state)
+    // Issuing a new nonce also invalidates any older reconnection token
+    let reconnect_nonce = Uuid::new_v4();
+    self.reconnect_nonces.insert(player_id, reconnect_nonce);
+
+    ConnectResponse::Ok(self.server_state, reconnect_nonce)
+  }
+}
+
+impl Handler<Reconnect> for GameMediatorActor {
+  type Result = ConnectResponse;
+
+  fn handle(&mut self, Reconnect { id, nonce, addr }: Reconnect, ctx: &mut Self::Context) -> Self::Result {
+    if self.reconnect_nonces.get(&id) != Some(&nonce) {
+      return ConnectResponse::InvalidReconnectToken;
+    }
+
+    <Self as Handler<Connect>>::handle(self, Connect(id, addr), ctx)
   }
 }
 
@@ -168,6 +596,21 @@ impl Handler<Disconnect> for GameMediatorActor {
     if let Some(addr) = self.actors.get(&player_id) {
       if addr == &player_addr {
         self.actors.remove(&player_id);
+        self.dropped_player_messages.remove(&player_id);
+        self.metrics.connected_players.set(self.actors.len() as i64);
+
+        // Once the game starts, disconnected players stay in the game until they are killed
+        if self.unregister_on_disconnect && self.server_state.can_change_registration() {
+          let had_enough_players = self.registered.len() >= self.min_players_needed;
+          if let Some(data) = self.registered.remove(&player_id) {
+            log::info!(
+              player_id:% = player_id, event = "player_unregistered";
+              "Player \"{}\" unregistered after disconnecting", data.get_name()
+            );
+            self.metrics.registered_players.set(self.registered.len() as i64);
+            self.broadcast_registration_change(RegistrationUpdate::player_unregistered(player_id), had_enough_players);
+          }
+        }
       }
     }
   }
@@ -177,7 +620,18 @@ impl Handler<ConnectViewer> for GameMediatorActor {
   type Result = ConnectViewerResponse;
 
   fn handle(&mut self, ConnectViewer(addr): ConnectViewer, _: &mut Self::Context) -> Self::Result {
+    if self.use_delta_updates {
+      if let Some(ref latest_state) = self.latest_state {
+        addr.do_send(latest_state.clone());
+      }
+    }
+
+    if self.server_state == ServerState::Registration {
+      addr.do_send(self.get_registration_update());
+    }
+
     self.viewers.insert(addr);
+    self.metrics.connected_viewers.set(self.viewers.len() as i64);
     ConnectViewerResponse(self.server_state)
   }
 }
@@ -187,21 +641,42 @@ impl Handler<DisconnectViewer> for GameMediatorActor {
 
   fn handle(&mut self, DisconnectViewer(addr): DisconnectViewer, _: &mut Self::Context) -> Self::Result {
     self.viewers.remove(&addr);
+    self.dropped_viewer_messages.remove(&addr);
+    self.metrics.connected_viewers.set(self.viewers.len() as i64);
+  }
+}
+
+impl Handler<StopSpectating> for GameMediatorActor {
+  type Result = ();
+
+  fn handle(&mut self, StopSpectating { addr, query }: StopSpectating, _: &mut Self::Context) -> Self::Result {
+    let latest_state = if self.use_delta_updates {
+      self.latest_state.clone()
+    } else {
+      None
+    };
+
+    addr.do_send(SpectatingStopped { latest_state, query });
   }
 }
 
 impl Handler<Register> for GameMediatorActor {
   type Result = RegisterResponse;
 
-  fn handle(&mut self, Register { id, data }: Register, _: &mut Self::Context) -> Self::Result {
+  fn handle(&mut self, Register { id, data }: Register, ctx: &mut Self::Context) -> Self::Result {
     if !self.server_state.can_change_registration() {
       return RegisterResponse::GameAlreadyStarted;
     }
 
+    // Only the server is allowed to add bots
+    let data = data.with_bot_flag(false);
     let not_enough_before = self.registered.len() < self.min_players_needed;
 
-    // Since this is idempotent, register the player ONLY if they aren't already registered
-    if !self.registered.contains_key(&id) {
+    // Registering again only updates the player data (like a corrected display name)
+    if let Some(existing) = self.registered.get_mut(&id) {
+      log::info!(player_id:% = id, event = "player_reregistered"; "Player \"{}\" updated their registration", data.get_name());
+      *existing = data.clone();
+    } else {
       // Make sure we aren't at the maximum players yet
       if self.registered.len() >= self.max_players_allowed {
         return RegisterResponse::TooManyRegistered {
@@ -209,16 +684,28 @@ impl Handler<Register> for GameMediatorActor {
         };
       }
 
-      self.registered.insert(id, data);
+      log::info!(player_id:% = id, event = "player_registered"; "Player \"{}\" registered", data.get_name());
+      self.registered.insert(id, data.clone());
+      self.metrics.registered_players.set(self.registered.len() as i64);
     }
 
     // Reset the lobby counter when the count just goes over the minimum number of players needed
     if not_enough_before && self.registered.len() >= self.min_players_needed {
       self.secs_left = self.lobby_wait_secs;
+      self.restart_registration_timer(ctx);
     }
 
-    // Broadcast the update
-    self.broadcast_registration_update();
+    // No point waiting out the countdown once the lobby is completely full
+    if self.start_when_full && self.registered.len() >= self.max_players_allowed {
+      log::info!("Lobby is full, starting the game now");
+      self.secs_left = 0;
+      self.broadcast_registration_update();
+      self.start_game();
+      return RegisterResponse::Success;
+    }
+
+    // Broadcast the update (includes the full countdown if it just started)
+    self.broadcast_registration_change(RegistrationUpdate::player_registered(id, data), !not_enough_before);
 
     RegisterResponse::Success
   }
@@ -233,10 +720,14 @@ impl Handler<Unregister> for GameMediatorActor {
     }
 
     // Force unregister the player, even if they are already unregistered
-    self.registered.remove(&id);
+    let had_enough_players = self.registered.len() >= self.min_players_needed;
+    if let Some(data) = self.registered.remove(&id) {
+      log::info!(player_id:% = id, event = "player_unregistered"; "Player \"{}\" unregistered", data.get_name());
+    }
+    self.metrics.registered_players.set(self.registered.len() as i64);
 
     // Broadcast the update
-    self.broadcast_registration_update();
+    self.broadcast_registration_change(RegistrationUpdate::player_unregistered(id), had_enough_players);
 
     true
   }
@@ -247,7 +738,21 @@ impl Handler<Init> for GameMediatorActor {
 
   fn handle(&mut self, init: Init, _: &mut Self::Context) -> Self::Result {
     self.server_state = ServerState::Running;
-    self.broadcast_all(init);
+    self.latest_state = Some(
+      NextState::new(
+        init.get_match_id(),
+        init.get_game_state().clone(),
+        HashMap::new(),
+        0,
+        init.get_ticks_left(),
+        init.get_seconds_per_tick(),
+        init.get_game_state().state_hash(),
+      )
+      .with_player_views(init.get_player_views().as_ref().clone()),
+    );
+    self.record_replay(&init);
+    self.broadcast_player_views(init, Init::view_for);
+    self.send_bot_actions();
   }
 }
 
@@ -255,7 +760,22 @@ impl Handler<NextState> for GameMediatorActor {
   type Result = ();
 
   fn handle(&mut self, next_state: NextState, _: &mut Self::Context) -> Self::Result {
-    self.broadcast_all(next_state);
+    self.record_tick_stats(next_state.get_actions_taken());
+    self.record_replay(&next_state);
+    match self.latest_state.take() {
+      // Each player gets the changes to their own view of the game state
+      Some(previous) if self.use_delta_updates && next_state.has_player_views() => {
+        self.broadcast_player_views(next_state.to_delta(previous.get_game_state()), |_, player_id| {
+          next_state
+            .view_for(player_id)
+            .to_delta(previous.get_player_view(player_id))
+        })
+      },
+      Some(previous) if self.use_delta_updates => self.broadcast_all(next_state.to_delta(previous.get_game_state())),
+      _ => self.broadcast_player_views(next_state.clone(), NextState::view_for),
+    }
+    self.latest_state = Some(next_state);
+    self.send_bot_actions();
   }
 }
 
@@ -263,30 +783,82 @@ impl Handler<PlayerKilled> for GameMediatorActor {
   type Result = ();
 
   fn handle(&mut self, player_killed: PlayerKilled, _: &mut Self::Context) -> Self::Result {
+    self.bots_alive.remove(&player_killed.get_player_id());
+    self.players_killed.push(player_killed.get_player_id());
+    self.record_replay(&player_killed);
     self.broadcast_all(player_killed);
   }
 }
 
+impl Handler<RejectAction> for GameMediatorActor {
+  type Result = ();
+
+  fn handle(&mut self, RejectAction { player_id, tag }: RejectAction, _: &mut Self::Context) -> Self::Result {
+    if let Some(actor) = self.actors.get(&player_id) {
+      actor.do_send(ActionRejected(tag));
+    }
+  }
+}
+
 impl Handler<GameEnded> for GameMediatorActor {
   type Result = ();
 
   fn handle(&mut self, game_ended: GameEnded, _: &mut Self::Context) -> Self::Result {
-    self.registered.clear();
+    self.record_tick_stats(game_ended.get_actions_taken());
+    let elimination_order = self.get_elimination_order(game_ended.get_winners());
+    let stats = std::mem::take(&mut self.player_stats);
+    let game_ended = game_ended.with_results(elimination_order, stats);
+
+    // Remember the result for lobby screens between rounds
+    let players = std::mem::take(&mut self.registered);
+    self.recent_results.truncate(MAX_RECENT_RESULTS - 1);
+    self.recent_results.push_front(MatchResult::new(
+      game_ended.get_match_id(),
+      game_ended.get_winners().clone(),
+      players,
+    ));
+
+    self.metrics.registered_players.set(0);
+    self.metrics.games_ended.inc();
     self.player_order = None;
+    self.match_id = None;
+    self.bots_alive.clear();
+    self.latest_state = None;
     self.server_state = ServerState::Registration;
+    self.record_replay(&game_ended);
+    self.finish_replay();
     self.broadcast_all(game_ended);
   }
 }
 
+impl Handler<GameCancelled> for GameMediatorActor {
+  type Result = ();
+
+  fn handle(&mut self, GameCancelled { num_players }: GameCancelled, _: &mut Self::Context) -> Self::Result {
+    if self.server_state == ServerState::Initializing {
+      self.cancel_game(num_players);
+    }
+  }
+}
+
 impl Handler<GameEngineCrash> for GameMediatorActor {
   type Result = ();
 
-  fn handle(&mut self, _: GameEngineCrash, _: &mut Self::Context) -> Self::Result {
+  fn handle(&mut self, crash: GameEngineCrash, _: &mut Self::Context) -> Self::Result {
     self.server_state = ServerState::FatalError;
+    self.metrics.game_engine_crashes.inc();
+    if crash.timed_out {
+      self.metrics.game_engine_timeouts.inc();
+    }
     self.player_order = None;
+    self.match_id = None;
+    self.grace_secs_left = None;
+    self.bots_alive.clear();
+    self.latest_state = None;
+    self.finish_replay();
 
     for (_, actor) in self.actors.iter() {
-      actor.do_send(GameEngineCrash);
+      actor.do_send(crash.clone());
     }
   }
 }
@@ -301,3 +873,135 @@ impl Handler<GetRegisteredPlayers> for GameMediatorActor {
     }
   }
 }
+
+impl Handler<GetServerState> for GameMediatorActor {
+  type Result = GetServerStateResponse;
+
+  fn handle(&mut self, _: GetServerState, _: &mut Self::Context) -> Self::Result {
+    GetServerStateResponse(self.server_state)
+  }
+}
+
+impl Handler<GetGameState> for GameMediatorActor {
+  type Result = GetGameStateResponse;
+
+  fn handle(&mut self, _: GetGameState, _: &mut Self::Context) -> Self::Result {
+    let registration = (self.server_state == ServerState::Registration).then(|| {
+      if self.registered.len() < self.min_players_needed {
+        RegistrationUpdateEnum::WaitingOnPlayers {
+          players: self.registered.clone(),
+          min_players_needed: self.min_players_needed,
+          max_players_allowed: self.max_players_allowed,
+        }
+      } else {
+        RegistrationUpdateEnum::GameStartingSoon {
+          players: self.registered.clone(),
+          min_players_needed: self.min_players_needed,
+          max_players_allowed: self.max_players_allowed,
+          seconds_left: self.secs_left,
+        }
+      }
+    });
+
+    GetGameStateResponse {
+      server_state: self.server_state,
+      registration,
+      match_id: self.match_id,
+      latest_state: self.latest_state.clone(),
+    }
+  }
+}
+
+impl Handler<GetPlayerState> for GameMediatorActor {
+  type Result = Option<Player>;
+
+  fn handle(&mut self, GetPlayerState(player_id): GetPlayerState, _: &mut Self::Context) -> Self::Result {
+    if self.server_state != ServerState::Running {
+      return None;
+    }
+
+    let latest_state = self.latest_state.as_ref()?;
+    latest_state.get_game_state().get_players().get(&player_id).cloned()
+  }
+}
+
+impl Handler<GetRecentResults> for GameMediatorActor {
+  type Result = MessageResult<GetRecentResults>;
+
+  fn handle(&mut self, GetRecentResults(limit): GetRecentResults, _: &mut Self::Context) -> Self::Result {
+    let limit = limit.unwrap_or(MAX_RECENT_RESULTS).min(MAX_RECENT_RESULTS);
+    MessageResult(self.recent_results.iter().take(limit).cloned().collect())
+  }
+}
+
+impl Handler<SetLobbyWait> for GameMediatorActor {
+  type Result = SetLobbyWaitResponse;
+
+  fn handle(&mut self, SetLobbyWait { seconds }: SetLobbyWait, _: &mut Self::Context) -> Self::Result {
+    let old_wait_secs = self.lobby_wait_secs;
+    log::info!("Lobby wait time changed from {}s to {}s", old_wait_secs, seconds);
+    self.lobby_wait_secs = seconds;
+
+    let countdown_running =
+      self.server_state == ServerState::Registration && self.registered.len() >= self.min_players_needed;
+    if !countdown_running {
+      return SetLobbyWaitResponse {
+        lobby_wait_secs: seconds,
+        seconds_left: None,
+      };
+    }
+
+    // Keep the time already waited, so only the remaining time changes
+    self.secs_left = if seconds >= old_wait_secs {
+      self.secs_left.saturating_add(seconds - old_wait_secs)
+    } else {
+      self.secs_left.saturating_sub(old_wait_secs - seconds).max(1)
+    };
+    self.broadcast_all(RegistrationUpdate::countdown_tick(self.secs_left));
+
+    SetLobbyWaitResponse {
+      lobby_wait_secs: seconds,
+      seconds_left: Some(self.secs_left),
+    }
+  }
+}
+
+impl Handler<RebootEngine> for GameMediatorActor {
+  type Result = RebootEngineResponse;
+
+  fn handle(&mut self, RebootEngine(game_engine): RebootEngine, _: &mut Self::Context) -> Self::Result {
+    if self.server_state != ServerState::FatalError {
+      return RebootEngineResponse::NotCrashed(self.server_state);
+    }
+
+    log::info!("Rebooting the game engine...");
+    if let Err(e) = game_engine.restart() {
+      log::error!("Failed to reboot the game engine: {}", e.get_developer_notes());
+      return RebootEngineResponse::Failed(e);
+    }
+
+    // Start over with a brand new registration
+    log::info!("Game engine rebooted - waiting for players to register for the game");
+    self.registered.clear();
+    self.metrics.registered_players.set(0);
+    self.secs_left = self.lobby_wait_secs;
+    self.server_state = ServerState::Registration;
+    self.broadcast_registration_update();
+
+    RebootEngineResponse::Success
+  }
+}
+
+impl Handler<Shutdown> for GameMediatorActor {
+  type Result = ();
+
+  fn handle(&mut self, Shutdown { reason }: Shutdown, _: &mut Self::Context) -> Self::Result {
+    log::info!(
+      "Closing {} player connection(s) and {} viewer(s): {}",
+      self.actors.len(),
+      self.viewers.len(),
+      reason
+    );
+    self.broadcast_all(ServerShutdown::new(reason));
+  }
+}
@@ -2,9 +2,11 @@ use actix::prelude::*;
 use std::collections::HashMap;
 use uuid::Uuid;
 
-use crate::actors::{ViewerActor, WebsocketActor};
-use crate::game::ServerState;
+use crate::actors::{shared_messages::NextState, ViewerActor, WebsocketActor};
+use crate::errors::GameEngineError;
+use crate::game::{GameEngine, ServerState};
 use crate::jwt::JWTPlayerData;
+use crate::protocol::{MatchResult, Player, QueryRequest, RegistrationUpdateEnum};
 
 /// Connect a websocket actor with the mediator
 #[derive(Debug, Clone, Message)]
@@ -15,9 +17,28 @@ pub struct Connect(pub Uuid, pub Addr<WebsocketActor>);
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Message, MessageResponse)]
 #[rtype(result = "()")]
 pub enum ConnectResponse {
-  Ok(ServerState),
+  Ok(ServerState, Uuid), // Also returns the nonce for the next reconnection token
   NotRegistered,
   AlreadyConnected,
+  InvalidReconnectToken,
+}
+
+/// Connect a websocket actor using a reconnection token
+///   The nonce must match the last one issued to the player
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "ConnectResponse")]
+pub struct Reconnect {
+  pub id: Uuid,
+  pub nonce: Uuid,
+  pub addr: Addr<WebsocketActor>,
+}
+
+/// Sent by the game engine when the Lua code rejects a player action
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+pub struct RejectAction {
+  pub player_id: Uuid,
+  pub tag: Option<String>,
 }
 
 /// Disconnect a websocket actor from the mediator
@@ -40,7 +61,26 @@ pub struct ConnectViewerResponse(pub ServerState);
 #[rtype(result = "()")]
 pub struct DisconnectViewer(pub Addr<ViewerActor>);
 
+/// Stop sending a viewer a single player's view of the game
+///   The mediator answers through the viewer's mailbox, so the reply arrives before any later delta updates
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+pub struct StopSpectating {
+  pub addr: Addr<ViewerActor>,
+  pub query: QueryRequest,
+}
+
+/// Reply to StopSpectating with the latest full game state
+///   Only set with delta updates, since the viewer's copy of the game is still the player's view
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+pub struct SpectatingStopped {
+  pub latest_state: Option<NextState>,
+  pub query: QueryRequest,
+}
+
 /// Register a player in the game -- This is idempotent
+///  Registering again replaces the stored player data
 #[derive(Debug, Clone, Message)]
 #[rtype(result = "RegisterResponse")]
 pub struct Register {
@@ -74,3 +114,69 @@ pub struct GetRegisteredPlayersResponse {
   pub players: HashMap<Uuid, JWTPlayerData>,
   pub player_order: Option<Vec<Uuid>>,
 }
+
+/// Get the current server state from the mediator
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "GetServerStateResponse")]
+pub struct GetServerState;
+
+#[derive(Debug, Clone, Copy, MessageResponse)]
+pub struct GetServerStateResponse(pub ServerState);
+
+/// Get a snapshot of the latest game state from the mediator
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "GetGameStateResponse")]
+pub struct GetGameState;
+
+#[derive(Debug, Clone, MessageResponse)]
+pub struct GetGameStateResponse {
+  pub server_state: ServerState,
+  pub registration: Option<RegistrationUpdateEnum>, // Only set during registration
+  pub match_id: Option<Uuid>,                       // Only set while a game is in progress
+  pub latest_state: Option<NextState>,
+}
+
+/// Get a single player's details from the latest game state
+///  Returns None if the game isn't running or the player is no longer in the game
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "Option<Player>")]
+pub struct GetPlayerState(pub Uuid);
+
+/// Get the results of the most recent games, newest first
+///  The limit is capped by the number of games the mediator remembers
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "Vec<MatchResult>")]
+pub struct GetRecentResults(pub Option<usize>);
+
+/// Change how long the lobby waits before starting the game
+///   A running countdown is shifted by the same amount, but never drops below one second
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "SetLobbyWaitResponse")]
+pub struct SetLobbyWait {
+  pub seconds: u32,
+}
+
+#[derive(Debug, Clone, Copy, MessageResponse)]
+pub struct SetLobbyWaitResponse {
+  pub lobby_wait_secs: u32,
+  pub seconds_left: Option<u32>, // Only set if the countdown is running
+}
+
+/// Restart the game engine after a fatal error
+#[derive(Clone, Message)]
+#[rtype(result = "RebootEngineResponse")]
+pub struct RebootEngine(pub GameEngine);
+
+#[derive(Debug, MessageResponse)]
+pub enum RebootEngineResponse {
+  Success,
+  NotCrashed(ServerState),
+  Failed(GameEngineError),
+}
+
+/// Notify all connected clients that the server is about to exit
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+pub struct Shutdown {
+  pub reason: String,
+}
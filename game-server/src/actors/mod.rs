@@ -3,11 +3,13 @@
 //
 mod game_mediator_actor;
 pub mod mediator_messages;
+mod rate_limiter;
+mod replay_recorder;
 pub mod shared_messages;
 mod viewer_actor;
 mod websocket_actor;
 pub mod websocket_messages;
 
-pub use game_mediator_actor::GameMediatorActor;
+pub use game_mediator_actor::{GameMediatorActor, MediatorConfig};
 pub use viewer_actor::ViewerActor;
 pub use websocket_actor::WebsocketActor;
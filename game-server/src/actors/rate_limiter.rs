@@ -0,0 +1,56 @@
+use std::time::Instant;
+
+/// Token bucket that limits how many messages a single websocket client can send
+///   A limit of 0 disables rate limiting entirely
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+  max_per_second: u32,
+  tokens: f64,
+  last_refill: Instant,
+  dropped: u32,
+}
+
+impl RateLimiter {
+  pub fn new(max_per_second: u32) -> Self {
+    Self {
+      max_per_second,
+      tokens: max_per_second.into(),
+      last_refill: Instant::now(),
+      dropped: 0,
+    }
+  }
+
+  /// Milliseconds until the bucket has a token for the next message
+  pub fn get_retry_after_ms(&self) -> u64 {
+    if self.max_per_second == 0 || self.tokens >= 1.0 {
+      return 0;
+    }
+
+    let missing_secs = (1.0 - self.tokens) / f64::from(self.max_per_second);
+    (missing_secs * 1000.0).ceil() as u64
+  }
+
+  /// Try to take a token for the next message
+  ///   Returns the number of messages dropped in a row if the bucket is empty
+  pub fn try_acquire(&mut self) -> Result<(), u32> {
+    if self.max_per_second == 0 {
+      return Ok(());
+    }
+
+    // Refill the bucket based on how much time has passed, up to a one second burst
+    let now = Instant::now();
+    let max_tokens = f64::from(self.max_per_second);
+    let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+    self.tokens = (self.tokens + elapsed * max_tokens).min(max_tokens);
+    self.last_refill = now;
+
+    if self.tokens >= 1.0 {
+      self.tokens -= 1.0;
+      self.dropped = 0;
+      Ok(())
+    } else {
+      self.dropped = self.dropped.saturating_add(1);
+      Err(self.dropped)
+    }
+  }
+}
@@ -0,0 +1,64 @@
+use bytestring::ByteString;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use uuid::Uuid;
+
+use crate::protocol::ToBytestring;
+
+/// Writes every message broadcast during a match to a newline-delimited JSON file
+///   Each line is the message as a viewer receives it, in the order it was sent
+///   Game states are always recorded in full, never as delta updates or per-player views
+///
+/// The file is written on its own thread so a slow disk never blocks the game mediator
+pub struct ReplayRecorder {
+  path: PathBuf,
+  sender: Sender<ByteString>,
+}
+
+impl ReplayRecorder {
+  /// Create the replay file "<match_id>.jsonl" inside the directory, creating the directory if needed
+  pub fn create(replay_dir: &Path, match_id: Uuid) -> io::Result<Self> {
+    fs::create_dir_all(replay_dir)?;
+
+    let path = replay_dir.join(format!("{}.jsonl", match_id));
+    let file = BufWriter::new(File::create(&path)?);
+
+    let (sender, receiver) = mpsc::channel();
+    let writer_path = path.clone();
+    thread::Builder::new()
+      .name("replay-writer".into())
+      .spawn(move || match write_replay(file, receiver) {
+        Ok(()) => log::info!("Saved match replay to '{}'", writer_path.display()),
+        Err(e) => log::warn!("Failed to write replay file '{}': {}", writer_path.display(), e),
+      })?;
+
+    Ok(Self { path, sender })
+  }
+
+  pub fn get_path(&self) -> &Path {
+    &self.path
+  }
+
+  /// Queue a single message to be appended to the replay
+  ///   Fails if the writer thread already stopped because of an earlier error
+  pub fn record(&mut self, message: &impl ToBytestring) -> io::Result<()> {
+    let json = message.to_bytestring()?;
+    self
+      .sender
+      .send(json)
+      .map_err(|_| io::Error::other("replay writer has stopped"))
+  }
+}
+
+/// Append each message on its own line until the recorder is dropped
+fn write_replay(mut file: BufWriter<File>, messages: Receiver<ByteString>) -> io::Result<()> {
+  for json in messages {
+    file.write_all(json.as_bytes())?;
+    file.write_all(b"\n")?;
+  }
+
+  file.flush()
+}
@@ -1,22 +1,44 @@
 use actix::prelude::*;
+use bytes::Bytes;
 use bytestring::ByteString;
+use chrono::Utc;
 use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use uuid::Uuid;
 
 use crate::jwt::JWTPlayerData;
 use crate::protocol::*;
 
+/// Game state that each player is allowed to see, from the optional Lua ViewFor() method
+///   Players without an entry (and all viewers) see the full game state
+pub type PlayerViews = HashMap<Uuid, Arc<GameState>>;
+
 /// Fatal error has caused the game engine to crash - Server must reboot!
+///   Lua methods that run past LUA_TIMEOUT_MS are reported separately, since they usually mean an infinite loop
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+pub struct GameEngineCrash {
+  pub timed_out: bool,
+}
+
+/// Round was refused because there are not enough players, so registration is open again
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+pub struct GameCancelled {
+  pub num_players: usize,
+}
+
+/// Client has fallen too far behind on the broadcast messages, so the connection should be closed
 #[derive(Debug, Clone, Message)]
 #[rtype(result = "()")]
-pub struct GameEngineCrash;
+pub struct ConnectionTooSlow;
 
 ///
 /// Broadcast update about registration
 ///
 #[derive(Debug, Clone, Message)]
 #[rtype(result = "()")]
-pub struct RegistrationUpdate(ByteString);
+pub struct RegistrationUpdate(EncodedMessage);
 
 impl RegistrationUpdate {
   pub fn waiting_on_players(
@@ -24,14 +46,11 @@ impl RegistrationUpdate {
     min_players_needed: usize,
     max_players_allowed: usize,
   ) -> Self {
-    Self(
-      RegistrationUpdateEnum::WaitingOnPlayers {
-        players,
-        min_players_needed,
-        max_players_allowed,
-      }
-      .into_bytestring(),
-    )
+    Self(EncodedMessage::new(RegistrationUpdateEnum::WaitingOnPlayers {
+      players,
+      min_players_needed,
+      max_players_allowed,
+    }))
   }
 
   pub fn game_starting_soon(
@@ -40,19 +59,45 @@ impl RegistrationUpdate {
     max_players_allowed: usize,
     seconds_left: u32,
   ) -> Self {
-    Self(
-      RegistrationUpdateEnum::GameStartingSoon {
-        players,
-        min_players_needed,
-        max_players_allowed,
-        seconds_left,
-      }
-      .into_bytestring(),
-    )
+    Self(EncodedMessage::new(RegistrationUpdateEnum::GameStartingSoon {
+      players,
+      min_players_needed,
+      max_players_allowed,
+      seconds_left,
+    }))
   }
 
-  pub fn game_starting(players: HashMap<Uuid, JWTPlayerData>, player_order: Vec<Uuid>) -> Self {
-    Self(RegistrationUpdateEnum::GameStarting { players, player_order }.into_bytestring())
+  pub fn countdown_tick(seconds_left: u32) -> Self {
+    Self(EncodedMessage::new(RegistrationUpdateEnum::CountdownTick {
+      seconds_left,
+    }))
+  }
+
+  pub fn player_registered(id: Uuid, data: JWTPlayerData) -> Self {
+    Self(EncodedMessage::new(RegistrationUpdateEnum::PlayerRegistered {
+      id,
+      data,
+    }))
+  }
+
+  pub fn player_unregistered(id: Uuid) -> Self {
+    Self(EncodedMessage::new(RegistrationUpdateEnum::PlayerUnregistered { id }))
+  }
+
+  pub fn game_starting(match_id: Uuid, players: HashMap<Uuid, JWTPlayerData>, player_order: Vec<Uuid>) -> Self {
+    Self(EncodedMessage::new(RegistrationUpdateEnum::GameStarting {
+      match_id,
+      players,
+      player_order,
+      protocol_version: PROTOCOL_VERSION,
+    }))
+  }
+
+  pub fn waiting_on_connections(missing_players: Vec<Uuid>, seconds_left: u32) -> Self {
+    Self(EncodedMessage::new(RegistrationUpdateEnum::WaitingOnConnections {
+      missing_players,
+      seconds_left,
+    }))
   }
 }
 
@@ -61,11 +106,26 @@ impl RegistrationUpdate {
 ///
 #[derive(Debug, Clone, Message)]
 #[rtype(result = "()")]
-pub struct GameStarting(ByteString);
+pub struct GameStarting {
+  match_id: Uuid,
+  data: EncodedMessage,
+}
 
 impl GameStarting {
-  pub fn new(players: HashMap<Uuid, JWTPlayerData>, player_order: Vec<Uuid>) -> Self {
-    Self(RegistrationUpdateEnum::GameStarting { players, player_order }.into_bytestring())
+  pub fn new(match_id: Uuid, players: HashMap<Uuid, JWTPlayerData>, player_order: Vec<Uuid>) -> Self {
+    Self {
+      match_id,
+      data: EncodedMessage::new(RegistrationUpdateEnum::GameStarting {
+        match_id,
+        players,
+        player_order,
+        protocol_version: PROTOCOL_VERSION,
+      }),
+    }
+  }
+
+  pub fn get_match_id(&self) -> Uuid {
+    self.match_id
   }
 }
 
@@ -74,18 +134,127 @@ impl GameStarting {
 ///
 #[derive(Debug, Clone, Message)]
 #[rtype(result = "()")]
-pub struct Init(ByteString);
+pub struct Init {
+  match_id: Uuid,
+  player_order: Arc<Vec<Uuid>>,
+  game_state: Arc<GameState>,
+  player_views: Arc<PlayerViews>,
+  ticks_left: u32,
+  seconds_per_tick: u32,
+  started_at: String,
+  data: EncodedMessage,
+}
 
 impl Init {
-  pub fn new(game_state: GameState, ticks_left: u32, seconds_per_tick: u32) -> Self {
-    Self(
-      GameStateUpdate::Init {
-        game_state,
+  pub fn new(
+    match_id: Uuid,
+    player_order: &[Uuid],
+    game_state: GameState,
+    ticks_left: u32,
+    seconds_per_tick: u32,
+  ) -> Self {
+    let started_at = Utc::now().to_rfc3339();
+    let player_order = Arc::new(player_order.to_vec());
+    let game_state = Arc::new(game_state);
+    let data = Self::encode(
+      match_id,
+      &game_state,
+      ticks_left,
+      seconds_per_tick,
+      &player_order,
+      &started_at,
+    );
+
+    Self {
+      match_id,
+      player_order,
+      game_state,
+      player_views: Arc::default(),
+      ticks_left,
+      seconds_per_tick,
+      started_at,
+      data,
+    }
+  }
+
+  /// Encode the message with either the full game state or a player's view
+  ///   The shared data is kept around in case a client needs the MessagePack encoding later
+  fn encode(
+    match_id: Uuid,
+    game_state: &Arc<GameState>,
+    ticks_left: u32,
+    seconds_per_tick: u32,
+    player_order: &Arc<Vec<Uuid>>,
+    started_at: &str,
+  ) -> EncodedMessage {
+    let (game_state, player_order, started_at) = (game_state.clone(), player_order.clone(), started_at.to_owned());
+    EncodedMessage::from_fn(move |encode| {
+      encode(&GameStateUpdate::Init {
+        match_id,
+        game_state: &game_state,
         ticks_left,
         seconds_per_tick,
-      }
-      .into_bytestring(),
-    )
+        player_order: &player_order,
+        started_at: &started_at,
+        protocol_version: PROTOCOL_VERSION,
+      })
+    })
+  }
+
+  /// Attach the (possibly filtered) game state that each player is allowed to see
+  pub fn with_player_views(self, player_views: PlayerViews) -> Self {
+    Self {
+      player_views: Arc::new(player_views),
+      ..self
+    }
+  }
+
+  /// Build a copy of this message with only the game state that the given player is allowed to see
+  pub fn view_for(&self, player_id: &Uuid) -> Self {
+    let game_state = match self.player_views.get(player_id) {
+      Some(game_state) => game_state.clone(),
+      None => return self.clone(),
+    };
+
+    let data = Self::encode(
+      self.match_id,
+      &game_state,
+      self.ticks_left,
+      self.seconds_per_tick,
+      &self.player_order,
+      &self.started_at,
+    );
+
+    Self {
+      game_state,
+      player_views: Arc::default(),
+      data,
+      ..self.clone()
+    }
+  }
+
+  pub fn has_player_views(&self) -> bool {
+    !self.player_views.is_empty()
+  }
+
+  pub fn get_match_id(&self) -> Uuid {
+    self.match_id
+  }
+
+  pub fn get_game_state(&self) -> &Arc<GameState> {
+    &self.game_state
+  }
+
+  pub fn get_player_views(&self) -> &Arc<PlayerViews> {
+    &self.player_views
+  }
+
+  pub fn get_ticks_left(&self) -> u32 {
+    self.ticks_left
+  }
+
+  pub fn get_seconds_per_tick(&self) -> u32 {
+    self.seconds_per_tick
   }
 }
 
@@ -94,24 +263,174 @@ impl Init {
 ///
 #[derive(Debug, Clone, Message)]
 #[rtype(result = "()")]
-pub struct NextState(ByteString);
+pub struct NextState {
+  match_id: Uuid,
+  game_state: Arc<GameState>,
+  actions_taken: Arc<HashMap<Uuid, PlayerAction>>,
+  player_views: Arc<PlayerViews>,
+  current_tick: u32,
+  ticks_left: u32,
+  seconds_per_tick: u32,
+  state_hash: Option<String>, // Hash of the game state in this message (see GameState::state_hash)
+  data: EncodedMessage,
+}
 
 impl NextState {
   pub fn new(
-    game_state: GameState,
+    match_id: Uuid,
+    game_state: impl Into<Arc<GameState>>,
     actions_taken: HashMap<Uuid, PlayerAction>,
+    current_tick: u32,
     ticks_left: u32,
     seconds_per_tick: u32,
+    state_hash: Option<String>,
   ) -> Self {
-    Self(
-      GameStateUpdate::NextState {
-        game_state,
-        actions_taken,
+    let game_state = game_state.into();
+    let actions_taken = Arc::new(actions_taken);
+    let data = Self::encode(
+      match_id,
+      &game_state,
+      &actions_taken,
+      current_tick,
+      ticks_left,
+      seconds_per_tick,
+      state_hash.as_deref(),
+    );
+
+    Self {
+      match_id,
+      game_state,
+      actions_taken,
+      player_views: Arc::default(),
+      current_tick,
+      ticks_left,
+      seconds_per_tick,
+      state_hash,
+      data,
+    }
+  }
+
+  /// Encode the message with either the full game state or a player's view
+  fn encode(
+    match_id: Uuid,
+    game_state: &Arc<GameState>,
+    actions_taken: &Arc<HashMap<Uuid, PlayerAction>>,
+    current_tick: u32,
+    ticks_left: u32,
+    seconds_per_tick: u32,
+    state_hash: Option<&str>,
+  ) -> EncodedMessage {
+    let (game_state, actions_taken, state_hash) =
+      (game_state.clone(), actions_taken.clone(), state_hash.map(str::to_owned));
+    EncodedMessage::from_fn(move |encode| {
+      encode(&GameStateUpdate::NextState {
+        match_id,
+        game_state: &game_state,
+        actions_taken: &actions_taken,
+        current_tick,
         ticks_left,
         seconds_per_tick,
-      }
-      .into_bytestring(),
-    )
+        state_hash: state_hash.as_deref(),
+      })
+    })
+  }
+
+  /// Build a copy of this message that only sends the changes since the previous game state
+  pub fn to_delta(&self, previous: &GameState) -> Self {
+    let (match_id, current_tick, ticks_left, seconds_per_tick) =
+      (self.match_id, self.current_tick, self.ticks_left, self.seconds_per_tick);
+    let (changes, actions_taken, state_hash) = (
+      self.game_state.diff(previous),
+      self.actions_taken.clone(),
+      self.state_hash.clone(),
+    );
+
+    let data = EncodedMessage::from_fn(move |encode| {
+      encode(&GameStateUpdate::Delta {
+        match_id,
+        changes: changes.clone(),
+        actions_taken: &actions_taken,
+        current_tick,
+        ticks_left,
+        seconds_per_tick,
+        state_hash: state_hash.as_deref(),
+      })
+    });
+
+    Self { data, ..self.clone() }
+  }
+
+  /// Attach the (possibly filtered) game state that each player is allowed to see
+  pub fn with_player_views(self, player_views: PlayerViews) -> Self {
+    Self {
+      player_views: Arc::new(player_views),
+      ..self
+    }
+  }
+
+  /// Build a copy of this message with only the game state that the given player is allowed to see
+  pub fn view_for(&self, player_id: &Uuid) -> Self {
+    let game_state = match self.player_views.get(player_id) {
+      Some(game_state) => game_state.clone(),
+      None => return self.clone(),
+    };
+
+    // Players can only check the hash against the game state they can see
+    let state_hash = game_state.state_hash();
+    let data = Self::encode(
+      self.match_id,
+      &game_state,
+      &self.actions_taken,
+      self.current_tick,
+      self.ticks_left,
+      self.seconds_per_tick,
+      state_hash.as_deref(),
+    );
+
+    Self {
+      game_state,
+      state_hash,
+      player_views: Arc::default(),
+      data,
+      ..self.clone()
+    }
+  }
+
+  pub fn get_actions_taken(&self) -> &HashMap<Uuid, PlayerAction> {
+    &self.actions_taken
+  }
+
+  pub fn has_player_views(&self) -> bool {
+    !self.player_views.is_empty()
+  }
+
+  pub fn get_match_id(&self) -> Uuid {
+    self.match_id
+  }
+
+  pub fn get_game_state(&self) -> &Arc<GameState> {
+    &self.game_state
+  }
+
+  /// Get the game state that the given player is allowed to see
+  pub fn get_player_view(&self, player_id: &Uuid) -> &Arc<GameState> {
+    self.player_views.get(player_id).unwrap_or(&self.game_state)
+  }
+
+  pub fn get_current_tick(&self) -> u32 {
+    self.current_tick
+  }
+
+  pub fn get_state_hash(&self) -> Option<&str> {
+    self.state_hash.as_deref()
+  }
+
+  pub fn get_ticks_left(&self) -> u32 {
+    self.ticks_left
+  }
+
+  pub fn get_seconds_per_tick(&self) -> u32 {
+    self.seconds_per_tick
   }
 }
 
@@ -119,18 +438,27 @@ impl NextState {
 #[derive(Debug, Clone, Message)]
 #[rtype(result = "()")]
 pub struct PlayerKilled {
+  match_id: Uuid,
   player_id: Uuid,
-  data: ByteString,
+  data: EncodedMessage,
 }
 
 impl PlayerKilled {
-  pub fn new(player_id: Uuid) -> Self {
+  pub fn new(match_id: Uuid, player_id: Uuid) -> Self {
     Self {
+      match_id,
       player_id,
-      data: GameStateUpdate::PlayerKilled { id: player_id }.into_bytestring(),
+      data: EncodedMessage::new(GameStateUpdate::PlayerKilled {
+        match_id,
+        id: player_id,
+      }),
     }
   }
 
+  pub fn get_match_id(&self) -> Uuid {
+    self.match_id
+  }
+
   pub fn get_player_id(&self) -> Uuid {
     self.player_id
   }
@@ -139,77 +467,168 @@ impl PlayerKilled {
 /// Broadcast the game ended message
 #[derive(Debug, Clone, Message)]
 #[rtype(result = "()")]
-pub struct GameEnded(ByteString);
+pub struct GameEnded {
+  match_id: Uuid,
+  winners: Arc<HashSet<Uuid>>,
+  game_state: Arc<GameState>,
+  actions_taken: Arc<HashMap<Uuid, PlayerAction>>,
+  elimination_order: Arc<Vec<Uuid>>,
+  stats: Arc<HashMap<Uuid, PlayerStats>>,
+  data: EncodedMessage,
+}
 
 impl GameEnded {
-  pub fn new(winners: HashSet<Uuid>, game_state: GameState, actions_taken: HashMap<Uuid, PlayerAction>) -> Self {
-    Self(
-      GameStateUpdate::GameEnded {
-        winners,
-        game_state,
-        actions_taken,
-      }
-      .into_bytestring(),
-    )
-  }
-}
+  pub fn new(
+    match_id: Uuid,
+    winners: HashSet<Uuid>,
+    game_state: GameState,
+    actions_taken: HashMap<Uuid, PlayerAction>,
+  ) -> Self {
+    let (winners, game_state, actions_taken) = (Arc::new(winners), Arc::new(game_state), Arc::new(actions_taken));
+    let (elimination_order, stats) = (Arc::default(), Arc::default());
+    let data = Self::encode(
+      match_id,
+      &winners,
+      &game_state,
+      &actions_taken,
+      &elimination_order,
+      &stats,
+    );
 
-impl ToBytestring for RegistrationUpdate {
-  fn to_bytestring(&self) -> ByteString {
-    self.0.clone()
+    Self {
+      match_id,
+      winners,
+      game_state,
+      actions_taken,
+      elimination_order,
+      stats,
+      data,
+    }
   }
 
-  fn into_bytestring(self) -> ByteString {
-    self.0
+  /// Encode the message, with or without the final results
+  fn encode(
+    match_id: Uuid,
+    winners: &Arc<HashSet<Uuid>>,
+    game_state: &Arc<GameState>,
+    actions_taken: &Arc<HashMap<Uuid, PlayerAction>>,
+    elimination_order: &Arc<Vec<Uuid>>,
+    stats: &Arc<HashMap<Uuid, PlayerStats>>,
+  ) -> EncodedMessage {
+    let (winners, game_state, actions_taken) = (winners.clone(), game_state.clone(), actions_taken.clone());
+    let (elimination_order, stats) = (elimination_order.clone(), stats.clone());
+    EncodedMessage::from_fn(move |encode| {
+      encode(&GameStateUpdate::GameEnded {
+        match_id,
+        winners: &winners,
+        game_state: &game_state,
+        actions_taken: &actions_taken,
+        elimination_order: &elimination_order,
+        stats: &stats,
+      })
+    })
   }
-}
 
-impl ToBytestring for Init {
-  fn to_bytestring(&self) -> ByteString {
-    self.0.clone()
+  /// Attach the final placements (first place first) and the player statistics,
+  ///   which are tracked by the mediator over the whole round
+  pub fn with_results(self, elimination_order: Vec<Uuid>, stats: HashMap<Uuid, PlayerStats>) -> Self {
+    let (elimination_order, stats) = (Arc::new(elimination_order), Arc::new(stats));
+    let data = Self::encode(
+      self.match_id,
+      &self.winners,
+      &self.game_state,
+      &self.actions_taken,
+      &elimination_order,
+      &stats,
+    );
+
+    Self {
+      elimination_order,
+      stats,
+      data,
+      ..self
+    }
   }
 
-  fn into_bytestring(self) -> ByteString {
-    self.0
+  pub fn get_match_id(&self) -> Uuid {
+    self.match_id
   }
-}
 
-impl ToBytestring for GameStarting {
-  fn to_bytestring(&self) -> ByteString {
-    self.0.clone()
+  pub fn get_winners(&self) -> &HashSet<Uuid> {
+    &self.winners
   }
 
-  fn into_bytestring(self) -> ByteString {
-    self.0
+  pub fn get_actions_taken(&self) -> &HashMap<Uuid, PlayerAction> {
+    &self.actions_taken
   }
-}
 
-impl ToBytestring for NextState {
-  fn to_bytestring(&self) -> ByteString {
-    self.0.clone()
+  pub fn get_elimination_order(&self) -> &[Uuid] {
+    &self.elimination_order
   }
 
-  fn into_bytestring(self) -> ByteString {
-    self.0
+  pub fn get_stats(&self) -> &HashMap<Uuid, PlayerStats> {
+    &self.stats
   }
 }
 
-impl ToBytestring for PlayerKilled {
-  fn to_bytestring(&self) -> ByteString {
-    self.data.clone()
+/// Broadcast that the server is shutting down
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+pub struct ServerShutdown {
+  reason: String,
+  data: EncodedMessage,
+}
+
+impl ServerShutdown {
+  pub fn new(reason: impl Into<String>) -> Self {
+    let reason = reason.into();
+    Self {
+      data: EncodedMessage::from_fn({
+        let reason = reason.clone();
+        move |encode| encode(&GameStateUpdate::ServerShutdown { reason: &reason })
+      }),
+      reason,
+    }
   }
 
-  fn into_bytestring(self) -> ByteString {
-    self.data
+  pub fn get_reason(&self) -> &str {
+    &self.reason
   }
 }
 
-impl ToBytestring for GameEnded {
-  fn to_bytestring(&self) -> ByteString {
-    self.0.clone()
-  }
+/// Send each broadcast message using its pre-serialized data
+macro_rules! impl_encoded_message {
+  ($($message:ty => $field:tt),* $(,)?) => {
+    $(
+      impl ToBytestring for $message {
+        fn to_bytestring(&self) -> serde_json::Result<ByteString> {
+          self.$field.to_bytestring()
+        }
 
-  fn into_bytestring(self) -> ByteString {
-    self.0
-  }
+        fn into_bytestring(self) -> serde_json::Result<ByteString> {
+          self.$field.into_bytestring()
+        }
+      }
+
+      impl ToMsgpack for $message {
+        fn to_msgpack(&self) -> Result<Bytes, rmp_serde::encode::Error> {
+          self.$field.to_msgpack()
+        }
+
+        fn into_msgpack(self) -> Result<Bytes, rmp_serde::encode::Error> {
+          self.$field.into_msgpack()
+        }
+      }
+    )*
+  };
 }
+
+impl_encoded_message!(
+  RegistrationUpdate => 0,
+  Init => data,
+  GameStarting => data,
+  NextState => data,
+  PlayerKilled => data,
+  GameEnded => data,
+  ServerShutdown => data,
+);
@@ -6,59 +6,112 @@ use actix::prelude::*;
 use actix_http::ws::{CloseCode, CloseReason};
 use actix_web_actors::ws;
 use serde::Serialize;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
-use crate::actors::{mediator_messages::*, shared_messages::*, GameMediatorActor};
+use crate::actors::{mediator_messages::*, rate_limiter::RateLimiter, shared_messages::*, GameMediatorActor};
+use crate::config;
 use crate::errors::{ServiceError, WebsocketError};
 use crate::game::ServerState;
-use crate::protocol::{QueryResponse, ToBytestring, ViewerMessage};
+use crate::protocol::{
+  self, Encoding, MessageType, QueryRequest, QueryResponse, ToBytestring, ToMsgpack, ViewerMessage,
+};
 
 /// Actor used for managing the viewer communication
 pub struct ViewerActor {
   id: Uuid,
+  connection_id: Uuid, // Included in every log message, to follow a single session
   server_state: ServerState,
+  match_id: Option<Uuid>,
+  ticks_left: u32,
+  seconds_per_tick: u32,
+  spectating: Option<Uuid>, // Player whose view of the game is sent instead of the full state
+  player_views: bool,       // Whether the game sends each player a filtered view
   game_mediator: Addr<GameMediatorActor>,
+  encoding: Encoding,
+  last_heartbeat: Instant,
+  rate_limiter: RateLimiter,
+  max_message_bytes: usize,
 }
 
 impl ViewerActor {
-  pub fn new(id: Uuid, game_mediator: Addr<GameMediatorActor>) -> Self {
+  pub fn new(id: Uuid, connection_id: Uuid, game_mediator: Addr<GameMediatorActor>, encoding: Encoding) -> Self {
     Self {
       id,
+      connection_id,
       game_mediator,
+      encoding,
+      last_heartbeat: Instant::now(),
+      rate_limiter: RateLimiter::new(config::get_max_messages_per_second()),
+      max_message_bytes: config::get_max_message_bytes(),
       server_state: ServerState::Registration,
+      match_id: None,
+      ticks_left: 0,
+      seconds_per_tick: config::get_seconds_per_tick(),
+      spectating: None,
+      player_views: false,
     }
   }
 
-  /// Send a JSON response back to the client, handling any serialization errors
-  fn send_json<T>(data: &T, ctx: &mut <Self as Actor>::Context)
+  /// Send a response back to the client in the requested encoding, handling any serialization errors
+  fn send_data<T>(&self, data: &T, ctx: &mut <Self as Actor>::Context)
   where
     T: ?Sized + Serialize,
   {
-    match serde_json::to_string(data) {
-      Ok(json) => ctx.text(json),
-      Err(e) => log::error!("Failed to serialize JSON data: {}", e),
+    match self.encoding {
+      Encoding::Json => match serde_json::to_string(data) {
+        Ok(json) => ctx.text(json),
+        Err(e) => {
+          log::error!(viewer_id:% = self.id, connection_id:% = self.connection_id; "Failed to serialize JSON data: {}", e)
+        },
+      },
+      Encoding::MessagePack => match protocol::to_msgpack(data) {
+        Ok(bytes) => ctx.binary(bytes),
+        Err(e) => {
+          log::error!(viewer_id:% = self.id, connection_id:% = self.connection_id; "Failed to serialize MessagePack data: {}", e)
+        },
+      },
+    }
+  }
+
+  /// Send a pre-serialized broadcast message in the requested encoding
+  fn send_message(&self, message: impl ToBytestring + ToMsgpack, ctx: &mut <Self as Actor>::Context) {
+    match self.encoding {
+      Encoding::Json => match message.into_bytestring() {
+        Ok(json) => ctx.text(json),
+        Err(e) => {
+          log::error!(viewer_id:% = self.id, connection_id:% = self.connection_id; "Failed to send JSON message: {}", e)
+        },
+      },
+      Encoding::MessagePack => match message.into_msgpack() {
+        Ok(bytes) => ctx.binary(bytes),
+        Err(e) => {
+          log::error!(viewer_id:% = self.id, connection_id:% = self.connection_id; "Failed to send MessagePack message: {}", e)
+        },
+      },
     }
   }
 
   /// Send an error message back to the clinet
-  fn send_error(error: impl Into<ServiceError>, ctx: &mut <Self as Actor>::Context) {
+  fn send_error(&self, error: impl Into<ServiceError>, ctx: &mut <Self as Actor>::Context) {
     let error = error.into().get_error_response();
-    log::warn!("{}", error.get_description());
+    log::warn!(viewer_id:% = self.id, connection_id:% = self.connection_id; "{}", error.get_description());
 
-    Self::send_json(&error, ctx);
+    self.send_data(&error, ctx);
   }
 
   /// Send a fatal error message and stop the actor
   fn fatal_error(&self, error: impl Into<ServiceError>, close_code: CloseCode, ctx: &mut <Self as Actor>::Context) {
     let error = error.into().get_error_response();
     log::error!(
+      viewer_id:% = self.id, connection_id:% = self.connection_id;
       "Closing viewer {}: {} (Code {:#?})",
       self.id,
       error.get_description(),
       close_code
     );
 
-    Self::send_json(&error, ctx);
+    self.send_data(&error, ctx);
     ctx.close(Some(CloseReason::from((close_code, error.get_description().clone()))));
     ctx.stop();
   }
@@ -70,7 +123,26 @@ impl ViewerActor {
 impl Actor for ViewerActor {
   type Context = ws::WebsocketContext<Self>;
 
+  fn started(&mut self, ctx: &mut Self::Context) {
+    // Ping the client regularly to detect connections that silently dropped
+    let heartbeat_timeout = Duration::from_secs(config::get_heartbeat_timeout_seconds().into());
+    ctx.run_interval(
+      Duration::from_secs(config::get_heartbeat_interval_seconds().into()),
+      move |this, ctx| {
+        if this.last_heartbeat.elapsed() > heartbeat_timeout {
+          log::info!(viewer_id:% = this.id, connection_id:% = this.connection_id; "Heartbeat timed out, closing...");
+          ctx.close(Some(CloseReason::from((CloseCode::Away, "Heartbeat timed out"))));
+          return ctx.stop();
+        }
+
+        ctx.ping(b"");
+      },
+    );
+  }
+
   fn stopping(&mut self, ctx: &mut Self::Context) -> Running {
+    log::info!(viewer_id:% = self.id, connection_id:% = self.connection_id, event = "viewer_disconnected"; "Viewer disconnected");
+
     // Remove all references to this actor
     self.game_mediator.do_send(DisconnectViewer(ctx.address()));
     Running::Stop
@@ -82,48 +154,114 @@ impl Actor for ViewerActor {
 ///
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ViewerActor {
   fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
-    log::debug!("Received message: {:#?}", msg);
+    log::debug!(viewer_id:% = self.id, connection_id:% = self.connection_id; "Received message: {:#?}", msg);
     let msg: ws::Message = match msg {
-      Err(e) => return Self::send_error(WebsocketError::ProtocolError(e), ctx),
+      Err(ws::ProtocolError::Overflow) => {
+        let error = ServiceError::MessageTooLarge {
+          max_bytes: self.max_message_bytes,
+          received_bytes: None,
+        };
+        return self.fatal_error(error, CloseCode::Size, ctx);
+      },
+      Err(e) => return self.send_error(WebsocketError::ProtocolError(e), ctx),
       Ok(msg) => msg,
     };
 
-    // Parse as a JSON string
+    // Drop messages from clients that send too quickly (only the first dropped message gets an error)
+    if !matches!(msg, ws::Message::Pong(_) | ws::Message::Close(_)) {
+      match self.rate_limiter.try_acquire() {
+        Ok(()) => {},
+        Err(1) => {
+          let retry_after_ms = self.rate_limiter.get_retry_after_ms();
+          return self.send_error(ServiceError::RateLimited { retry_after_ms }, ctx);
+        },
+        Err(_) => return,
+      }
+    }
+
+    // Parse the message from the client
     let json = match msg {
       // Basic messages
       ws::Message::Nop => return,
-      ws::Message::Ping(msg) => return ctx.pong(&msg),
-      ws::Message::Pong(_) => return,
+      ws::Message::Ping(msg) => {
+        self.last_heartbeat = Instant::now();
+        return ctx.pong(&msg);
+      },
+      ws::Message::Pong(_) => {
+        self.last_heartbeat = Instant::now();
+        return;
+      },
       ws::Message::Close(reason) => {
-        log::info!("Received close message, closing... ({:#?})", reason);
+        log::info!(viewer_id:% = self.id, connection_id:% = self.connection_id; "Received close message, closing... ({:#?})", reason);
         ctx.close(reason);
         return ctx.stop();
       },
 
+      // Reject oversized messages before trying to parse them
+      ws::Message::Text(ref text) if text.len() > self.max_message_bytes => {
+        let error = ServiceError::MessageTooLarge {
+          max_bytes: self.max_message_bytes,
+          received_bytes: Some(text.len()),
+        };
+        return self.send_error(error, ctx);
+      },
+      ws::Message::Binary(ref bytes) if bytes.len() > self.max_message_bytes => {
+        let error = ServiceError::MessageTooLarge {
+          max_bytes: self.max_message_bytes,
+          received_bytes: Some(bytes.len()),
+        };
+        return self.send_error(error, ctx);
+      },
+
       // Parse JSON message
       ws::Message::Text(text) => match serde_json::from_str::<ViewerMessage>(&text) {
-        Err(e) => return Self::send_error(WebsocketError::JSONError(e), ctx),
+        Err(e) => {
+          let error = ServiceError::InvalidMessage {
+            message_type: MessageType::from_json(&text),
+            why: e.to_string(),
+          };
+          return self.send_error(error, ctx);
+        },
         Ok(json) => json,
       },
 
+      // Parse MessagePack message
+      ws::Message::Binary(bytes) if self.encoding == Encoding::MessagePack => {
+        match protocol::from_msgpack::<ViewerMessage>(&bytes) {
+          Err(e) => {
+            let error = ServiceError::InvalidMessage {
+              message_type: MessageType::from_msgpack(&bytes),
+              why: e.to_string(),
+            };
+            return self.send_error(error, ctx);
+          },
+          Ok(message) => message,
+        }
+      },
+
       // Unsupported messages
       ws::Message::Binary(_) => {
-        return Self::send_error(WebsocketError::UnsupportedFrameType("Binary".into()), ctx);
+        return self.send_error(WebsocketError::UnsupportedFrameType("Binary".into()), ctx);
       },
       ws::Message::Continuation(_) => {
-        return Self::send_error(WebsocketError::UnsupportedFrameType("Continuation".into()), ctx);
+        return self.send_error(WebsocketError::UnsupportedFrameType("Continuation".into()), ctx);
       },
     };
 
     // Handle the JSON messages
     match json {
-      ViewerMessage::GetServerState => self.send_current_state(ctx),
-      ViewerMessage::GetRegisteredPlayers => self.send_registered_players(ctx),
+      ViewerMessage::GetServerState(query) => self.send_current_state(query, ctx),
+      ViewerMessage::GetRegisteredPlayers(query) => self.send_registered_players(query, ctx),
+      ViewerMessage::GetTimeRemaining(query) => self.send_time_remaining(query, ctx),
+      ViewerMessage::GetGameConfig(query) => self.send_data(&QueryResponse::game_config().reply_to(query), ctx),
+      ViewerMessage::GetGameState(query) => self.send_game_state(query, ctx),
+      ViewerMessage::GetRecentResults { limit, query } => self.send_recent_results(limit, query, ctx),
+      ViewerMessage::SpectatePlayer { id, query } => self.spectate_player(id, query, ctx),
     }
   }
 
   fn finished(&mut self, ctx: &mut Self::Context) {
-    log::debug!("Websocket stream closed, stopping actor");
+    log::debug!(viewer_id:% = self.id, connection_id:% = self.connection_id; "Websocket stream closed, stopping actor");
     ctx.stop()
   }
 }
@@ -132,6 +270,7 @@ impl Handler<ConnectViewerResponse> for ViewerActor {
   type Result = ();
 
   fn handle(&mut self, ConnectViewerResponse(state): ConnectViewerResponse, ctx: &mut Self::Context) -> Self::Result {
+    log::info!(viewer_id:% = self.id, connection_id:% = self.connection_id, event = "viewer_connected"; "Viewer connected");
     self.server_state = state;
 
     // Special case: an error state should clse the connection
@@ -144,8 +283,20 @@ impl Handler<ConnectViewerResponse> for ViewerActor {
 impl Handler<GameEngineCrash> for ViewerActor {
   type Result = ();
 
-  fn handle(&mut self, _: GameEngineCrash, ctx: &mut Self::Context) -> Self::Result {
-    self.fatal_error(ServiceError::GameEngineCrash, CloseCode::Error, ctx);
+  fn handle(&mut self, crash: GameEngineCrash, ctx: &mut Self::Context) -> Self::Result {
+    if crash.timed_out {
+      self.fatal_error(ServiceError::GameEngineTimeout, CloseCode::Again, ctx);
+    } else {
+      self.fatal_error(ServiceError::GameEngineCrash, CloseCode::Error, ctx);
+    }
+  }
+}
+
+impl Handler<GameCancelled> for ViewerActor {
+  type Result = ();
+
+  fn handle(&mut self, GameCancelled { num_players }: GameCancelled, ctx: &mut Self::Context) -> Self::Result {
+    self.send_error(ServiceError::NotEnoughPlayers { num_players }, ctx);
   }
 }
 
@@ -153,7 +304,7 @@ impl Handler<RegistrationUpdate> for ViewerActor {
   type Result = ();
 
   fn handle(&mut self, update: RegistrationUpdate, ctx: &mut Self::Context) -> Self::Result {
-    ctx.text(update.into_bytestring());
+    self.send_message(update, ctx);
   }
 }
 
@@ -162,7 +313,8 @@ impl Handler<GameStarting> for ViewerActor {
 
   fn handle(&mut self, starting: GameStarting, ctx: &mut Self::Context) -> Self::Result {
     self.server_state = ServerState::Initializing;
-    ctx.text(starting.into_bytestring())
+    self.match_id = Some(starting.get_match_id());
+    self.send_message(starting, ctx)
   }
 }
 
@@ -171,7 +323,14 @@ impl Handler<Init> for ViewerActor {
 
   fn handle(&mut self, init: Init, ctx: &mut Self::Context) -> Self::Result {
     self.server_state = ServerState::Running;
-    ctx.text(init.into_bytestring())
+    self.match_id = Some(init.get_match_id());
+    self.ticks_left = init.get_ticks_left();
+    self.seconds_per_tick = init.get_seconds_per_tick();
+    self.player_views = init.has_player_views();
+    match self.spectating {
+      Some(player_id) => self.send_message(init.view_for(&player_id), ctx),
+      None => self.send_message(init, ctx),
+    }
   }
 }
 
@@ -179,7 +338,31 @@ impl Handler<NextState> for ViewerActor {
   type Result = ();
 
   fn handle(&mut self, state: NextState, ctx: &mut Self::Context) -> Self::Result {
-    ctx.text(state.into_bytestring())
+    self.match_id = Some(state.get_match_id());
+    self.ticks_left = state.get_ticks_left();
+    self.seconds_per_tick = state.get_seconds_per_tick();
+    self.player_views = state.has_player_views();
+    match self.spectating {
+      Some(player_id) => self.send_message(state.view_for(&player_id), ctx),
+      None => self.send_message(state, ctx),
+    }
+  }
+}
+
+impl Handler<SpectatingStopped> for ViewerActor {
+  type Result = ();
+
+  fn handle(&mut self, stopped: SpectatingStopped, ctx: &mut Self::Context) -> Self::Result {
+    self.spectating = None;
+    if let Some(latest_state) = stopped.latest_state {
+      self.send_message(latest_state, ctx);
+    }
+
+    let response = QueryResponse::Spectating {
+      id: None,
+      player_view: false,
+    };
+    self.send_data(&response.reply_to(stopped.query), ctx);
   }
 }
 
@@ -187,7 +370,7 @@ impl Handler<PlayerKilled> for ViewerActor {
   type Result = ();
 
   fn handle(&mut self, player_killed: PlayerKilled, ctx: &mut Self::Context) -> Self::Result {
-    ctx.text(player_killed.into_bytestring())
+    self.send_message(player_killed, ctx)
   }
 }
 
@@ -196,32 +379,148 @@ impl Handler<GameEnded> for ViewerActor {
 
   fn handle(&mut self, game_ended: GameEnded, ctx: &mut Self::Context) -> Self::Result {
     self.server_state = ServerState::Registration;
-    ctx.text(game_ended.into_bytestring())
+    self.match_id = None;
+    self.ticks_left = 0;
+    self.spectating = None;
+    self.player_views = false;
+    self.send_message(game_ended, ctx)
+  }
+}
+
+impl Handler<ServerShutdown> for ViewerActor {
+  type Result = ();
+
+  fn handle(&mut self, shutdown: ServerShutdown, ctx: &mut Self::Context) -> Self::Result {
+    log::info!(viewer_id:% = self.id, connection_id:% = self.connection_id; "Server shutting down, closing websocket...");
+    let close_reason = CloseReason::from((CloseCode::Restart, shutdown.get_reason()));
+    self.send_message(shutdown, ctx);
+    ctx.close(Some(close_reason));
+    ctx.stop();
+  }
+}
+
+impl Handler<ConnectionTooSlow> for ViewerActor {
+  type Result = ();
+
+  fn handle(&mut self, _: ConnectionTooSlow, ctx: &mut Self::Context) -> Self::Result {
+    log::info!(viewer_id:% = self.id, connection_id:% = self.connection_id; "Fell too far behind on messages, closing...");
+    ctx.close(Some(CloseReason::from((
+      CloseCode::Again,
+      "Too far behind on messages",
+    ))));
+    ctx.stop();
   }
 }
 
 impl ViewerActor {
-  fn send_current_state(&self, ctx: &mut <Self as Actor>::Context) {
-    Self::send_json(
+  fn send_current_state(&self, query: QueryRequest, ctx: &mut <Self as Actor>::Context) {
+    self.send_data(
       &QueryResponse::ServerState {
         state: self.server_state,
-      },
+        match_id: self.match_id,
+      }
+      .reply_to(query),
+      ctx,
+    );
+  }
+
+  fn send_time_remaining(&self, query: QueryRequest, ctx: &mut <Self as Actor>::Context) {
+    self.send_data(
+      &QueryResponse::TimeRemaining {
+        ticks_left: self.ticks_left,
+        seconds_per_tick: self.seconds_per_tick,
+      }
+      .reply_to(query),
       ctx,
     );
   }
 
-  fn send_registered_players(&self, ctx: &mut <Self as Actor>::Context) {
+  fn send_registered_players(&self, query: QueryRequest, ctx: &mut <Self as Actor>::Context) {
     // Spawn a future to process the request
     ctx.spawn(
-      wrap_future::<_, Self>(self.game_mediator.send(GetRegisteredPlayers)).map(|result, _this, ctx| match result {
-        Ok(registered) => Self::send_json(
+      wrap_future::<_, Self>(self.game_mediator.send(GetRegisteredPlayers)).map(|result, this, ctx| match result {
+        Ok(registered) => this.send_data(
           &QueryResponse::RegisteredPlayers {
             players: registered.players,
             player_order: registered.player_order,
-          },
+          }
+          .reply_to(query),
+          ctx,
+        ),
+        Err(e) => this.send_error(ServiceError::WebsocketMailboxError(e), ctx),
+      }),
+    );
+  }
+
+  fn send_recent_results(&self, limit: Option<usize>, query: QueryRequest, ctx: &mut <Self as Actor>::Context) {
+    // Spawn a future to process the request
+    ctx.spawn(
+      wrap_future::<_, Self>(self.game_mediator.send(GetRecentResults(limit))).map(|result, this, ctx| match result {
+        Ok(matches) => this.send_data(&QueryResponse::RecentResults { matches }.reply_to(query), ctx),
+        Err(e) => this.send_error(ServiceError::WebsocketMailboxError(e), ctx),
+      }),
+    );
+  }
+
+  /// Start (or stop) sending the viewer a single player's view of the game
+  ///   Without fog of war, the viewer still gets the full state and should highlight the player instead
+  fn spectate_player(&mut self, id: Option<Uuid>, query: QueryRequest, ctx: &mut <Self as Actor>::Context) {
+    let player_id = match id {
+      Some(player_id) => player_id,
+      None if self.spectating.is_some() => {
+        // Delta updates are computed against the full state, so the viewer needs it again before the next delta
+        return self.game_mediator.do_send(StopSpectating {
+          addr: ctx.address(),
+          query,
+        });
+      },
+      None => {
+        let response = QueryResponse::Spectating {
+          id: None,
+          player_view: false,
+        };
+        return self.send_data(&response.reply_to(query), ctx);
+      },
+    };
+
+    // Spawn a future to make sure the player is in the current match
+    ctx.spawn(
+      wrap_future::<_, Self>(self.game_mediator.send(GetRegisteredPlayers)).map(move |result, this, ctx| {
+        let in_match = match result {
+          Ok(registered) => registered.player_order.is_some_and(|order| order.contains(&player_id)),
+          Err(e) => return this.send_error(ServiceError::WebsocketMailboxError(e), ctx),
+        };
+        if !in_match {
+          return this.send_error(ServiceError::NotInMatch(player_id), ctx);
+        }
+
+        log::info!(viewer_id:% = this.id, connection_id:% = this.connection_id; "Spectating player {}", player_id);
+        this.spectating = Some(player_id);
+        let response = QueryResponse::Spectating {
+          id: Some(player_id),
+          player_view: this.player_views,
+        };
+        this.send_data(&response.reply_to(query), ctx);
+      }),
+    );
+  }
+
+  fn send_game_state(&self, query: QueryRequest, ctx: &mut <Self as Actor>::Context) {
+    // Spawn a future to process the request
+    ctx.spawn(
+      wrap_future::<_, Self>(self.game_mediator.send(GetGameState)).map(|result, this, ctx| match result {
+        Ok(snapshot) => this.send_data(
+          &QueryResponse::GameState {
+            server_state: snapshot.server_state,
+            match_id: snapshot.match_id,
+            game_state: snapshot
+              .latest_state
+              .map(|state| state.get_game_state().as_ref().clone()),
+          }
+          .reply_to(query),
           ctx,
         ),
-        Err(e) => Self::send_error(ServiceError::WebsocketMailboxError(e), ctx),
+        Err(e) => this.send_error(ServiceError::WebsocketMailboxError(e), ctx),
       }),
     );
   }
@@ -6,74 +6,142 @@ use actix::prelude::*;
 use actix_http::ws::{CloseCode, CloseReason};
 use actix_web_actors::ws;
 use serde::Serialize;
-use std::sync::mpsc::Sender;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{error::TrySendError, Sender};
 use uuid::Uuid;
 
-use crate::actors::{mediator_messages::*, shared_messages::*, websocket_messages::*, GameMediatorActor};
+use crate::actors::{
+  mediator_messages::*, rate_limiter::RateLimiter, shared_messages::*, websocket_messages::*, GameMediatorActor,
+};
+use crate::config;
 use crate::errors::{ServiceError, WebsocketError};
 use crate::game::ServerState;
-use crate::jwt::{JWTPlayerData, PlayerToken};
-use crate::protocol::{PlayerAction, QueryResponse, ToBytestring, WebsocketMessage};
+use crate::jwt::JWTPlayerData;
+use crate::protocol::{
+  self, Encoding, MessageType, PlayerAction, QueryRequest, QueryResponse, SentAction, ToBytestring, ToMsgpack,
+  WebsocketMessage,
+};
+
+const MAX_SENT_ACTIONS: usize = 20;
 
 /// Actor used for managing the websocket communication
 pub struct WebsocketActor {
   player_id: Uuid,
+  connection_id: Uuid, // Included in every log message, to follow a single session
   player_data: JWTPlayerData,
   game_mediator: Addr<GameMediatorActor>,
   send_player_action: Sender<(Uuid, PlayerAction)>,
+  encoding: Encoding,
+  last_heartbeat: Instant,
+  rate_limiter: RateLimiter,
+  max_message_bytes: usize,
+  action_replace_mode: bool,
+  last_activity: Instant,
+  connected: bool, // Only set once the mediator accepts the connection
 
   server_state: ServerState,
+  match_id: Option<Uuid>,
+  current_tick: u32,
+  ticks_left: u32,
+  seconds_per_tick: u32,
   action_sent: bool,
+  sent_actions: VecDeque<SentAction>, // Newest last, cleared every game
   player_killed: bool,
 }
 
 impl WebsocketActor {
   pub fn new(
-    player_token: PlayerToken,
+    player_id: Uuid,
+    connection_id: Uuid,
+    player_data: JWTPlayerData,
     game_mediator: Addr<GameMediatorActor>,
     send_player_action: Sender<(Uuid, PlayerAction)>,
+    encoding: Encoding,
   ) -> Self {
     Self {
-      player_id: player_token.get_id(),
-      player_data: player_token.into_data(),
+      player_id,
+      connection_id,
+      player_data,
       game_mediator,
       send_player_action,
+      encoding,
+      last_heartbeat: Instant::now(),
+      rate_limiter: RateLimiter::new(config::get_max_messages_per_second()),
+      max_message_bytes: config::get_max_message_bytes(),
+      action_replace_mode: config::action_replace_mode(),
+      last_activity: Instant::now(),
+      connected: false,
 
       server_state: ServerState::Registration,
+      match_id: None,
+      current_tick: 0,
+      ticks_left: 0,
+      seconds_per_tick: config::get_seconds_per_tick(),
       action_sent: false,
+      sent_actions: VecDeque::with_capacity(MAX_SENT_ACTIONS),
       player_killed: false,
     }
   }
 
-  /// Send a JSON response back to the client, handling any serialization errors
-  fn send_json<T>(data: &T, ctx: &mut <Self as Actor>::Context)
+  /// Send a response back to the client in the requested encoding, handling any serialization errors
+  fn send_data<T>(&self, data: &T, ctx: &mut <Self as Actor>::Context)
   where
     T: ?Sized + Serialize,
   {
-    match serde_json::to_string(data) {
-      Ok(json) => ctx.text(json),
-      Err(e) => log::error!("Failed to serialize JSON data: {}", e),
+    match self.encoding {
+      Encoding::Json => match serde_json::to_string(data) {
+        Ok(json) => ctx.text(json),
+        Err(e) => {
+          log::error!(player_id:% = self.player_id, connection_id:% = self.connection_id; "Failed to serialize JSON data: {}", e)
+        },
+      },
+      Encoding::MessagePack => match protocol::to_msgpack(data) {
+        Ok(bytes) => ctx.binary(bytes),
+        Err(e) => {
+          log::error!(player_id:% = self.player_id, connection_id:% = self.connection_id; "Failed to serialize MessagePack data: {}", e)
+        },
+      },
+    }
+  }
+
+  /// Send a pre-serialized broadcast message in the requested encoding
+  fn send_message(&self, message: impl ToBytestring + ToMsgpack, ctx: &mut <Self as Actor>::Context) {
+    match self.encoding {
+      Encoding::Json => match message.into_bytestring() {
+        Ok(json) => ctx.text(json),
+        Err(e) => {
+          log::error!(player_id:% = self.player_id, connection_id:% = self.connection_id; "Failed to send JSON message: {}", e)
+        },
+      },
+      Encoding::MessagePack => match message.into_msgpack() {
+        Ok(bytes) => ctx.binary(bytes),
+        Err(e) => {
+          log::error!(player_id:% = self.player_id, connection_id:% = self.connection_id; "Failed to send MessagePack message: {}", e)
+        },
+      },
     }
   }
 
   /// Send an error message back to the clinet
-  fn send_error(error: impl Into<ServiceError>, ctx: &mut <Self as Actor>::Context) {
+  fn send_error(&self, error: impl Into<ServiceError>, ctx: &mut <Self as Actor>::Context) {
     let error = error.into().get_error_response();
-    log::warn!("{}", error.get_description());
+    log::warn!(player_id:% = self.player_id, connection_id:% = self.connection_id; "{}", error.get_description());
 
-    Self::send_json(&error, ctx);
+    self.send_data(&error, ctx);
   }
 
   /// Send a fatal error message and stop the actor
-  fn fatal_error(error: impl Into<ServiceError>, close_code: CloseCode, ctx: &mut <Self as Actor>::Context) {
+  fn fatal_error(&self, error: impl Into<ServiceError>, close_code: CloseCode, ctx: &mut <Self as Actor>::Context) {
     let error = error.into().get_error_response();
     log::error!(
+      player_id:% = self.player_id, connection_id:% = self.connection_id;
       "Closing websocket: {} (Code {:#?})",
       error.get_description(),
       close_code
     );
 
-    Self::send_json(&error, ctx);
+    self.send_data(&error, ctx);
     ctx.close(Some(CloseReason::from((close_code, error.get_description().clone()))));
     ctx.stop();
   }
@@ -85,7 +153,46 @@ impl WebsocketActor {
 impl Actor for WebsocketActor {
   type Context = ws::WebsocketContext<Self>;
 
+  fn started(&mut self, ctx: &mut Self::Context) {
+    // Ping the client regularly to detect connections that silently dropped
+    let heartbeat_timeout = Duration::from_secs(config::get_heartbeat_timeout_seconds().into());
+    ctx.run_interval(
+      Duration::from_secs(config::get_heartbeat_interval_seconds().into()),
+      move |this, ctx| {
+        if this.last_heartbeat.elapsed() > heartbeat_timeout {
+          log::info!(player_id:% = this.player_id, connection_id:% = this.connection_id; "Heartbeat timed out, closing...");
+          ctx.close(Some(CloseReason::from((CloseCode::Away, "Heartbeat timed out"))));
+          return ctx.stop();
+        }
+
+        ctx.ping(b"");
+      },
+    );
+
+    // Players who go silent during registration shouldn't hold a lobby slot forever
+    //  (Silence is allowed once the game is running, so only check during registration)
+    let idle_timeout = config::get_player_idle_timeout_seconds();
+    if idle_timeout > 0 {
+      let idle_timeout = Duration::from_secs(idle_timeout.into());
+      ctx.run_interval(Duration::from_secs(1), move |this, ctx| {
+        if this.server_state == ServerState::Registration && this.last_activity.elapsed() > idle_timeout {
+          log::info!(player_id:% = this.player_id, connection_id:% = this.connection_id; "Player idle during registration, closing...");
+          this.game_mediator.do_send(Unregister { id: this.player_id });
+          ctx.close(Some(CloseReason::from((CloseCode::Away, "Idle timeout"))));
+          ctx.stop();
+        }
+      });
+    }
+  }
+
   fn stopping(&mut self, ctx: &mut Self::Context) -> Running {
+    if self.connected {
+      log::info!(
+        player_id:% = self.player_id, connection_id:% = self.connection_id, event = "player_disconnected";
+        "Player \"{}\" disconnected", self.player_data.get_name()
+      );
+    }
+
     // Remove all references to this actor
     self.game_mediator.do_send(Disconnect(self.player_id, ctx.address()));
     Running::Stop
@@ -97,44 +204,123 @@ impl Actor for WebsocketActor {
 ///
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebsocketActor {
   fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
-    log::debug!("Received message: {:#?}", msg);
+    log::debug!(player_id:% = self.player_id, connection_id:% = self.connection_id; "Received message: {:#?}", msg);
     let msg: ws::Message = match msg {
-      Err(e) => return Self::send_error(WebsocketError::ProtocolError(e), ctx),
+      Err(ws::ProtocolError::Overflow) => {
+        let error = ServiceError::MessageTooLarge {
+          max_bytes: self.max_message_bytes,
+          received_bytes: None,
+        };
+        return self.fatal_error(error, CloseCode::Size, ctx);
+      },
+      Err(e) => return self.send_error(WebsocketError::ProtocolError(e), ctx),
       Ok(msg) => msg,
     };
 
-    // Parse as a JSON string
+    // Drop messages from clients that send too quickly (only the first dropped message gets an error)
+    if !matches!(msg, ws::Message::Pong(_) | ws::Message::Close(_)) {
+      match self.rate_limiter.try_acquire() {
+        Ok(()) => {},
+        Err(1) => {
+          let retry_after_ms = self.rate_limiter.get_retry_after_ms();
+          return self.send_error(ServiceError::RateLimited { retry_after_ms }, ctx);
+        },
+        Err(_) => return,
+      }
+    }
+
+    // Pongs only answer our own heartbeat, so they don't show the player is still there
+    if matches!(
+      msg,
+      ws::Message::Text(_) | ws::Message::Binary(_) | ws::Message::Ping(_)
+    ) {
+      self.last_activity = Instant::now();
+    }
+
+    // Parse the message from the client
     let json = match msg {
       // Basic messages
       ws::Message::Nop => return,
-      ws::Message::Ping(msg) => return ctx.pong(&msg),
-      ws::Message::Pong(_) => return,
+      ws::Message::Ping(msg) => {
+        self.last_heartbeat = Instant::now();
+        return ctx.pong(&msg);
+      },
+      ws::Message::Pong(_) => {
+        self.last_heartbeat = Instant::now();
+        return;
+      },
       ws::Message::Close(reason) => {
-        log::info!("Received close message, closing... ({:#?})", reason);
+        log::info!(player_id:% = self.player_id, connection_id:% = self.connection_id; "Received close message, closing... ({:#?})", reason);
         ctx.close(reason);
         return ctx.stop();
       },
 
+      // Reject oversized messages before trying to parse them
+      ws::Message::Text(ref text) if text.len() > self.max_message_bytes => {
+        let error = ServiceError::MessageTooLarge {
+          max_bytes: self.max_message_bytes,
+          received_bytes: Some(text.len()),
+        };
+        return self.send_error(error, ctx);
+      },
+      ws::Message::Binary(ref bytes) if bytes.len() > self.max_message_bytes => {
+        let error = ServiceError::MessageTooLarge {
+          max_bytes: self.max_message_bytes,
+          received_bytes: Some(bytes.len()),
+        };
+        return self.send_error(error, ctx);
+      },
+
       // Parse JSON message
       ws::Message::Text(text) => match serde_json::from_str::<WebsocketMessage>(&text) {
-        Err(e) => return Self::send_error(WebsocketError::JSONError(e), ctx),
+        Err(e) => {
+          let error = ServiceError::InvalidMessage {
+            message_type: MessageType::from_json(&text),
+            why: e.to_string(),
+          };
+          return self.send_error(error, ctx);
+        },
         Ok(json) => json,
       },
 
+      // Parse MessagePack message
+      ws::Message::Binary(bytes) if self.encoding == Encoding::MessagePack => {
+        match protocol::from_msgpack::<WebsocketMessage>(&bytes) {
+          Err(e) => {
+            let error = ServiceError::InvalidMessage {
+              message_type: MessageType::from_msgpack(&bytes),
+              why: e.to_string(),
+            };
+            return self.send_error(error, ctx);
+          },
+          Ok(message) => message,
+        }
+      },
+
       // Unsupported messages
       ws::Message::Binary(_) => {
-        return Self::send_error(WebsocketError::UnsupportedFrameType("Binary".into()), ctx);
+        return self.send_error(WebsocketError::UnsupportedFrameType("Binary".into()), ctx);
       },
       ws::Message::Continuation(_) => {
-        return Self::send_error(WebsocketError::UnsupportedFrameType("Continuation".into()), ctx);
+        return self.send_error(WebsocketError::UnsupportedFrameType("Continuation".into()), ctx);
       },
     };
 
     match json {
       WebsocketMessage::Register => self.register(ctx),
       WebsocketMessage::Unregister => self.unregister(ctx),
-      WebsocketMessage::GetServerState => self.send_server_state(ctx),
-      WebsocketMessage::GetRegisteredPlayers => self.send_registered_players(ctx),
+      WebsocketMessage::GetServerState(query) => self.send_server_state(query, ctx),
+      WebsocketMessage::GetRegisteredPlayers(query) => self.send_registered_players(query, ctx),
+      WebsocketMessage::GetTimeRemaining(query) => self.send_time_remaining(query, ctx),
+      WebsocketMessage::GetGameConfig(query) => self.send_data(&QueryResponse::game_config().reply_to(query), ctx),
+      WebsocketMessage::GetMyState(query) => self.send_my_state(query, ctx),
+      WebsocketMessage::GetMyActions(query) => self.send_data(
+        &QueryResponse::MyActions {
+          actions: self.sent_actions.iter().cloned().collect(),
+        }
+        .reply_to(query),
+        ctx,
+      ),
       WebsocketMessage::Move(action) => self.do_action(action.transpose(), ctx),
       WebsocketMessage::Attack(action) => self.do_action(action.transpose(), ctx),
       WebsocketMessage::DropWeapon(action) => self.do_action(action.transpose(), ctx),
@@ -142,7 +328,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebsocketActor {
   }
 
   fn finished(&mut self, ctx: &mut Self::Context) {
-    log::debug!("Websocket stream closed, stopping actor");
+    log::debug!(player_id:% = self.player_id, connection_id:% = self.connection_id; "Websocket stream closed, stopping actor");
     ctx.stop()
   }
 }
@@ -152,10 +338,15 @@ impl Handler<ConnectResponse> for WebsocketActor {
 
   fn handle(&mut self, response: ConnectResponse, ctx: &mut Self::Context) -> Self::Result {
     match response {
-      ConnectResponse::Ok(state) => {
+      ConnectResponse::Ok(state, _) => {
+        log::info!(
+          player_id:% = self.player_id, connection_id:% = self.connection_id, event = "player_connected";
+          "Player \"{}\" connected", self.player_data.get_name()
+        );
+        self.connected = true;
         self.server_state = state;
         if self.server_state == ServerState::FatalError {
-          Self::fatal_error(ServiceError::GameEngineCrash, CloseCode::Error, ctx);
+          self.fatal_error(ServiceError::GameEngineCrash, CloseCode::Error, ctx);
         }
       },
       _ => ctx.close(Some(CloseCode::Abnormal.into())),
@@ -163,11 +354,46 @@ impl Handler<ConnectResponse> for WebsocketActor {
   }
 }
 
+impl Handler<SendReconnectToken> for WebsocketActor {
+  type Result = ();
+
+  fn handle(&mut self, SendReconnectToken(token): SendReconnectToken, ctx: &mut Self::Context) -> Self::Result {
+    self.send_data(&QueryResponse::ReconnectToken { token }, ctx);
+  }
+}
+
+impl Handler<ActionRejected> for WebsocketActor {
+  type Result = ();
+
+  fn handle(&mut self, ActionRejected(tag): ActionRejected, ctx: &mut Self::Context) -> Self::Result {
+    // The rejected action doesn't count, so the player can send another one this tick
+    self.action_sent = false;
+
+    let why = match tag {
+      Some(tag) => format!("action '{}' was rejected by the game engine", tag),
+      None => "action was rejected by the game engine".into(),
+    };
+    self.send_error(ServiceError::CannotSendAction { why }, ctx);
+  }
+}
+
 impl Handler<GameEngineCrash> for WebsocketActor {
   type Result = ();
 
-  fn handle(&mut self, _: GameEngineCrash, ctx: &mut Self::Context) -> Self::Result {
-    Self::fatal_error(ServiceError::GameEngineCrash, CloseCode::Error, ctx);
+  fn handle(&mut self, crash: GameEngineCrash, ctx: &mut Self::Context) -> Self::Result {
+    if crash.timed_out {
+      self.fatal_error(ServiceError::GameEngineTimeout, CloseCode::Again, ctx);
+    } else {
+      self.fatal_error(ServiceError::GameEngineCrash, CloseCode::Error, ctx);
+    }
+  }
+}
+
+impl Handler<GameCancelled> for WebsocketActor {
+  type Result = ();
+
+  fn handle(&mut self, GameCancelled { num_players }: GameCancelled, ctx: &mut Self::Context) -> Self::Result {
+    self.send_error(ServiceError::NotEnoughPlayers { num_players }, ctx);
   }
 }
 
@@ -175,7 +401,7 @@ impl Handler<RegistrationUpdate> for WebsocketActor {
   type Result = ();
 
   fn handle(&mut self, update: RegistrationUpdate, ctx: &mut Self::Context) -> Self::Result {
-    ctx.text(update.into_bytestring());
+    self.send_message(update, ctx);
   }
 }
 
@@ -183,7 +409,47 @@ impl Handler<KickUnregisteredPlayer> for WebsocketActor {
   type Result = ();
 
   fn handle(&mut self, _: KickUnregisteredPlayer, ctx: &mut Self::Context) -> Self::Result {
-    Self::fatal_error(ServiceError::NotRegistered(self.player_id), CloseCode::Error, ctx);
+    self.fatal_error(ServiceError::NotRegistered(self.player_id), CloseCode::Error, ctx);
+  }
+}
+
+impl Handler<ConnectionTooSlow> for WebsocketActor {
+  type Result = ();
+
+  fn handle(&mut self, _: ConnectionTooSlow, ctx: &mut Self::Context) -> Self::Result {
+    log::info!(player_id:% = self.player_id, connection_id:% = self.connection_id; "Fell too far behind on messages, closing...");
+    ctx.close(Some(CloseReason::from((
+      CloseCode::Again,
+      "Too far behind on messages",
+    ))));
+    ctx.stop();
+  }
+}
+
+impl Handler<ReplaceConnection> for WebsocketActor {
+  type Result = ();
+
+  fn handle(&mut self, ReplaceConnection(new_addr): ReplaceConnection, ctx: &mut Self::Context) -> Self::Result {
+    log::info!(player_id:% = self.player_id, connection_id:% = self.connection_id; "Replaced by a new connection, closing...");
+    new_addr.do_send(TransferConnectionState {
+      action_sent: self.action_sent,
+      player_killed: self.player_killed,
+    });
+
+    ctx.close(Some(CloseReason::from((
+      CloseCode::Policy,
+      "Replaced by a new connection",
+    ))));
+    ctx.stop();
+  }
+}
+
+impl Handler<TransferConnectionState> for WebsocketActor {
+  type Result = ();
+
+  fn handle(&mut self, state: TransferConnectionState, _: &mut Self::Context) -> Self::Result {
+    self.action_sent = state.action_sent;
+    self.player_killed = state.player_killed;
   }
 }
 
@@ -192,7 +458,8 @@ impl Handler<GameStarting> for WebsocketActor {
 
   fn handle(&mut self, starting: GameStarting, ctx: &mut Self::Context) -> Self::Result {
     self.server_state = ServerState::Initializing;
-    ctx.text(starting.into_bytestring())
+    self.match_id = Some(starting.get_match_id());
+    self.send_message(starting, ctx)
   }
 }
 
@@ -201,10 +468,15 @@ impl Handler<Init> for WebsocketActor {
 
   fn handle(&mut self, init: Init, ctx: &mut Self::Context) -> Self::Result {
     self.server_state = ServerState::Running;
+    self.match_id = Some(init.get_match_id());
+    self.current_tick = 0;
+    self.ticks_left = init.get_ticks_left();
+    self.seconds_per_tick = init.get_seconds_per_tick();
     self.action_sent = false;
+    self.sent_actions.clear();
     self.player_killed = false;
 
-    ctx.text(init.into_bytestring())
+    self.send_message(init, ctx)
   }
 }
 
@@ -212,8 +484,12 @@ impl Handler<NextState> for WebsocketActor {
   type Result = ();
 
   fn handle(&mut self, state: NextState, ctx: &mut Self::Context) -> Self::Result {
+    self.match_id = Some(state.get_match_id());
+    self.current_tick = state.get_current_tick();
+    self.ticks_left = state.get_ticks_left();
+    self.seconds_per_tick = state.get_seconds_per_tick();
     self.action_sent = false;
-    ctx.text(state.into_bytestring())
+    self.send_message(state, ctx)
   }
 }
 
@@ -224,7 +500,7 @@ impl Handler<PlayerKilled> for WebsocketActor {
     if player_killed.get_player_id() == self.player_id {
       self.player_killed = true;
     }
-    ctx.text(player_killed.into_bytestring())
+    self.send_message(player_killed, ctx)
   }
 }
 
@@ -233,7 +509,23 @@ impl Handler<GameEnded> for WebsocketActor {
 
   fn handle(&mut self, game_ended: GameEnded, ctx: &mut Self::Context) -> Self::Result {
     self.server_state = ServerState::Registration;
-    ctx.text(game_ended.into_bytestring())
+    self.match_id = None;
+    self.current_tick = 0;
+    self.ticks_left = 0;
+    self.last_activity = Instant::now();
+    self.send_message(game_ended, ctx)
+  }
+}
+
+impl Handler<ServerShutdown> for WebsocketActor {
+  type Result = ();
+
+  fn handle(&mut self, shutdown: ServerShutdown, ctx: &mut Self::Context) -> Self::Result {
+    log::info!(player_id:% = self.player_id, connection_id:% = self.connection_id; "Server shutting down, closing websocket...");
+    let close_reason = CloseReason::from((CloseCode::Restart, shutdown.get_reason()));
+    self.send_message(shutdown, ctx);
+    ctx.close(Some(close_reason));
+    ctx.stop();
   }
 }
 
@@ -247,18 +539,18 @@ impl WebsocketActor {
       }))
       .map(|result, this, ctx| match result {
         Ok(RegisterResponse::Success) => {},
-        Ok(RegisterResponse::GameAlreadyStarted) => Self::send_error(
+        Ok(RegisterResponse::GameAlreadyStarted) => this.send_error(
           ServiceError::FailedToRegister(this.player_id, "game already started".into()),
           ctx,
         ),
-        Ok(RegisterResponse::TooManyRegistered { max_allowed }) => Self::send_error(
+        Ok(RegisterResponse::TooManyRegistered { max_allowed }) => this.send_error(
           ServiceError::FailedToRegister(
             this.player_id,
             format!("too many players registered ({} maximum allowed)", max_allowed),
           ),
           ctx,
         ),
-        Err(e) => Self::send_error(ServiceError::WebsocketMailboxError(e), ctx),
+        Err(e) => this.send_error(ServiceError::WebsocketMailboxError(e), ctx),
       }),
     );
   }
@@ -269,41 +561,86 @@ impl WebsocketActor {
       wrap_future::<_, Self>(self.game_mediator.send(Unregister { id: self.player_id })).map(|result, this, ctx| {
         match result {
           Ok(true) => {},
-          Ok(false) => Self::send_error(ServiceError::FailedToUnregister(this.player_id), ctx),
-          Err(e) => Self::send_error(ServiceError::WebsocketMailboxError(e), ctx),
+          Ok(false) => this.send_error(ServiceError::FailedToUnregister(this.player_id), ctx),
+          Err(e) => this.send_error(ServiceError::WebsocketMailboxError(e), ctx),
         }
       }),
     );
   }
 
-  fn send_server_state(&self, ctx: &mut <Self as Actor>::Context) {
-    Self::send_json(
+  fn send_server_state(&self, query: QueryRequest, ctx: &mut <Self as Actor>::Context) {
+    self.send_data(
       &QueryResponse::ServerState {
         state: self.server_state,
-      },
+        match_id: self.match_id,
+      }
+      .reply_to(query),
+      ctx,
+    );
+  }
+
+  fn send_time_remaining(&self, query: QueryRequest, ctx: &mut <Self as Actor>::Context) {
+    self.send_data(
+      &QueryResponse::TimeRemaining {
+        ticks_left: self.ticks_left,
+        seconds_per_tick: self.seconds_per_tick,
+      }
+      .reply_to(query),
       ctx,
     );
   }
 
-  fn send_registered_players(&self, ctx: &mut <Self as Actor>::Context) {
+  fn send_registered_players(&self, query: QueryRequest, ctx: &mut <Self as Actor>::Context) {
     // Spawn a future to process the request
     ctx.spawn(
-      wrap_future::<_, Self>(self.game_mediator.send(GetRegisteredPlayers)).map(|result, _this, ctx| match result {
-        Ok(registered) => Self::send_json(
+      wrap_future::<_, Self>(self.game_mediator.send(GetRegisteredPlayers)).map(|result, this, ctx| match result {
+        Ok(registered) => this.send_data(
           &QueryResponse::RegisteredPlayers {
             players: registered.players,
             player_order: registered.player_order,
-          },
+          }
+          .reply_to(query),
           ctx,
         ),
-        Err(e) => Self::send_error(ServiceError::WebsocketMailboxError(e), ctx),
+        Err(e) => this.send_error(ServiceError::WebsocketMailboxError(e), ctx),
+      }),
+    );
+  }
+
+  fn send_my_state(&self, query: QueryRequest, ctx: &mut <Self as Actor>::Context) {
+    // Dead players are no longer part of the game
+    if self.player_killed {
+      return self.send_data(
+        &QueryResponse::MyState {
+          match_id: self.match_id,
+          player: None,
+        }
+        .reply_to(query),
+        ctx,
+      );
+    }
+
+    // Spawn a future to process the request
+    ctx.spawn(
+      wrap_future::<_, Self>(self.game_mediator.send(GetPlayerState(self.player_id))).map(|result, this, ctx| {
+        match result {
+          Ok(player) => this.send_data(
+            &QueryResponse::MyState {
+              match_id: this.match_id,
+              player,
+            }
+            .reply_to(query),
+            ctx,
+          ),
+          Err(e) => this.send_error(ServiceError::WebsocketMailboxError(e), ctx),
+        }
       }),
     );
   }
 
   fn do_action(&mut self, action: PlayerAction, ctx: &mut <Self as Actor>::Context) {
     if self.player_killed {
-      return Self::send_error(
+      return self.send_error(
         ServiceError::CannotSendAction {
           why: "player has been killed".into(),
         },
@@ -312,7 +649,7 @@ impl WebsocketActor {
     }
 
     if !self.server_state.can_send_action() {
-      return Self::send_error(
+      return self.send_error(
         ServiceError::CannotSendAction {
           why: "game has not started yet".into(),
         },
@@ -320,8 +657,9 @@ impl WebsocketActor {
       );
     }
 
-    if self.action_sent {
-      return Self::send_error(
+    // In replace mode, the game engine only keeps the last action sent by each player in the tick
+    if self.action_sent && !self.action_replace_mode {
+      return self.send_error(
         ServiceError::CannotSendAction {
           why: "already sent player action".into(),
         },
@@ -329,18 +667,32 @@ impl WebsocketActor {
       );
     }
 
-    match self.send_player_action.send((self.player_id, action)) {
+    let sent_action = SentAction::new(self.current_tick, action.clone());
+    match self.send_player_action.try_send((self.player_id, action)) {
       Ok(_) => {
         self.action_sent = true;
+        let tag = sent_action.get_action().tag.clone();
+
+        // Keep a bounded history so the client can check what the server received
+        if self.sent_actions.len() >= MAX_SENT_ACTIONS {
+          self.sent_actions.pop_front();
+        }
+        self.sent_actions.push_back(sent_action);
+
+        self.send_data(&QueryResponse::ActionAccepted { tag }, ctx);
       },
-      Err(_) => {
-        return Self::send_error(
-          ServiceError::CannotSendAction {
-            why: "channel error".into(),
-          },
-          ctx,
-        );
-      },
+      Err(TrySendError::Full(_)) => self.send_error(
+        ServiceError::CannotSendAction {
+          why: "game engine is busy, try again".into(),
+        },
+        ctx,
+      ),
+      Err(TrySendError::Closed(_)) => self.send_error(
+        ServiceError::CannotSendAction {
+          why: "channel error".into(),
+        },
+        ctx,
+      ),
     }
   }
 }
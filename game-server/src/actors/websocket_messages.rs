@@ -3,8 +3,35 @@
 //
 use actix::prelude::*;
 
+use crate::actors::WebsocketActor;
+
 /// Sent to the websocket actor to close the connection
 ///   This happens if the game starts and they are not registered
 #[derive(Debug, Clone, Message)]
 #[rtype(result = "()")]
 pub struct KickUnregisteredPlayer;
+
+/// Sent to the websocket actor when the same player opens a new connection
+///   The old actor hands over its state to the new actor, then closes
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+pub struct ReplaceConnection(pub Addr<WebsocketActor>);
+
+/// Sent to the websocket actor with a fresh reconnection token for the client
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+pub struct SendReconnectToken(pub String);
+
+/// Sent to the websocket actor when the game engine rejects the last action
+///   Includes the tag of the rejected action (if any)
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+pub struct ActionRejected(pub Option<String>);
+
+/// State carried over from the replaced websocket actor
+#[derive(Debug, Clone, Copy, Message)]
+#[rtype(result = "()")]
+pub struct TransferConnectionState {
+  pub action_sent: bool,
+  pub player_killed: bool,
+}
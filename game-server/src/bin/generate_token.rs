@@ -1,11 +1,17 @@
-use chrono::Duration;
+use chrono::{Duration, TimeZone, Utc};
 use chrono_english::{parse_duration, DateResult, Interval};
 use dotenv::dotenv;
+use jsonwebtoken::EncodingKey;
+use serde::{de::DeserializeOwned, Serialize};
 use std::env;
+use std::fs;
+use std::path::PathBuf;
 use structopt::StructOpt;
 use uuid::Uuid;
 
-use game_server::jwt::{JWTPlayerData, JWTSecret, PlayerToken, ViewerToken};
+use game_server::config;
+use game_server::jwt::audience::{Admin, Player, Viewer};
+use game_server::jwt::{AdminToken, Audience, JWTPlayerData, JWTSecret, JWTToken, PlayerToken, ViewerToken};
 
 /// Generate a JSON web token for the game server
 #[derive(StructOpt)]
@@ -13,12 +19,20 @@ enum Opt {
   /// Generate a player JWT
   Player {
     /// Player UUID (Picks a random one if omitted))
-    #[structopt(short, long)]
+    #[structopt(short, long, conflicts_with_all = &["count", "names-file"])]
     id: Option<Uuid>,
 
-    /// Player name or alias
-    #[structopt(short, long)]
-    name: String,
+    /// Player name or alias (numbered when generating more than one token)
+    #[structopt(short, long, required_unless = "names-file")]
+    name: Option<String>,
+
+    /// Generate this many player tokens at once, each with a random UUID
+    #[structopt(short, long, conflicts_with = "names-file")]
+    count: Option<usize>,
+
+    /// Generate a player token with a random UUID for every name in the file (one name per line)
+    #[structopt(long, parse(from_os_str), conflicts_with = "name")]
+    names_file: Option<PathBuf>,
 
     /// Duration for the JWT as an English string
     #[structopt(short, long, default_value = "1 year")]
@@ -27,6 +41,10 @@ enum Opt {
     /// JSON Web Token secret
     #[structopt(short = "s", long, env, hide_env_values = true)]
     jwt_secret: String,
+
+    /// Print the ID, token, and expiration time as a JSON object
+    #[structopt(long)]
+    json: bool,
   },
 
   /// Generate a viewer JWT
@@ -42,6 +60,43 @@ enum Opt {
     /// JSON Web Token secret
     #[structopt(short = "s", long, env, hide_env_values = true)]
     jwt_secret: String,
+
+    /// Print the ID, token, and expiration time as a JSON object
+    #[structopt(long)]
+    json: bool,
+  },
+
+  /// Generate an admin JWT
+  Admin {
+    /// Admin UUID (Picks a random one if omitted))
+    #[structopt(short, long)]
+    id: Option<Uuid>,
+
+    /// Duration for the JWT as an English string
+    #[structopt(short, long, default_value = "1 year")]
+    duration: String,
+
+    /// JSON Web Token secret
+    #[structopt(short = "s", long, env, hide_env_values = true)]
+    jwt_secret: String,
+
+    /// Print the ID, token, and expiration time as a JSON object
+    #[structopt(long)]
+    json: bool,
+  },
+
+  /// Validate a JWT and print the decoded claims
+  Verify {
+    /// JSON Web Token to validate
+    token: String,
+
+    /// Expected audience for the JWT
+    #[structopt(short, long, default_value = "player", possible_values = &["player", "viewer", "admin"])]
+    audience: String,
+
+    /// JSON Web Token secret
+    #[structopt(short = "s", long, env, hide_env_values = true)]
+    jwt_secret: String,
   },
 }
 
@@ -52,10 +107,12 @@ impl Opt {
     let id = match self {
       Self::Player { id, .. } => id,
       Self::Viewer { id, .. } => id,
+      Self::Admin { id, .. } => id,
+      Self::Verify { .. } => &None,
     };
 
     let new_id_generated = id.is_none();
-    (id.unwrap_or_else(|| Uuid::new_v4()), new_id_generated)
+    (id.unwrap_or_else(Uuid::new_v4), new_id_generated)
   }
 
   /// Get the read JSON Web Token secret
@@ -63,6 +120,41 @@ impl Opt {
     match self {
       Self::Player { jwt_secret, .. } => jwt_secret,
       Self::Viewer { jwt_secret, .. } => jwt_secret,
+      Self::Admin { jwt_secret, .. } => jwt_secret,
+      Self::Verify { jwt_secret, .. } => jwt_secret,
+    }
+  }
+
+  /// Get the list of player names when generating many tokens at once, or None for a single token
+  pub fn get_batch_names(&self) -> anyhow::Result<Option<Vec<String>>> {
+    match self {
+      Self::Player {
+        names_file: Some(names_file),
+        ..
+      } => {
+        let contents = fs::read_to_string(names_file)
+          .map_err(|e| anyhow::anyhow!("failed to read '{}': {}", names_file.display(), e))?;
+        let names = contents.lines().map(str::trim).filter(|name| !name.is_empty());
+        Ok(Some(names.map(String::from).collect()))
+      },
+
+      Self::Player {
+        name: Some(name),
+        count: Some(count),
+        ..
+      } => Ok(Some((1..=*count).map(|i| format!("{} {}", name, i)).collect())),
+
+      _ => Ok(None),
+    }
+  }
+
+  /// Print the output as JSON instead of plain text
+  pub fn use_json(&self) -> bool {
+    match self {
+      Self::Player { json, .. } => *json,
+      Self::Viewer { json, .. } => *json,
+      Self::Admin { json, .. } => *json,
+      Self::Verify { .. } => true,
     }
   }
 
@@ -76,6 +168,8 @@ impl Opt {
     let duration = match self {
       Self::Player { duration, .. } => parse_duration(duration),
       Self::Viewer { duration, .. } => parse_duration(duration),
+      Self::Admin { duration, .. } => parse_duration(duration),
+      Self::Verify { .. } => return Ok(Duration::zero()),
     }?;
 
     Ok(match duration {
@@ -86,6 +180,53 @@ impl Opt {
   }
 }
 
+/// JSON output for a single token
+#[derive(Serialize)]
+struct TokenOutput {
+  id: Uuid,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  name: Option<String>,
+  token: String,
+  expires_at: Option<String>, // RFC 3339 timestamp
+}
+
+impl TokenOutput {
+  /// Encode the token and remember the details needed for the output
+  pub fn new<A, T>(token: JWTToken<A, T>, name: Option<String>, key: &EncodingKey) -> anyhow::Result<Self>
+  where
+    A: Audience,
+    T: Serialize + DeserializeOwned,
+  {
+    Ok(Self {
+      id: token.get_id(),
+      name,
+      token: token
+        .encode(key)
+        .map_err(|e| anyhow::anyhow!("failed to encode JWT: {}", e))?,
+      expires_at: Utc
+        .timestamp_opt(token.get_expiration(), 0)
+        .single()
+        .map(|expires_at| expires_at.to_rfc3339()),
+    })
+  }
+}
+
+/// Decode and validate the token, then print the claims as JSON
+fn verify_token<A, T>(token: &str, jwt_secret: &JWTSecret) -> anyhow::Result<()>
+where
+  A: Audience,
+  T: Serialize + DeserializeOwned,
+{
+  let token = JWTToken::<A, T>::decode(token, &jwt_secret.get_decoding_key())
+    .map_err(|e| anyhow::anyhow!("invalid JWT: {}", e))?;
+  token
+    .validate_lifetime(config::get_max_token_lifetime_minutes())
+    .map_err(|e| anyhow::anyhow!("invalid JWT: {}", e))?;
+
+  println!("{}", serde_json::to_string_pretty(&token)?);
+  Ok(())
+}
+
 //
 // Main program entry point
 //
@@ -97,36 +238,68 @@ fn main() -> anyhow::Result<()> {
   let opt: Opt = Opt::from_args();
   opt.update_environment();
 
+  // Validate an existing token instead of generating a new one
+  if let Opt::Verify { token, audience, .. } = &opt {
+    let jwt_secret = JWTSecret::new(opt.get_jwt_secret());
+    return match audience.as_str() {
+      "viewer" => verify_token::<Viewer, ()>(token, &jwt_secret),
+      "admin" => verify_token::<Admin, ()>(token, &jwt_secret),
+      _ => verify_token::<Player, JWTPlayerData>(token, &jwt_secret),
+    };
+  }
+
   // Parse the duration
   let duration = opt
     .parse_duration()
-    .or_else(|e| Err(anyhow::anyhow!("invalid duration: {}", e)))?;
+    .map_err(|e| anyhow::anyhow!("invalid duration: {}", e))?;
 
   let jwt_encoding_key = JWTSecret::new(opt.get_jwt_secret()).get_encoding_key();
+
+  // Generate many player tokens at once, printed as one JSON object per line
+  if let Some(names) = opt.get_batch_names()? {
+    for name in names {
+      let token = PlayerToken::new(Uuid::new_v4(), duration, JWTPlayerData::new(name.clone()));
+      let output = TokenOutput::new(token, Some(name), &jwt_encoding_key)?;
+      println!("{}", serde_json::to_string(&output)?);
+    }
+
+    return Ok(());
+  }
+
   let (id, new_id_generated) = opt.get_id();
+  let use_json = opt.use_json();
 
   // Generate and encode the token
-  let token = match opt {
+  let output = match opt {
     Opt::Player { name, .. } => {
+      let name = name.expect("name is required for a single player token");
       let token = PlayerToken::new(id, duration, JWTPlayerData::new(name));
-      token
-        .encode(&jwt_encoding_key)
-        .or_else(|e| Err(anyhow::anyhow!("failed to encode JWT: {}", e)))?
+      TokenOutput::new(token, None, &jwt_encoding_key)?
     },
 
     Opt::Viewer { .. } => {
       let token = ViewerToken::new(id, duration, ());
-      token
-        .encode(&jwt_encoding_key)
-        .or_else(|e| Err(anyhow::anyhow!("failed to encode JWT: {}", e)))?
+      TokenOutput::new(token, None, &jwt_encoding_key)?
+    },
+
+    Opt::Admin { .. } => {
+      let token = AdminToken::new(id, duration, ());
+      TokenOutput::new(token, None, &jwt_encoding_key)?
     },
+
+    Opt::Verify { .. } => unreachable!("tokens are verified before generating"),
   };
 
+  if use_json {
+    println!("{}", serde_json::to_string(&output)?);
+    return Ok(());
+  }
+
   // Print the token UUID to standard error if a random one was generated
   if new_id_generated {
     eprintln!("Token UUID: {}", id);
   }
-  println!("{}", token);
+  println!("{}", output.token);
 
   Ok(())
 }
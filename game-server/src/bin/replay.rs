@@ -0,0 +1,233 @@
+use actix::prelude::*;
+use actix_web::{web, App, Error, HttpRequest, HttpResponse, HttpServer};
+use actix_web_actors::ws::{self, CloseCode, CloseReason, WsResponseBuilder};
+use dotenv::dotenv;
+use log::LevelFilter;
+use serde::Deserialize;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use structopt::StructOpt;
+
+use game_server::logging::{self, LogFormat};
+use game_server::protocol::MessageType;
+
+/// Play back a match replay recorded by the game server (see RECORD_REPLAYS)
+#[derive(StructOpt)]
+struct Opt {
+  /// Replay file to play back ("<match_id>.jsonl")
+  #[structopt(parse(from_os_str))]
+  replay_file: PathBuf,
+
+  /// Playback speed multiplier (2 plays the match twice as fast)
+  #[structopt(long, default_value = "1")]
+  speed: f64,
+
+  /// Send every message right away instead of waiting between ticks
+  #[structopt(long)]
+  no_sleep: bool,
+
+  /// Serve the replay to websocket viewers on this port instead of printing it to stdout
+  #[structopt(short, long)]
+  port: Option<u16>,
+
+  /// Host to run the websocket server
+  #[structopt(long, default_value = "127.0.0.1")]
+  host: String,
+
+  /// Protocol name expected in the "Sec-WebSocket-Protocol" header
+  #[structopt(long, env, default_value = "game-server")]
+  ws_protocol: String,
+
+  /// Format for the log output ("pretty" or "json")
+  #[structopt(long, env, default_value = "pretty", possible_values = &["pretty", "json"])]
+  log_format: LogFormat,
+}
+
+/// Fields needed from each recorded message to work out the original cadence
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ReplayLine {
+  #[serde(flatten)]
+  message_type: MessageType,
+  seconds_per_tick: Option<u32>,
+}
+
+/// Single message from the replay file, with the time to wait before sending it
+#[derive(Debug, Clone)]
+struct ReplayMessage {
+  text: String,
+  delay: Duration,
+}
+
+//
+// Main program entry point
+//
+#[actix_web::main]
+async fn main() -> anyhow::Result<()> {
+  // Load our ".env" configuration file
+  dotenv().ok();
+
+  // Parse command-line arguments
+  let opt: Opt = Opt::from_args();
+
+  // Keep stdout clean for the replay messages when not serving them over a websocket
+  let log_level = if opt.port.is_some() {
+    LevelFilter::Info
+  } else {
+    LevelFilter::Warn
+  };
+  logging::init(opt.log_format, log_level)?;
+
+  if !(opt.speed > 0.0 && opt.speed.is_finite()) {
+    return Err(anyhow::anyhow!("--speed must be greater than 0 (got {})", opt.speed));
+  }
+  let speed = if opt.no_sleep { None } else { Some(opt.speed) };
+  let messages = Arc::new(read_replay(&opt.replay_file, speed)?);
+  log::info!(
+    "Loaded {} message(s) from '{}'",
+    messages.len(),
+    opt.replay_file.display()
+  );
+
+  match opt.port {
+    Some(port) => serve_replay(messages, &opt.host, port, opt.ws_protocol).await,
+    None => web::block(move || print_replay(&messages)).await?,
+  }
+}
+
+/// Read every message from the replay file
+///   Messages that advance the game by a tick wait "secondsPerTick" divided by the speed, or not at all without a speed
+fn read_replay(replay_file: &Path, speed: Option<f64>) -> anyhow::Result<Vec<ReplayMessage>> {
+  let contents = fs::read_to_string(replay_file)
+    .map_err(|e| anyhow::anyhow!("Failed to read '{}': {}", replay_file.display(), e))?;
+
+  let mut seconds_per_tick = 0;
+  let mut messages = Vec::new();
+  for (i, line) in contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+    let parsed: ReplayLine = serde_json::from_str(line).map_err(|e| {
+      anyhow::anyhow!(
+        "Invalid message on line {} of '{}': {}",
+        i + 1,
+        replay_file.display(),
+        e
+      )
+    })?;
+    seconds_per_tick = parsed.seconds_per_tick.unwrap_or(seconds_per_tick);
+
+    let is_tick = matches!(parsed.message_type.message_type.as_str(), "nextState" | "gameEnded");
+    let delay = match speed {
+      Some(speed) if is_tick => Duration::from_secs_f64(f64::from(seconds_per_tick) / speed),
+      _ => Duration::ZERO,
+    };
+
+    messages.push(ReplayMessage {
+      text: line.into(),
+      delay,
+    });
+  }
+
+  Ok(messages)
+}
+
+/// Print the messages to stdout, one per line
+fn print_replay(messages: &[ReplayMessage]) -> anyhow::Result<()> {
+  let mut stdout = io::stdout().lock();
+  for message in messages.iter() {
+    thread::sleep(message.delay);
+    writeln!(stdout, "{}", message.text)?;
+    stdout.flush()?;
+  }
+
+  Ok(())
+}
+
+/// Run a websocket server that plays the whole replay to every viewer that connects
+async fn serve_replay(
+  messages: Arc<Vec<ReplayMessage>>,
+  host: &str,
+  port: u16,
+  ws_protocol: String,
+) -> anyhow::Result<()> {
+  log::info!("Serving the replay on ws://{}:{}/api/v1/view", host, port);
+
+  HttpServer::new(move || {
+    App::new()
+      .app_data(web::Data::new(messages.clone()))
+      .app_data(web::Data::new(ws_protocol.clone()))
+      .route("/api/v1/view", web::get().to(connect_viewer))
+  })
+  .bind((host, port))?
+  .run()
+  .await?;
+
+  Ok(())
+}
+
+/// Start playing the replay to a new viewer, without checking the viewer token
+async fn connect_viewer(
+  req: HttpRequest,
+  stream: web::Payload,
+  messages: web::Data<Arc<Vec<ReplayMessage>>>,
+  ws_protocol: web::Data<String>,
+) -> Result<HttpResponse, Error> {
+  log::info!("Viewer connected, starting the replay");
+  WsResponseBuilder::new(ReplayActor::new(messages.get_ref().clone()), &req, stream)
+    .protocols(&[ws_protocol.as_str()])
+    .start()
+}
+
+/// Actor that sends the replay messages to a single websocket viewer
+struct ReplayActor {
+  messages: Arc<Vec<ReplayMessage>>,
+  next: usize,
+}
+
+impl ReplayActor {
+  fn new(messages: Arc<Vec<ReplayMessage>>) -> Self {
+    Self { messages, next: 0 }
+  }
+
+  /// Wait for the next message, then send it and schedule the one after
+  fn schedule_next(&mut self, ctx: &mut <Self as Actor>::Context) {
+    let delay = match self.messages.get(self.next) {
+      Some(message) => message.delay,
+      None => {
+        log::info!("Replay finished, closing websocket...");
+        ctx.close(Some(CloseReason::from((CloseCode::Normal, "Replay finished"))));
+        return ctx.stop();
+      },
+    };
+
+    ctx.run_later(delay, |act, ctx| {
+      ctx.text(act.messages[act.next].text.as_str());
+      act.next += 1;
+      act.schedule_next(ctx);
+    });
+  }
+}
+
+impl Actor for ReplayActor {
+  type Context = ws::WebsocketContext<Self>;
+
+  fn started(&mut self, ctx: &mut Self::Context) {
+    self.schedule_next(ctx);
+  }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ReplayActor {
+  fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+    match msg {
+      Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
+      Ok(ws::Message::Close(reason)) => {
+        ctx.close(reason);
+        ctx.stop();
+      },
+      Err(_) => ctx.stop(),
+      _ => {}, // Viewers can't control the replay
+    }
+  }
+}
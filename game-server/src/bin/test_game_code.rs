@@ -1,22 +1,26 @@
 use dotenv::dotenv;
 use log::LevelFilter;
-use rand::seq::SliceRandom;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use rlua::prelude::*;
-use simple_logger::SimpleLogger;
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::env;
-use std::fs;
-use std::path::Path;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
 use uuid::Uuid;
 
 use game_server::config::{self, DEFAULT_LUA_FILE};
 use game_server::errors::GameEngineError;
-use game_server::protocol::{actions::*, game::GameState, PlayerAction, TaggedRequest};
-
-const MAX_TRIES: usize = 5;
+use game_server::game::{self, ActionWeights, MIN_PLAYERS_PER_GAME};
+use game_server::jwt::JWTPlayerData;
+use game_server::logging::{self, LogFormat};
+use game_server::protocol::{game::GameState, PlayerAction};
 
 /// Simple test of the game engine code to detect runtime bugs
 #[derive(StructOpt)]
@@ -25,14 +29,93 @@ struct Opt {
   #[structopt(long, env, default_value = DEFAULT_LUA_FILE)]
   lua_file: String,
 
+  /// Extra directories to search for Lua modules (separated by ";" or ":")
+  #[structopt(long, env)]
+  lua_paths: Option<String>,
+
+  /// Remove the "os" and "io" libraries (and native module loading) from the Lua environment
+  #[structopt(long, env, default_value = "true", parse(try_from_str))]
+  lua_sandbox: bool,
+
+  /// Reject unknown fields in the game state returned by the Lua code (catches typos like "playfeild")
+  #[structopt(long, env, takes_value(false))]
+  strict_game_state: bool,
+
+  /// Number of times to try running a Lua method before giving up
+  #[structopt(long, env, default_value = "5")]
+  max_engine_tries: usize,
+
   /// Number of total "ticks" for a complete round in the game
   #[structopt(long, env, default_value = "180")]
   ticks_per_game: u32,
 
+  /// Number of columns in the arena playfield
+  #[structopt(long, env, default_value = "10")]
+  arena_width: u32,
+
+  /// Number of rows in the arena playfield
+  #[structopt(long, env, default_value = "10")]
+  arena_height: u32,
+
   /// Number of players in the game
   #[structopt(long, default_value = "4")]
   num_players: usize,
 
+  /// Seed for the random number generators, to reproduce a previous run (random if not given)
+  #[structopt(long)]
+  seed: Option<u64>,
+
+  /// Abort if a game state breaks any of the given rules (all rules if no values are given)
+  #[structopt(
+    long,
+    min_values = 0,
+    possible_values = &["unique-positions", "in-bounds", "killed-removed"],
+  )]
+  check_invariants: Option<Vec<Invariant>>,
+
+  /// Write every game state (with the tick number and actions applied) to this file as JSON lines
+  #[structopt(short, long, parse(from_os_str))]
+  output: Option<PathBuf>,
+
+  /// Start from a snapshot saved with "--save-snapshot-at" instead of calling Init()
+  ///   Requires the Lua code to define a LoadState() function
+  #[structopt(long, parse(from_os_str), conflicts_with = "benchmark")]
+  load_snapshot: Option<PathBuf>,
+
+  /// Save a snapshot of the game to "--snapshot-file" after this tick (0 is the initial state from Init())
+  #[structopt(long, conflicts_with = "benchmark")]
+  save_snapshot_at: Option<u32>,
+
+  /// File to write the snapshot for "--save-snapshot-at"
+  #[structopt(long, parse(from_os_str), default_value = "snapshot.json")]
+  snapshot_file: PathBuf,
+
+  /// Relative weight for the random players to pick a "move" action
+  #[structopt(long, default_value = "5")]
+  weight_move: u32,
+
+  /// Relative weight for the random players to pick an "attack" action
+  #[structopt(long, default_value = "5")]
+  weight_attack: u32,
+
+  /// Relative weight for the random players to pick a "drop weapon" action
+  #[structopt(long, default_value = "2")]
+  weight_drop: u32,
+
+  /// Format for the log output ("pretty" or "json")
+  #[structopt(long, env, default_value = "pretty", possible_values = &["pretty", "json"])]
+  log_format: LogFormat,
+
+  /// Minimum level of log messages to show ("off", "error", "warn", "info", "debug", or "trace")
+  ///   Defaults to "debug" with "--show-debug" and "info" otherwise
+  #[structopt(long, env)]
+  log_level: Option<String>,
+
+  /// Measure how long the Lua Update() method takes instead of testing the game
+  ///   Runs "ticks-per-game" ticks as fast as possible, starting new games as needed
+  #[structopt(long)]
+  benchmark: bool,
+
   /// If set, also shows the debug output
   #[structopt(short = "d", long)]
   show_debug: bool,
@@ -42,16 +125,64 @@ impl Opt {
   /// Update the environment variables with the command-line options
   pub fn update_environment(&self) {
     env::set_var("LUA_FILE", &self.lua_file);
+    if let Some(ref lua_paths) = self.lua_paths {
+      env::set_var("LUA_PATHS", lua_paths);
+    }
+    env::set_var("LUA_SANDBOX", self.lua_sandbox.to_string());
+    if self.strict_game_state {
+      env::set_var("STRICT_GAME_STATE", "true");
+    }
+    env::set_var("MAX_ENGINE_TRIES", self.max_engine_tries.to_string());
     env::set_var("TICKS_PER_GAME", self.ticks_per_game.to_string());
+    env::set_var("ARENA_WIDTH", self.arena_width.to_string());
+    env::set_var("ARENA_HEIGHT", self.arena_height.to_string());
+    env::set_var("LOG_FORMAT", self.log_format.to_string());
+    if let Some(ref log_level) = self.log_level {
+      env::set_var("LOG_LEVEL", log_level);
+    }
   }
 
   pub fn should_show_debug(&self) -> bool {
     self.show_debug
   }
 
+  pub fn is_benchmark(&self) -> bool {
+    self.benchmark
+  }
+
   pub fn get_num_players(&self) -> usize {
     self.num_players
   }
+
+  pub fn get_seed(&self) -> Option<u64> {
+    self.seed
+  }
+
+  pub fn get_output(&self) -> Option<&PathBuf> {
+    self.output.as_ref()
+  }
+
+  pub fn get_load_snapshot(&self) -> Option<&PathBuf> {
+    self.load_snapshot.as_ref()
+  }
+
+  /// Get the tick and file to save a snapshot, if any
+  pub fn get_save_snapshot(&self) -> Option<(u32, &PathBuf)> {
+    self.save_snapshot_at.map(|tick| (tick, &self.snapshot_file))
+  }
+
+  pub fn get_action_weights(&self) -> ActionWeights {
+    ActionWeights::new(self.weight_move, self.weight_attack, self.weight_drop)
+  }
+
+  /// Get the list of invariants to check, if any
+  pub fn get_invariants(&self) -> Vec<Invariant> {
+    match self.check_invariants {
+      Some(ref invariants) if invariants.is_empty() => Invariant::ALL.to_vec(),
+      Some(ref invariants) => invariants.clone(),
+      None => vec![],
+    }
+  }
 }
 
 //
@@ -66,17 +197,71 @@ fn main() -> anyhow::Result<()> {
   opt.update_environment();
 
   // Configure the logger system
-  SimpleLogger::new().init()?;
+  let default_log_level = if opt.should_show_debug() {
+    LevelFilter::Debug
+  } else {
+    LevelFilter::Info
+  };
+  logging::init(config::get_log_format(), default_log_level)?;
+
+  // Set after the logger is installed, so an invalid LOG_LEVEL can still be reported
+  log::set_max_level(config::get_log_level(default_log_level));
   if opt.should_show_debug() {
     log::info!("Turning on debug output");
-    log::set_max_level(LevelFilter::Debug);
-  } else {
-    log::set_max_level(LevelFilter::Info);
   }
 
+  // The random players need at least one action to pick from
+  let action_weights = opt.get_action_weights();
+  if action_weights.is_empty() {
+    return Err(anyhow::anyhow!(
+      "at least one of --weight-move, --weight-attack, or --weight-drop must be non-zero"
+    ));
+  }
+
+  // A round with one player would be won before the first tick
+  if opt.get_num_players() < MIN_PLAYERS_PER_GAME {
+    return Err(anyhow::anyhow!(
+      "--num-players must be at least {} (got {})",
+      MIN_PLAYERS_PER_GAME,
+      opt.get_num_players()
+    ));
+  }
+
+  // Print the seed so a failing run can be reproduced
+  let seed = opt.get_seed().unwrap_or_else(|| {
+    let seed = rand::random();
+    log::info!("Using random seed {} (run with --seed {} to reproduce)", seed, seed);
+    seed
+  });
+
   // Load and run the game
-  let mut game_player = TestGamePlayer::new(config::get_lua_file(), opt.get_num_players())?;
-  game_player.run_game()?;
+  let mut game_player = TestGamePlayer::new(config::get_lua_file(), opt.get_num_players(), seed)?;
+  if let Some(output) = opt.get_output() {
+    let file = File::create(output).map_err(|e| anyhow::anyhow!("Failed to create '{}': {}", output.display(), e))?;
+    log::info!("Writing game states to '{}'", output.display());
+    game_player.set_output_file(file);
+  }
+  game_player.set_invariants(opt.get_invariants());
+  game_player.set_action_weights(action_weights);
+
+  if let Some(snapshot_file) = opt.get_load_snapshot() {
+    let snapshot = fs::read_to_string(snapshot_file)
+      .map_err(|e| anyhow::anyhow!("Failed to read '{}': {}", snapshot_file.display(), e))
+      .and_then(|json| {
+        serde_json::from_str(&json)
+          .map_err(|e| anyhow::anyhow!("Invalid snapshot '{}': {}", snapshot_file.display(), e))
+      })?;
+    game_player.set_snapshot(snapshot);
+  }
+  if let Some((tick, snapshot_file)) = opt.get_save_snapshot() {
+    game_player.save_snapshot_at(tick, snapshot_file.clone());
+  }
+
+  if opt.is_benchmark() {
+    game_player.run_benchmark()?;
+  } else {
+    game_player.run_game()?;
+  }
 
   Ok(())
 }
@@ -84,43 +269,182 @@ fn main() -> anyhow::Result<()> {
 /// Encapsulates the logic of running the Lua game engine on a given thread
 pub struct TestGamePlayer {
   lua: Lua,
+  rng: StdRng,
+  output_file: Option<File>,
+  snapshot: Option<Snapshot>,            // Only set if the game should start from a snapshot
+  save_snapshot: Option<(u32, PathBuf)>, // Tick and file to save the snapshot
+  invariants: Vec<Invariant>,
+  action_weights: ActionWeights,
   num_players: usize,
+  max_tries: usize,
+  strict_game_state: bool,
   player_order: Arc<Vec<Uuid>>,
+  player_names: Arc<HashMap<Uuid, String>>,
   players_remaining: Arc<Mutex<HashSet<Uuid>>>,
+  forced_winners: Arc<Mutex<Option<HashSet<Uuid>>>>, // Only set if Lua ends the game early
   ticks_per_game: u32,
+  seconds_per_tick: u32,
+  current_tick: u32,
+  ticks_left: Arc<Mutex<u32>>, // Lua can change the time remaining
+  arena_width: u32,
+  arena_height: u32,
+}
+
+/// Rules that every game state should follow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Invariant {
+  /// No two players share the same tile
+  UniquePositions,
+
+  /// Every player is inside the playfield
+  InBounds,
+
+  /// Killed players (or players with no health left) are removed from the game state
+  KilledRemoved,
+}
+
+impl Invariant {
+  const ALL: [Invariant; 3] = [
+    Invariant::UniquePositions,
+    Invariant::InBounds,
+    Invariant::KilledRemoved,
+  ];
+
+  /// Returns a descriptive message if the game state breaks the rule
+  fn check(&self, game_state: &GameState, players_remaining: &HashSet<Uuid>) -> Result<(), String> {
+    let players = game_state.get_players();
+
+    match self {
+      Invariant::UniquePositions => {
+        let mut positions = HashMap::new();
+        for (id, player) in players.iter() {
+          let (row, col) = player.get_position();
+          if let Some(other) = positions.insert((row, col), id) {
+            return Err(format!(
+              "players {} and {} are both at (row {}, col {})",
+              other, id, row, col
+            ));
+          }
+        }
+      },
+
+      Invariant::InBounds => {
+        let playfield = game_state.get_playfield();
+        for (id, player) in players.iter() {
+          let (row, col) = player.get_position();
+
+          // Lua arrays start at 1
+          let in_bounds = row >= 1
+            && col >= 1
+            && playfield
+              .get(row as usize - 1)
+              .is_some_and(|cols| col as usize <= cols.len());
+
+          if !in_bounds {
+            return Err(format!("player {} is out of bounds at (row {}, col {})", id, row, col));
+          }
+        }
+      },
+
+      Invariant::KilledRemoved => {
+        for (id, player) in players.iter() {
+          if !players_remaining.contains(id) {
+            return Err(format!("player {} was killed but is still in the game state", id));
+          }
+
+          if player.get_health() <= 0 {
+            return Err(format!(
+              "player {} has {} health but is still in the game state",
+              id,
+              player.get_health()
+            ));
+          }
+        }
+      },
+    }
+
+    Ok(())
+  }
+}
+
+impl FromStr for Invariant {
+  type Err = String;
+
+  fn from_str(input: &str) -> Result<Self, Self::Err> {
+    match input {
+      "unique-positions" => Ok(Invariant::UniquePositions),
+      "in-bounds" => Ok(Invariant::InBounds),
+      "killed-removed" => Ok(Invariant::KilledRemoved),
+      _ => Err(format!("unknown invariant '{}'", input)),
+    }
+  }
+}
+
+/// Everything needed to start the game from the middle, instead of calling Init()
+///   The Lua code restores its own variables from the game state in LoadState()
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Snapshot {
+  tick: u32,
   ticks_left: u32,
+  ticks_per_game: u32,
+  player_order: Vec<Uuid>,
+  player_names: HashMap<Uuid, String>,
+  players_remaining: HashSet<Uuid>,
+  game_state: GameState,
+}
+
+/// Single line in the JSON lines output file
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct OutputLine<'a> {
+  tick: u32,
+  ticks_left: u32,
+  actions_taken: &'a HashMap<Uuid, PlayerAction>,
+  game_state: &'a GameState,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  state_hash: Option<String>,
 }
 
 #[derive(Clone)]
 struct TestGamePlayerUserData {
   player_order: Arc<Vec<Uuid>>,
+  player_names: Arc<HashMap<Uuid, String>>,
   players_remaining: Arc<Mutex<HashSet<Uuid>>>,
+  forced_winners: Arc<Mutex<Option<HashSet<Uuid>>>>, // Only set if Lua ends the game early
   ticks_per_game: u32,
-  ticks_left: u32,
+  seconds_per_tick: u32,
+  current_tick: u32,
+  ticks_left: Arc<Mutex<u32>>, // Lua can change the time remaining
+  arena_width: u32,
+  arena_height: u32,
+  read_only: bool, // ViewFor() can look at the game, but not change it
 }
 
 impl TestGamePlayer {
   /// Construct a new test game player object
   ///   This validates the lua code when it is loaded
-  pub fn new(lua_file: impl AsRef<Path>, num_players: usize) -> Result<Self, GameEngineError> {
+  ///
+  /// The seed is used for both the test player actions and the Lua "math.random()" function
+  pub fn new(lua_file: impl AsRef<Path>, num_players: usize, seed: u64) -> Result<Self, GameEngineError> {
     // Read and execute the Lua code
     let lua_code = fs::read_to_string(&lua_file).map_err(GameEngineError::FailedToReadLuaFile)?;
 
     let lua = Lua::new();
     lua.context::<_, Result<(), GameEngineError>>(|ctx| {
-      // Add the parent directory (if it exists) to the Lua path
-      //  Silently fail on errors
-      if let Some(parent_dir) = lua_file.as_ref().parent() {
-        if let Some(parent_dir) = parent_dir.join("?.lua").to_str() {
-          log::debug!("Adding directory '{}' to Lua path", parent_dir);
-          if let Err(e) = ctx
-            .load(&format!(r#"package.path = [[{};]] .. package.path"#, parent_dir))
-            .exec()
-          {
-            log::warn!("Failed to update the Lua path: {}", e);
-          }
-        }
+      // Seed the Lua random number generator
+      let math: LuaTable = ctx.globals().get("math").map_err(GameEngineError::FailedToRunLuaFile)?;
+      math
+        .get::<_, LuaFunction>("randomseed")
+        .and_then(|randomseed| randomseed.call::<_, ()>(seed as LuaInteger))
+        .map_err(GameEngineError::FailedToRunLuaFile)?;
+
+      game::add_lua_paths(ctx, lua_file.as_ref());
+
+      if config::use_lua_sandbox() {
+        game::sandbox_lua(ctx)?;
       }
+      game::guard_lua_recursion(ctx)?;
 
       // Run the file
       ctx
@@ -143,22 +467,77 @@ impl TestGamePlayer {
 
     Ok(Self {
       lua,
+      rng: StdRng::seed_from_u64(seed),
+      output_file: None,
+      snapshot: None,
+      save_snapshot: None,
+      invariants: vec![],
+      action_weights: ActionWeights::default(),
       num_players,
+      max_tries: config::get_max_engine_tries(),
+      strict_game_state: config::use_strict_game_state(),
       player_order: Arc::default(),
+      player_names: Arc::default(),
       players_remaining: Arc::default(),
+      forced_winners: Arc::default(),
       ticks_per_game: config::get_ticks_per_game(),
-      ticks_left: 0,
+      seconds_per_tick: config::get_seconds_per_tick(),
+      current_tick: 0,
+      ticks_left: Arc::default(),
+      arena_width: config::get_arena_width(),
+      arena_height: config::get_arena_height(),
     })
   }
 
+  /// Write every game state to the given file, one JSON object per line
+  pub fn set_output_file(&mut self, file: File) {
+    self.output_file = Some(file);
+  }
+
+  /// Start the game from a snapshot instead of calling Init()
+  pub fn set_snapshot(&mut self, snapshot: Snapshot) {
+    self.snapshot = Some(snapshot);
+  }
+
+  /// Save a snapshot of the game to the file after the given tick
+  pub fn save_snapshot_at(&mut self, tick: u32, snapshot_file: PathBuf) {
+    self.save_snapshot = Some((tick, snapshot_file));
+  }
+
+  /// Check every game state against the given rules
+  pub fn set_invariants(&mut self, invariants: Vec<Invariant>) {
+    self.invariants = invariants;
+  }
+
+  /// Change how often the random players pick each action
+  pub fn set_action_weights(&mut self, action_weights: ActionWeights) {
+    self.action_weights = action_weights;
+  }
+
   /// Get the user data from the game state
   ///   This is passed to Lua as a "context" variable
   fn get_user_data(&self) -> TestGamePlayerUserData {
     TestGamePlayerUserData {
       player_order: self.player_order.clone(),
+      player_names: self.player_names.clone(),
       players_remaining: self.players_remaining.clone(),
+      forced_winners: self.forced_winners.clone(),
       ticks_per_game: self.ticks_per_game,
-      ticks_left: self.ticks_left,
+      seconds_per_tick: self.seconds_per_tick,
+      current_tick: self.current_tick,
+      ticks_left: self.ticks_left.clone(),
+      arena_width: self.arena_width,
+      arena_height: self.arena_height,
+      read_only: false,
+    }
+  }
+
+  /// Context for ViewFor(), which can't kill players or change the game clock
+  ///   Otherwise it could end the round after the tick already decided the game keeps going
+  fn get_read_only_user_data(&self) -> TestGamePlayerUserData {
+    TestGamePlayerUserData {
+      read_only: true,
+      ..self.get_user_data()
     }
   }
 
@@ -176,73 +555,179 @@ impl TestGamePlayer {
   ///
   /// 1. There are 2 or more players left in the game
   /// 2. AND there is time left on the clock
+  /// 3. AND the Lua code has not ended the game early
   fn is_round_running(&self) -> bool {
-    self.ticks_left > 0 && self.players_remaining.lock().unwrap().len() > 1
+    self.get_ticks_left() > 0
+      && self.players_remaining.lock().unwrap().len() > 1
+      && self.forced_winners.lock().unwrap().is_none()
+  }
+
+  fn get_ticks_left(&self) -> u32 {
+    *self.ticks_left.lock().unwrap()
+  }
+
+  /// Winners picked by the Lua code, or else all players still alive
+  fn get_winners(&self) -> HashSet<Uuid> {
+    match *self.forced_winners.lock().unwrap() {
+      Some(ref winners) => winners.clone(),
+      None => self.players_remaining.lock().unwrap().clone(),
+    }
   }
 
   ///
   /// Run the game and return a GameEngineError on a fatal error
   ///
   fn run_internal(&mut self) -> Result<(), GameEngineError> {
-    log::info!("Generating random list of players");
+    let initial_state = match self.snapshot.take() {
+      // Skip Init() and pick up where the snapshot left off
+      Some(snapshot) => {
+        log::info!("Loading snapshot from tick {}...", snapshot.tick);
+        Self::trap_errors(self.max_tries, || self.load_snapshot(&snapshot))?
+      },
 
-    // Wait for the mediator to say the game is ready to start
-    let player_order = (0..self.num_players).into_iter().map(|_| Uuid::new_v4()).collect();
+      None => {
+        log::info!("Generating random list of players");
 
-    // Initialize the game!
-    log::info!("Initializing game engine...");
+        // Wait for the mediator to say the game is ready to start
+        let (player_order, players) = self.random_players();
 
-    let initial_state = Self::trap_errors(MAX_TRIES, || self.init_game(&player_order))?;
+        // Initialize the game!
+        log::info!("Initializing game engine...");
+        Self::trap_errors(self.max_tries, || self.init_game(&player_order, &players))?
+      },
+    };
     log::debug!(
       "Initial state: {}",
       serde_json::to_string_pretty(&initial_state).unwrap()
     );
+    self.write_output(&initial_state, &HashMap::new());
+    self.try_save_snapshot(&initial_state);
+    self.check_invariants(&initial_state)?;
+    self.check_player_views(&initial_state)?;
 
     // Run until there is no time left
     while self.is_round_running() {
-      self.ticks_left -= 1;
+      self.current_tick += 1;
+      *self.ticks_left.lock().unwrap() -= 1;
+      let ticks_left = self.get_ticks_left();
       log::info!(
         "Game engine running - {} tick{} remaining",
-        self.ticks_left,
-        if self.ticks_left == 1 { "" } else { "s" }
+        ticks_left,
+        if ticks_left == 1 { "" } else { "s" }
       );
 
-      // Pick random actions for the players
-      //  Filter any actions for players that have died (just to be extra safe)
-      let players_remaining = self.players_remaining.lock().unwrap();
-      let player_actions: HashMap<_, _> = self
-        .pick_random_player_actions()
-        .into_iter()
-        .filter(|(id, _)| players_remaining.contains(id))
-        .collect();
-      drop(players_remaining);
-
       // Update the game state
-      let next_state = Self::trap_errors(MAX_TRIES, || self.tick_game(&player_actions))?;
+      let player_actions = self.pick_remaining_player_actions()?;
+      let next_state = Self::trap_errors(self.max_tries, || self.tick_game(&player_actions))?;
       log::debug!("Next state: {}", serde_json::to_string_pretty(&next_state).unwrap());
+      self.write_output(&next_state, &player_actions);
+      self.try_save_snapshot(&next_state);
+      self.check_invariants(&next_state)?;
+      if self.is_round_running() {
+        self.check_player_views(&next_state)?;
+      }
     }
 
     log::info!("Game ended without any problems");
 
     // Show the winner(s)
-    let players_remaining = self.players_remaining.lock().unwrap();
-    if players_remaining.len() > 1 {
-      log::info!("Winners: {:#?}", players_remaining);
+    let winners = self.get_winners();
+    if winners.len() > 1 {
+      log::info!("Winners: {:#?}", winners);
     } else {
-      log::info!("Winner: {:#?}", players_remaining);
+      log::info!("Winner: {:#?}", winners);
+    }
+
+    Ok(())
+  }
+
+  ///
+  /// Run the number of ticks in a game as fast as possible, then report the timing statistics
+  ///
+  /// Any output file or invariant checks are ignored, since only the game engine is being measured
+  pub fn run_benchmark(&mut self) -> Result<(), GameEngineError> {
+    let total_ticks = self.ticks_per_game as usize;
+    log::info!(
+      "Benchmarking {} ticks with {} players...",
+      total_ticks,
+      self.num_players
+    );
+
+    let mut tick_durations = Vec::with_capacity(total_ticks);
+    let start = Instant::now();
+    while tick_durations.len() < total_ticks {
+      // Start a new game whenever the last one ends early
+      let (player_order, players) = self.random_players();
+      Self::trap_errors(self.max_tries, || self.init_game(&player_order, &players))?;
+
+      while self.is_round_running() && tick_durations.len() < total_ticks {
+        self.current_tick += 1;
+        *self.ticks_left.lock().unwrap() -= 1;
+        let player_actions = self.pick_remaining_player_actions()?;
+
+        let tick_start = Instant::now();
+        Self::trap_errors(self.max_tries, || self.tick_game(&player_actions))?;
+        tick_durations.push(tick_start.elapsed());
+      }
     }
+    let elapsed = start.elapsed();
+
+    // Compute the statistics from the sorted durations
+    tick_durations.sort();
+    let mean = tick_durations.iter().sum::<Duration>() / total_ticks as u32;
+    let p99 = tick_durations[(total_ticks * 99).div_ceil(100) - 1];
+
+    log::info!("Benchmark finished:");
+    log::info!("  Min tick:   {:?}", tick_durations[0]);
+    log::info!("  Max tick:   {:?}", tick_durations[total_ticks - 1]);
+    log::info!("  Mean tick:  {:?}", mean);
+    log::info!("  P99 tick:   {:?}", p99);
+    log::info!(
+      "  Throughput: {:.1} ticks/second ({} ticks in {:?})",
+      total_ticks as f64 / elapsed.as_secs_f64(),
+      total_ticks,
+      elapsed
+    );
 
     Ok(())
   }
 
+  /// Generate a new list of player IDs using the seeded random number generator
+  ///   Every player is named after their place in the player order
+  fn random_players(&mut self) -> (Vec<Uuid>, HashMap<Uuid, JWTPlayerData>) {
+    let player_order: Vec<_> = (0..self.num_players)
+      .map(|_| uuid::Builder::from_random_bytes(self.rng.gen()).into_uuid())
+      .collect();
+
+    let players = player_order
+      .iter()
+      .enumerate()
+      .map(|(i, id)| (*id, JWTPlayerData::new(format!("Player {}", i + 1))))
+      .collect();
+
+    (player_order, players)
+  }
+
   ///
   /// Handle game initialization with the given player order
   ///
-  fn init_game(&mut self, player_order: &Vec<Uuid>) -> Result<GameState, GameEngineError> {
+  fn init_game(
+    &mut self,
+    player_order: &[Uuid],
+    players: &HashMap<Uuid, JWTPlayerData>,
+  ) -> Result<GameState, GameEngineError> {
     // Initialize game player variables
-    self.player_order = Arc::new(player_order.clone());
-    self.ticks_left = self.ticks_per_game;
+    self.player_order = Arc::new(player_order.to_vec());
+    self.player_names = Arc::new(
+      players
+        .iter()
+        .map(|(id, data)| (*id, data.get_name().clone()))
+        .collect(),
+    );
+    self.current_tick = 0;
+    self.ticks_left = Arc::new(Mutex::new(self.ticks_per_game));
     self.players_remaining = Arc::new(Mutex::new(player_order.iter().cloned().collect()));
+    self.forced_winners = Arc::default();
 
     // Run the Lua Init() method and return the initial game state as JSON
     self.lua.context::<_, Result<_, GameEngineError>>(|ctx| {
@@ -253,44 +738,225 @@ impl TestGamePlayer {
 
       let user_data = self.get_user_data();
       let player_order: Vec<_> = self.player_order.iter().map(Uuid::to_string).collect();
+      let players: HashMap<String, LuaValue> = players
+        .iter()
+        .map(|(id, data)| {
+          let id = id.to_string();
+          let value = rlua_serde::to_value(ctx, data).map_err(GameEngineError::JSONToLua)?;
+          Ok((id, value))
+        })
+        .collect::<Result<_, _>>()?;
 
       let lua_game_state = init
-        .call::<_, LuaValue>((user_data, player_order))
+        .call::<_, LuaValue>((user_data, player_order, players))
         .map_err(|e| GameEngineError::FailedToRunMethod("Init", e))?;
 
-      let json_game_state: GameState = rlua_serde::from_value(lua_game_state).map_err(GameEngineError::LuaToJSON)?;
+      let json_game_state = GameState::from_lua(lua_game_state, self.strict_game_state)?;
+      json_game_state.validate_playfield_size(self.arena_height, self.arena_width)?;
 
       Ok(json_game_state)
     })
   }
 
-  /// Randomly pick an action (and random direction if necessary) for each player
-  fn pick_random_player_actions(&self) -> HashMap<Uuid, PlayerAction> {
-    let mut rng = rand::thread_rng();
+  ///
+  /// Restore the game from a snapshot by calling the Lua LoadState() method instead of Init()
+  ///
+  fn load_snapshot(&mut self, snapshot: &Snapshot) -> Result<GameState, GameEngineError> {
+    // Restore the game player variables
+    self.player_order = Arc::new(snapshot.player_order.clone());
+    self.player_names = Arc::new(snapshot.player_names.clone());
+    self.ticks_per_game = snapshot.ticks_per_game;
+    self.current_tick = snapshot.tick;
+    self.ticks_left = Arc::new(Mutex::new(snapshot.ticks_left));
+    self.players_remaining = Arc::new(Mutex::new(snapshot.players_remaining.clone()));
+    self.forced_winners = Arc::default();
+
+    // Let the Lua code rebuild its own variables from the game state
+    self.lua.context(|ctx| {
+      let load_state = ctx
+        .globals()
+        .get::<_, LuaFunction>("LoadState")
+        .map_err(|e| GameEngineError::MissingRequiredLuaMethod("LoadState", e))?;
 
+      let lua_game_state = rlua_serde::to_value(ctx, &snapshot.game_state).map_err(GameEngineError::JSONToLua)?;
+      load_state
+        .call::<_, ()>((self.get_user_data(), lua_game_state))
+        .map_err(|e| GameEngineError::FailedToRunMethod("LoadState", e))
+    })?;
+
+    Ok(snapshot.game_state.clone())
+  }
+
+  /// Save a snapshot of the game if this is the requested tick
+  fn try_save_snapshot(&self, game_state: &GameState) {
+    let snapshot_file = match self.save_snapshot {
+      Some((tick, ref snapshot_file)) if tick == self.current_tick => snapshot_file,
+      _ => return,
+    };
+
+    let snapshot = Snapshot {
+      tick: self.current_tick,
+      ticks_left: self.get_ticks_left(),
+      ticks_per_game: self.ticks_per_game,
+      player_order: self.player_order.as_ref().clone(),
+      player_names: self.player_names.as_ref().clone(),
+      players_remaining: self.players_remaining.lock().unwrap().clone(),
+      game_state: game_state.clone(),
+    };
+
+    let result = serde_json::to_string_pretty(&snapshot)
+      .map_err(io::Error::from)
+      .and_then(|json| fs::write(snapshot_file, json));
+
+    match result {
+      Ok(()) => log::info!(
+        "Saved snapshot of tick {} to '{}' (run with --load-snapshot to start from here)",
+        self.current_tick,
+        snapshot_file.display()
+      ),
+      Err(e) => log::error!("Failed to save snapshot to '{}': {}", snapshot_file.display(), e),
+    }
+  }
+
+  /// Make sure the optional Lua ViewFor() method works for every player
+  fn check_player_views(&self, game_state: &GameState) -> Result<(), GameEngineError> {
+    let player_views = Self::trap_errors(self.max_tries, || self.view_for_players(game_state))?;
+    for (player_id, view) in player_views.iter() {
+      log::debug!(
+        "View for player {}: {}",
+        player_id,
+        serde_json::to_string_pretty(view).unwrap()
+      );
+    }
+
+    Ok(())
+  }
+
+  ///
+  /// Run the optional Lua ValidateAction() method to check if the player is allowed to take the action
+  ///   Every action is allowed if the method isn't defined
+  ///
+  fn validate_action(&self, player_id: &Uuid, action: &PlayerAction) -> Result<bool, GameEngineError> {
+    self.lua.context(|ctx| {
+      let validate_action = ctx
+        .globals()
+        .get::<_, Option<LuaFunction>>("ValidateAction")
+        .map_err(|e| GameEngineError::FailedToRunMethod("ValidateAction", e))?;
+
+      let validate_action = match validate_action {
+        Some(validate_action) => validate_action,
+        None => return Ok(true),
+      };
+
+      let action = rlua_serde::to_value(ctx, action).map_err(GameEngineError::JSONToLua)?;
+      validate_action
+        .call::<_, bool>((self.get_user_data(), player_id.to_string(), action))
+        .map_err(|e| GameEngineError::FailedToRunMethod("ValidateAction", e))
+    })
+  }
+
+  ///
+  /// Run the optional Lua ViewFor() method to get the game state that each player is allowed to see
+  ///   Returns an empty map if the method isn't defined, so every player sees the full game state
+  ///
+  fn view_for_players(&self, game_state: &GameState) -> Result<HashMap<Uuid, GameState>, GameEngineError> {
+    self.lua.context(|ctx| {
+      let view_for = ctx
+        .globals()
+        .get::<_, Option<LuaFunction>>("ViewFor")
+        .map_err(|e| GameEngineError::FailedToRunMethod("ViewFor", e))?;
+
+      let view_for = match view_for {
+        Some(view_for) => view_for,
+        None => return Ok(HashMap::new()),
+      };
+
+      self
+        .player_order
+        .iter()
+        .map(|player_id| {
+          // Every call gets a fresh copy, in case the Lua code modifies the state
+          let lua_game_state = rlua_serde::to_value(ctx, game_state).map_err(GameEngineError::JSONToLua)?;
+          let lua_view = view_for
+            .call::<_, LuaValue>((self.get_read_only_user_data(), lua_game_state, player_id.to_string()))
+            .map_err(|e| GameEngineError::FailedToRunMethod("ViewFor", e))?;
+
+          let view = GameState::from_lua(lua_view, self.strict_game_state)?;
+          view.validate_playfield_size(self.arena_height, self.arena_width)?;
+
+          Ok((*player_id, view))
+        })
+        .collect()
+    })
+  }
+
+  /// Append the game state to the output file (if any)
+  ///   The file is flushed after every line so a crash still leaves a usable trace
+  fn write_output(&mut self, game_state: &GameState, actions_taken: &HashMap<Uuid, PlayerAction>) {
+    let file = match self.output_file {
+      Some(ref mut file) => file,
+      None => return,
+    };
+
+    let line = OutputLine {
+      tick: self.current_tick,
+      ticks_left: *self.ticks_left.lock().unwrap(),
+      actions_taken,
+      game_state,
+      state_hash: game_state.state_hash(),
+    };
+
+    let result = serde_json::to_writer(&mut *file, &line)
+      .map_err(io::Error::from)
+      .and_then(|_| writeln!(file))
+      .and_then(|_| file.flush());
+
+    if let Err(e) = result {
+      log::error!("Failed to write game state to output file: {}", e);
+    }
+  }
+
+  /// Make sure the game state doesn't break any of the selected rules
+  fn check_invariants(&self, game_state: &GameState) -> Result<(), GameEngineError> {
+    let players_remaining = self.players_remaining.lock().unwrap();
+    for invariant in self.invariants.iter() {
+      invariant
+        .check(game_state, &players_remaining)
+        .map_err(GameEngineError::InvariantViolation)?;
+    }
+
+    Ok(())
+  }
+
+  /// Pick random actions for the players
+  ///   Filter any actions for players that have died (just to be extra safe)
+  ///   Any actions rejected by the Lua ValidateAction() method are skipped
+  fn pick_remaining_player_actions(&mut self) -> Result<HashMap<Uuid, PlayerAction>, GameEngineError> {
+    let random_actions = self.pick_random_player_actions();
+    let players_remaining = self.players_remaining.lock().unwrap().clone();
+
+    let mut player_actions = HashMap::new();
+    for (id, action) in random_actions
+      .into_iter()
+      .filter(|(id, _)| players_remaining.contains(id))
+    {
+      if Self::trap_errors(self.max_tries, || self.validate_action(&id, &action))? {
+        player_actions.insert(id, action);
+      } else {
+        log::debug!("Action rejected for player {}: {:?}", id, action);
+      }
+    }
+
+    Ok(player_actions)
+  }
+
+  /// Randomly pick an action (and random direction if necessary) for each player
+  fn pick_random_player_actions(&mut self) -> HashMap<Uuid, PlayerAction> {
+    let weights = self.action_weights;
     self
       .player_order
       .iter()
-      .map(|id| {
-        let direction = *[Direction::Up, Direction::Down, Direction::Left, Direction::Right]
-          .choose(&mut rng)
-          .unwrap();
-
-        let action = [
-          (TaggedRequest::new(PlayerActionEnum::Move(MoveAction { direction })), 5),
-          (
-            TaggedRequest::new(PlayerActionEnum::Attack(AttackAction { direction })),
-            5,
-          ),
-          (TaggedRequest::new(PlayerActionEnum::DropWeapon), 2),
-        ]
-        .choose_weighted(&mut rng, |(_, w)| *w)
-        .unwrap()
-        .clone()
-        .0;
-
-        (*id, action)
-      })
+      .map(|id| (*id, weights.pick_action(&mut self.rng)))
       .collect()
   }
 
@@ -319,7 +985,8 @@ impl TestGamePlayer {
         .call::<_, LuaValue>((user_data, player_actions))
         .map_err(|e| GameEngineError::FailedToRunMethod("Update", e))?;
 
-      let json_game_state: GameState = rlua_serde::from_value(lua_game_state).map_err(GameEngineError::LuaToJSON)?;
+      let json_game_state = GameState::from_lua(lua_game_state, self.strict_game_state)?;
+      json_game_state.validate_playfield_size(self.arena_height, self.arena_width)?;
 
       Ok(json_game_state)
     })
@@ -335,7 +1002,7 @@ impl TestGamePlayer {
       match func() {
         Ok(r) => return Ok(r),
         Err(e) => {
-          tries = tries + 1;
+          tries += 1;
           log::error!(
             "Game engine error: {} (Attempt {} / {})",
             e.get_developer_notes(),
@@ -352,14 +1019,46 @@ impl TestGamePlayer {
   }
 }
 
+impl TestGamePlayerUserData {
+  /// Round up, so the game never ends sooner than Lua asked for
+  fn seconds_to_ticks(&self, seconds: u32) -> u32 {
+    seconds.div_ceil(self.seconds_per_tick)
+  }
+
+  fn check_writable(&self, method: &str) -> LuaResult<()> {
+    if self.read_only {
+      return Err(LuaError::RuntimeError(format!(
+        "{}() cannot be called from ViewFor()",
+        method
+      )));
+    }
+    Ok(())
+  }
+}
+
 //
 // Helper context methods that get passed into Lua
 //
 impl LuaUserData for TestGamePlayerUserData {
   fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
     methods.add_method("notifyPlayerKilled", |_, this, uuid: String| {
+      this.check_writable("notifyPlayerKilled")?;
       let player_id: Uuid = Uuid::from_str(&uuid).map_err(|_| LuaError::RuntimeError("Invalid UUID".into()))?;
 
+      // Catch Lua bugs that pass the wrong ID, instead of broadcasting a bogus message
+      if !this.player_order.contains(&player_id) {
+        let valid_ids: Vec<_> = this.player_order.iter().map(Uuid::to_string).collect();
+        log::warn!(
+          "notifyPlayerKilled() called with unknown player {} (valid players: {})",
+          player_id,
+          valid_ids.join(", ")
+        );
+        return Err(LuaError::RuntimeError(format!(
+          "Player {} is not in the game",
+          player_id
+        )));
+      }
+
       // Update the internal list of players remaining
       this.players_remaining.lock().unwrap().remove(&player_id);
       log::info!("Player {} killed", player_id);
@@ -367,10 +1066,40 @@ impl LuaUserData for TestGamePlayerUserData {
       Ok(())
     });
 
+    methods.add_method("forceGameEnd", |_, this, winners: Vec<String>| {
+      this.check_writable("forceGameEnd")?;
+      if this.current_tick == 0 {
+        return Err(LuaError::RuntimeError(
+          "forceGameEnd() can only be called from Update()".into(),
+        ));
+      }
+
+      let mut winner_ids = HashSet::with_capacity(winners.len());
+      for uuid in winners.iter() {
+        let player_id: Uuid = Uuid::from_str(uuid).map_err(|_| LuaError::RuntimeError("Invalid UUID".into()))?;
+        if !this.player_order.contains(&player_id) {
+          return Err(LuaError::RuntimeError(format!(
+            "Player {} is not in the game",
+            player_id
+          )));
+        }
+        winner_ids.insert(player_id);
+      }
+
+      // The game loop stops once the current tick finishes
+      *this.forced_winners.lock().unwrap() = Some(winner_ids);
+      Ok(())
+    });
+
     methods.add_method("getPlayerOrder", |_, this, _: ()| {
       Ok(this.player_order.iter().map(Uuid::to_string).collect::<Vec<_>>())
     });
 
+    methods.add_method("getPlayerName", |_, this, uuid: String| {
+      let player_id: Uuid = Uuid::from_str(&uuid).map_err(|_| LuaError::RuntimeError("Invalid UUID".into()))?;
+      Ok(this.player_names.get(&player_id).cloned())
+    });
+
     methods.add_method("getPlayersRemaining", |_, this, _: ()| {
       Ok(
         this
@@ -384,7 +1113,31 @@ impl LuaUserData for TestGamePlayerUserData {
     });
 
     methods.add_method("getTicksLeft", |_, this, _: ()| {
-      Ok((this.ticks_left, this.ticks_per_game))
+      Ok((*this.ticks_left.lock().unwrap(), this.ticks_per_game))
+    });
+
+    methods.add_method("setTimeRemaining", |_, this, seconds: u32| {
+      this.check_writable("setTimeRemaining")?;
+      let ticks = this.seconds_to_ticks(seconds);
+      if ticks == 0 && this.current_tick == 0 {
+        return Err(LuaError::RuntimeError("Cannot end the game from Init()".into()));
+      }
+
+      *this.ticks_left.lock().unwrap() = ticks;
+      Ok(())
+    });
+
+    methods.add_method("addTime", |_, this, seconds: u32| {
+      this.check_writable("addTime")?;
+      let mut ticks_left = this.ticks_left.lock().unwrap();
+      *ticks_left = ticks_left.saturating_add(this.seconds_to_ticks(seconds));
+      Ok(())
+    });
+
+    methods.add_method("getCurrentTick", |_, this, _: ()| Ok(this.current_tick));
+
+    methods.add_method("getArenaSize", |_, this, _: ()| {
+      Ok((this.arena_height, this.arena_width))
     });
   }
 }
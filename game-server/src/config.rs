@@ -1,28 +1,117 @@
 //
 // Environment configuration functions
 //
+use actix_web::http::Uri;
 use dotenv::dotenv;
+use log::LevelFilter;
+use serde::Deserialize;
 use std::any::type_name;
 use std::env;
-use std::path::PathBuf;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use structopt::StructOpt;
 
+use crate::logging::LogFormat;
+
 const DEFAULT_HOST: &str = "127.0.0.1";
 const DEFAULT_PORT: u16 = 53700;
 const DEFAULT_JWT_SECRET: &str = "secret";
+const DEFAULT_MAX_TOKEN_LIFETIME_MINUTES: u32 = 0;
+const DEFAULT_WS_PROTOCOL: &str = "game-server";
+const DEFAULT_HEARTBEAT_INTERVAL_SECONDS: u32 = 5;
+const DEFAULT_HEARTBEAT_TIMEOUT_SECONDS: u32 = 15;
+const DEFAULT_PLAYER_IDLE_TIMEOUT_SECONDS: u32 = 0;
+const DEFAULT_MAX_MESSAGES_PER_SECOND: u32 = 20;
+const DEFAULT_MAX_MESSAGE_BYTES: usize = 64 * 1024;
+
+/// Messages this many times larger than MAX_MESSAGE_BYTES close the connection instead of returning an error
+const MAX_FRAME_SIZE_MULTIPLIER: usize = 4;
 
 pub const DEFAULT_LUA_FILE: &str = "lua/game.lua";
+const DEFAULT_MAX_ENGINE_TRIES: usize = 5;
+const DEFAULT_LUA_TIMEOUT_MS: u64 = 0;
+
+/// Every environment variable used to configure the server
+const CONFIG_VARIABLES: &[&str] = &[
+  "HOST",
+  "PORT",
+  "UNIX_SOCKET",
+  "USE_HTTPS",
+  "KEY_FILE",
+  "CERT_FILE",
+  "ALLOWED_ORIGINS",
+  "JWT_SECRET",
+  "MAX_TOKEN_LIFETIME_MINUTES",
+  "WS_PROTOCOL",
+  "HEARTBEAT_INTERVAL_SECONDS",
+  "HEARTBEAT_TIMEOUT_SECONDS",
+  "PLAYER_IDLE_TIMEOUT_SECONDS",
+  "MAX_MESSAGES_PER_SECOND",
+  "MAX_MESSAGE_BYTES",
+  "USE_DELTA_UPDATES",
+  "REGISTRATION_DELTAS",
+  "LUA_FILE",
+  "LUA_PATHS",
+  "LUA_SANDBOX",
+  "STRICT_GAME_STATE",
+  "MAX_ENGINE_TRIES",
+  "LUA_TIMEOUT_MS",
+  "MIN_PLAYERS_NEEDED",
+  "MAX_PLAYERS_ALLOWED",
+  "LOBBY_WAIT_SECONDS",
+  "START_WHEN_FULL",
+  "FILL_WITH_BOTS",
+  "BOT_FILL_SECONDS",
+  "CONNECT_GRACE_SECONDS",
+  "UNREGISTER_ON_DISCONNECT",
+  "RECONNECT_REPLACES_OLD",
+  "TICKS_PER_GAME",
+  "SECONDS_PER_TICK",
+  "ACTION_REPLACE_MODE",
+  "ARENA_WIDTH",
+  "ARENA_HEIGHT",
+  "LOG_FORMAT",
+  "LOG_LEVEL",
+  "ENABLE_METRICS",
+  "RECORD_REPLAYS",
+  "REPLAY_DIR",
+  "LENIENT",
+];
+
+/// Values that should never be printed
+const SECRET_VARIABLES: &[&str] = &["JWT_SECRET"];
+
+/// Value for ALLOWED_ORIGINS that allows requests from any origin
+pub const ANY_ORIGIN: &str = "*";
 
 const DEFAULT_MIN_PLAYERS: usize = 2;
 const DEFAULT_MAX_PLAYERS: usize = 8;
 const DEFAULT_LOBBY_WAIT_SECONDS: u32 = 10;
+const DEFAULT_CONNECT_GRACE_SECONDS: u32 = 0;
+const DEFAULT_BOT_FILL_SECONDS: u32 = 30;
 const DEFAULT_TICK_PER_GAME: u32 = 60 * 3;
 const DEFAULT_SECONDS_PER_TICK: u32 = 1;
+const DEFAULT_ARENA_WIDTH: u32 = 10;
+const DEFAULT_ARENA_HEIGHT: u32 = 10;
+const DEFAULT_REPLAY_DIR: &str = "replays";
 
 /// API Game Server for the Semester Project
 #[derive(StructOpt)]
 pub struct Opt {
+  /// TOML file with configuration values (overridden by environment variables and command-line options)
+  #[structopt(long, parse(from_os_str))]
+  config: Option<PathBuf>,
+
+  /// Check that the Lua file can be loaded, then exit without starting the server
+  #[structopt(long)]
+  validate_lua: bool,
+
+  /// Check the whole configuration and print the effective values, then exit without starting the server
+  #[structopt(long)]
+  dry_run: bool,
+
   /// Host to run the server
   #[structopt(short, long, env, default_value = DEFAULT_HOST)]
   host: String,
@@ -31,6 +120,10 @@ pub struct Opt {
   #[structopt(short, long, env, default_value = "53700")]
   port: u16,
 
+  /// Listen on a Unix domain socket at this path instead of the host and port
+  #[structopt(long, env, parse(from_os_str))]
+  unix_socket: Option<PathBuf>,
+
   /// Enable HTTPS (SSL) for the server
   #[structopt(long, env, takes_value(false), requires("key-file"), requires("cert-file"))]
   use_https: bool,
@@ -43,14 +136,74 @@ pub struct Opt {
   #[structopt(long, env, parse(from_os_str))]
   cert_file: Option<PathBuf>,
 
+  /// Comma-separated list of origins allowed to make cross-origin requests (or "*" for any origin)
+  #[structopt(long, env)]
+  allowed_origins: Option<String>,
+
   /// JSON Web Token secret
   #[structopt(short = "s", long, env, hide_env_values = true, default_value = DEFAULT_JWT_SECRET, hide_default_value(true))]
   jwt_secret: String,
 
+  /// Reject any JWT that is valid for longer than this many minutes (0 for no limit)
+  #[structopt(long, env, default_value = "0")]
+  max_token_lifetime_minutes: u32,
+
+  /// WebSocket subprotocol that clients must request when connecting
+  #[structopt(long, env, default_value = DEFAULT_WS_PROTOCOL)]
+  ws_protocol: String,
+
+  /// Number of seconds between each ping sent to the websocket clients
+  #[structopt(long, env, default_value = "5")]
+  heartbeat_interval_seconds: u32,
+
+  /// Close a websocket connection if the client doesn't respond within this many seconds
+  #[structopt(long, env, default_value = "15")]
+  heartbeat_timeout_seconds: u32,
+
+  /// Close idle player connections during registration after this many seconds (0 to disable)
+  #[structopt(long, env, default_value = "0")]
+  player_idle_timeout_seconds: u32,
+
+  /// Maximum number of messages each websocket client can send per second (0 for no limit)
+  #[structopt(long, env, default_value = "20")]
+  max_messages_per_second: u32,
+
+  /// Maximum size (in bytes) of a single message sent by a websocket client
+  #[structopt(long, env, default_value = "65536")]
+  max_message_bytes: usize,
+
+  /// Only send the changes between game states after the first full state
+  #[structopt(long, env, takes_value(false))]
+  use_delta_updates: bool,
+
+  /// Send single players joining or leaving the lobby instead of the full list of registered players
+  #[structopt(long, env, takes_value(false))]
+  registration_deltas: bool,
+
   /// Lua file containing the game engine code
   #[structopt(long, env, default_value = DEFAULT_LUA_FILE)]
   lua_file: String,
 
+  /// Extra directories to search for Lua modules (separated by ";" or ":")
+  #[structopt(long, env)]
+  lua_paths: Option<String>,
+
+  /// Remove the "os" and "io" libraries (and native module loading) from the Lua environment
+  #[structopt(long, env, default_value = "true", parse(try_from_str))]
+  lua_sandbox: bool,
+
+  /// Reject unknown fields in the game state returned by the Lua code (catches typos like "playfeild")
+  #[structopt(long, env, takes_value(false))]
+  strict_game_state: bool,
+
+  /// Number of times to try running a Lua method before the game engine crashes
+  #[structopt(long, env, default_value = "5")]
+  max_engine_tries: usize,
+
+  /// Crash the game engine if a single Lua method runs longer than this many milliseconds (0 to disable)
+  #[structopt(long, env, default_value = "0")]
+  lua_timeout_ms: u64,
+
   /// Minimum number of players required to play the game
   #[structopt(long, env, default_value = "2")]
   min_players_needed: usize,
@@ -63,6 +216,30 @@ pub struct Opt {
   #[structopt(long, env, default_value = "10")]
   lobby_wait_seconds: u32,
 
+  /// Start the game right away once the maximum number of players have registered
+  #[structopt(long, env, default_value = "true", parse(try_from_str))]
+  start_when_full: bool,
+
+  /// Fill the empty lobby slots with bots if the minimum number of players isn't reached in time
+  #[structopt(long, env, takes_value(false))]
+  fill_with_bots: bool,
+
+  /// Amount of time to wait for more players before filling the lobby with bots
+  #[structopt(long, env, default_value = "30")]
+  bot_fill_seconds: u32,
+
+  /// Amount of time to wait for all registered players to connect after the game starts
+  #[structopt(long, env, default_value = "0")]
+  connect_grace_seconds: u32,
+
+  /// Unregister players when their websocket disconnects before the game starts
+  #[structopt(long, env, takes_value(false))]
+  unregister_on_disconnect: bool,
+
+  /// Close the old websocket when a player reconnects, instead of rejecting the new connection
+  #[structopt(long, env, takes_value(false))]
+  reconnect_replaces_old: bool,
+
   /// Number of total "ticks" for a complete round in the game
   #[structopt(long, env, default_value = "180")]
   ticks_per_game: u32,
@@ -70,13 +247,68 @@ pub struct Opt {
   /// Number of seconds between each "tick" in the game engine
   #[structopt(long, env, default_value = "1")]
   seconds_per_tick: u32,
+
+  /// Let a new action overwrite the previous action in the same tick, instead of rejecting it
+  #[structopt(long, env, takes_value(false))]
+  action_replace_mode: bool,
+
+  /// Number of columns in the arena playfield
+  #[structopt(long, env, default_value = "10")]
+  arena_width: u32,
+
+  /// Number of rows in the arena playfield
+  #[structopt(long, env, default_value = "10")]
+  arena_height: u32,
+
+  /// Format for the log output ("pretty" or "json")
+  #[structopt(long, env, default_value = "pretty", possible_values = &["pretty", "json"])]
+  log_format: LogFormat,
+
+  /// Minimum level of log messages to show ("off", "error", "warn", "info", "debug", or "trace")
+  ///   Defaults to "debug" in debug builds and "info" in release builds
+  #[structopt(long, env)]
+  log_level: Option<String>,
+
+  /// Expose Prometheus metrics on the unauthenticated "/metrics" route
+  #[structopt(long, env, takes_value(false))]
+  enable_metrics: bool,
+
+  /// Write every broadcast message of each match to a replay file in REPLAY_DIR
+  #[structopt(long, env, takes_value(false))]
+  record_replays: bool,
+
+  /// Directory for the replay files, named "<match_id>.jsonl"
+  #[structopt(long, env, default_value = DEFAULT_REPLAY_DIR)]
+  replay_dir: String,
+
+  /// Clamp invalid configuration values with a warning instead of refusing to start
+  #[structopt(long, env, takes_value(false))]
+  lenient: bool,
 }
 
 impl Opt {
+  /// Get the path to the TOML configuration file, if one was given
+  pub fn get_config_file(&self) -> Option<&PathBuf> {
+    self.config.as_ref()
+  }
+
+  /// Only validate the Lua file instead of running the server
+  pub fn should_validate_lua(&self) -> bool {
+    self.validate_lua
+  }
+
+  /// Only validate the configuration instead of running the server
+  pub fn is_dry_run(&self) -> bool {
+    self.dry_run
+  }
+
   /// Update the environment variables with the command-line options
   pub fn update_environment(&self) {
     env::set_var("HOST", &self.host);
-    env::set_var("PORT", &self.port.to_string());
+    env::set_var("PORT", self.port.to_string());
+    if let Some(ref unix_socket) = self.unix_socket {
+      env::set_var("UNIX_SOCKET", unix_socket);
+    }
 
     if self.use_https {
       env::set_var("USE_HTTPS", "true");
@@ -87,15 +319,247 @@ impl Opt {
     if let Some(ref cert_file) = self.cert_file {
       env::set_var("CERT_FILE", cert_file);
     }
+    if let Some(ref allowed_origins) = self.allowed_origins {
+      env::set_var("ALLOWED_ORIGINS", allowed_origins);
+    }
 
     env::set_var("JWT_SECRET", &self.jwt_secret);
+    env::set_var(
+      "MAX_TOKEN_LIFETIME_MINUTES",
+      self.max_token_lifetime_minutes.to_string(),
+    );
+    env::set_var("WS_PROTOCOL", &self.ws_protocol);
+    env::set_var(
+      "HEARTBEAT_INTERVAL_SECONDS",
+      self.heartbeat_interval_seconds.to_string(),
+    );
+    env::set_var("HEARTBEAT_TIMEOUT_SECONDS", self.heartbeat_timeout_seconds.to_string());
+    env::set_var(
+      "PLAYER_IDLE_TIMEOUT_SECONDS",
+      self.player_idle_timeout_seconds.to_string(),
+    );
+    env::set_var("MAX_MESSAGES_PER_SECOND", self.max_messages_per_second.to_string());
+    env::set_var("MAX_MESSAGE_BYTES", self.max_message_bytes.to_string());
+    if self.use_delta_updates {
+      env::set_var("USE_DELTA_UPDATES", "true");
+    }
+    if self.registration_deltas {
+      env::set_var("REGISTRATION_DELTAS", "true");
+    }
     env::set_var("LUA_FILE", &self.lua_file);
+    if let Some(ref lua_paths) = self.lua_paths {
+      env::set_var("LUA_PATHS", lua_paths);
+    }
+    env::set_var("LUA_SANDBOX", self.lua_sandbox.to_string());
+    if self.strict_game_state {
+      env::set_var("STRICT_GAME_STATE", "true");
+    }
+    env::set_var("MAX_ENGINE_TRIES", self.max_engine_tries.to_string());
+    env::set_var("LUA_TIMEOUT_MS", self.lua_timeout_ms.to_string());
 
     env::set_var("MIN_PLAYERS_NEEDED", self.min_players_needed.to_string());
     env::set_var("MAX_PLAYERS_ALLOWED", self.max_players_allowed.to_string());
     env::set_var("LOBBY_WAIT_SECONDS", self.lobby_wait_seconds.to_string());
+    env::set_var("START_WHEN_FULL", self.start_when_full.to_string());
+    if self.fill_with_bots {
+      env::set_var("FILL_WITH_BOTS", "true");
+    }
+    env::set_var("BOT_FILL_SECONDS", self.bot_fill_seconds.to_string());
+    env::set_var("CONNECT_GRACE_SECONDS", self.connect_grace_seconds.to_string());
+    if self.unregister_on_disconnect {
+      env::set_var("UNREGISTER_ON_DISCONNECT", "true");
+    }
+    if self.reconnect_replaces_old {
+      env::set_var("RECONNECT_REPLACES_OLD", "true");
+    }
     env::set_var("TICKS_PER_GAME", self.ticks_per_game.to_string());
     env::set_var("SECONDS_PER_TICK", self.seconds_per_tick.to_string());
+    if self.action_replace_mode {
+      env::set_var("ACTION_REPLACE_MODE", "true");
+    }
+    env::set_var("ARENA_WIDTH", self.arena_width.to_string());
+    env::set_var("ARENA_HEIGHT", self.arena_height.to_string());
+
+    env::set_var("LOG_FORMAT", self.log_format.to_string());
+    if let Some(ref log_level) = self.log_level {
+      env::set_var("LOG_LEVEL", log_level);
+    }
+    if self.enable_metrics {
+      env::set_var("ENABLE_METRICS", "true");
+    }
+    if self.record_replays {
+      env::set_var("RECORD_REPLAYS", "true");
+    }
+    env::set_var("REPLAY_DIR", &self.replay_dir);
+    if self.lenient {
+      env::set_var("LENIENT", "true");
+    }
+  }
+}
+
+/// Configuration values that can be loaded from a TOML file
+///
+/// Keys use the same names as the command-line options, but with underscores (like `min_players_needed`)
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+  host: Option<String>,
+  port: Option<u16>,
+  unix_socket: Option<PathBuf>,
+  use_https: Option<bool>,
+  key_file: Option<PathBuf>,
+  cert_file: Option<PathBuf>,
+  allowed_origins: Option<String>,
+  jwt_secret: Option<String>,
+  max_token_lifetime_minutes: Option<u32>,
+  ws_protocol: Option<String>,
+  heartbeat_interval_seconds: Option<u32>,
+  heartbeat_timeout_seconds: Option<u32>,
+  player_idle_timeout_seconds: Option<u32>,
+  max_messages_per_second: Option<u32>,
+  max_message_bytes: Option<usize>,
+  use_delta_updates: Option<bool>,
+  registration_deltas: Option<bool>,
+  lua_file: Option<String>,
+  lua_paths: Option<String>,
+  lua_sandbox: Option<bool>,
+  strict_game_state: Option<bool>,
+  max_engine_tries: Option<usize>,
+  lua_timeout_ms: Option<u64>,
+  min_players_needed: Option<usize>,
+  max_players_allowed: Option<usize>,
+  lobby_wait_seconds: Option<u32>,
+  start_when_full: Option<bool>,
+  fill_with_bots: Option<bool>,
+  bot_fill_seconds: Option<u32>,
+  connect_grace_seconds: Option<u32>,
+  unregister_on_disconnect: Option<bool>,
+  reconnect_replaces_old: Option<bool>,
+  ticks_per_game: Option<u32>,
+  seconds_per_tick: Option<u32>,
+  action_replace_mode: Option<bool>,
+  arena_width: Option<u32>,
+  arena_height: Option<u32>,
+  log_format: Option<String>,
+  log_level: Option<String>,
+  enable_metrics: Option<bool>,
+  record_replays: Option<bool>,
+  replay_dir: Option<String>,
+  lenient: Option<bool>,
+}
+
+impl Config {
+  /// Read and parse the TOML configuration file
+  pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+    let path = path.as_ref();
+    let contents =
+      fs::read_to_string(path).map_err(|e| anyhow::anyhow!("Failed to read '{}': {}", path.display(), e))?;
+
+    toml::from_str(&contents).map_err(|e| anyhow::anyhow!("Failed to parse '{}': {}", path.display(), e))
+  }
+
+  /// Update the environment variables with the file values
+  ///   Variables that are already set in the environment are NOT overridden
+  ///
+  /// Boolean flags are only set when true, since the command-line parser treats any value as "set"
+  pub fn update_environment(&self) {
+    set_var_if_missing("HOST", self.host.as_ref());
+    set_var_if_missing("PORT", self.port.map(|v| v.to_string()));
+    set_var_if_missing("UNIX_SOCKET", self.unix_socket.as_ref());
+    set_var_if_missing("USE_HTTPS", self.use_https.filter(|v| *v).map(|v| v.to_string()));
+    set_var_if_missing("KEY_FILE", self.key_file.as_ref());
+    set_var_if_missing("CERT_FILE", self.cert_file.as_ref());
+    set_var_if_missing("ALLOWED_ORIGINS", self.allowed_origins.as_ref());
+    set_var_if_missing("JWT_SECRET", self.jwt_secret.as_ref());
+    set_var_if_missing(
+      "MAX_TOKEN_LIFETIME_MINUTES",
+      self.max_token_lifetime_minutes.map(|v| v.to_string()),
+    );
+    set_var_if_missing("WS_PROTOCOL", self.ws_protocol.as_ref());
+    set_var_if_missing(
+      "HEARTBEAT_INTERVAL_SECONDS",
+      self.heartbeat_interval_seconds.map(|v| v.to_string()),
+    );
+    set_var_if_missing(
+      "HEARTBEAT_TIMEOUT_SECONDS",
+      self.heartbeat_timeout_seconds.map(|v| v.to_string()),
+    );
+    set_var_if_missing(
+      "PLAYER_IDLE_TIMEOUT_SECONDS",
+      self.player_idle_timeout_seconds.map(|v| v.to_string()),
+    );
+    set_var_if_missing(
+      "MAX_MESSAGES_PER_SECOND",
+      self.max_messages_per_second.map(|v| v.to_string()),
+    );
+    set_var_if_missing("MAX_MESSAGE_BYTES", self.max_message_bytes.map(|v| v.to_string()));
+    set_var_if_missing(
+      "USE_DELTA_UPDATES",
+      self.use_delta_updates.filter(|v| *v).map(|v| v.to_string()),
+    );
+    set_var_if_missing(
+      "REGISTRATION_DELTAS",
+      self.registration_deltas.filter(|v| *v).map(|v| v.to_string()),
+    );
+    set_var_if_missing("LUA_FILE", self.lua_file.as_ref());
+    set_var_if_missing("LUA_PATHS", self.lua_paths.as_ref());
+    set_var_if_missing("LUA_SANDBOX", self.lua_sandbox.map(|v| v.to_string()));
+    set_var_if_missing(
+      "STRICT_GAME_STATE",
+      self.strict_game_state.filter(|v| *v).map(|v| v.to_string()),
+    );
+    set_var_if_missing("MAX_ENGINE_TRIES", self.max_engine_tries.map(|v| v.to_string()));
+    set_var_if_missing("LUA_TIMEOUT_MS", self.lua_timeout_ms.map(|v| v.to_string()));
+    set_var_if_missing("MIN_PLAYERS_NEEDED", self.min_players_needed.map(|v| v.to_string()));
+    set_var_if_missing("MAX_PLAYERS_ALLOWED", self.max_players_allowed.map(|v| v.to_string()));
+    set_var_if_missing("LOBBY_WAIT_SECONDS", self.lobby_wait_seconds.map(|v| v.to_string()));
+    set_var_if_missing("START_WHEN_FULL", self.start_when_full.map(|v| v.to_string()));
+    set_var_if_missing(
+      "FILL_WITH_BOTS",
+      self.fill_with_bots.filter(|v| *v).map(|v| v.to_string()),
+    );
+    set_var_if_missing("BOT_FILL_SECONDS", self.bot_fill_seconds.map(|v| v.to_string()));
+    set_var_if_missing(
+      "CONNECT_GRACE_SECONDS",
+      self.connect_grace_seconds.map(|v| v.to_string()),
+    );
+    set_var_if_missing(
+      "UNREGISTER_ON_DISCONNECT",
+      self.unregister_on_disconnect.filter(|v| *v).map(|v| v.to_string()),
+    );
+    set_var_if_missing(
+      "RECONNECT_REPLACES_OLD",
+      self.reconnect_replaces_old.filter(|v| *v).map(|v| v.to_string()),
+    );
+    set_var_if_missing("TICKS_PER_GAME", self.ticks_per_game.map(|v| v.to_string()));
+    set_var_if_missing("SECONDS_PER_TICK", self.seconds_per_tick.map(|v| v.to_string()));
+    set_var_if_missing(
+      "ACTION_REPLACE_MODE",
+      self.action_replace_mode.filter(|v| *v).map(|v| v.to_string()),
+    );
+    set_var_if_missing("ARENA_WIDTH", self.arena_width.map(|v| v.to_string()));
+    set_var_if_missing("ARENA_HEIGHT", self.arena_height.map(|v| v.to_string()));
+    set_var_if_missing("LOG_FORMAT", self.log_format.as_ref());
+    set_var_if_missing("LOG_LEVEL", self.log_level.as_ref());
+    set_var_if_missing(
+      "ENABLE_METRICS",
+      self.enable_metrics.filter(|v| *v).map(|v| v.to_string()),
+    );
+    set_var_if_missing(
+      "RECORD_REPLAYS",
+      self.record_replays.filter(|v| *v).map(|v| v.to_string()),
+    );
+    set_var_if_missing("REPLAY_DIR", self.replay_dir.as_ref());
+    set_var_if_missing("LENIENT", self.lenient.filter(|v| *v).map(|v| v.to_string()));
+  }
+}
+
+/// Set the environment variable only if it has a value and isn't already set
+fn set_var_if_missing(env_name: &str, value: Option<impl AsRef<OsStr>>) {
+  if let Some(value) = value {
+    if env::var_os(env_name).is_none() {
+      env::set_var(env_name, value);
+    }
   }
 }
 
@@ -126,6 +590,291 @@ where
   }
 }
 
+/// Parse the string into the given type, returning a description of the problem if parsing failed
+fn parse_strict<T>(env_name: &str, default_value: T) -> Result<T, String>
+where
+  T: FromStr,
+{
+  match env::var(env_name) {
+    Ok(input) => input
+      .parse()
+      .map_err(|_| format!("{}: invalid {} '{}'", env_name, type_name::<T>(), input)),
+    Err(_) => Ok(default_value),
+  }
+}
+
+/// Make sure the file given by the environment variable can be opened for reading
+fn check_file_readable(env_name: &str, filename: &str, errors: &mut Vec<String>) {
+  if let Err(e) = File::open(filename) {
+    errors.push(format!("{}: cannot read '{}': {}", env_name, filename, e));
+  }
+}
+
+/// Unix domain sockets don't support HTTPS, and an existing file is only replaced if it is a stale socket
+fn check_unix_socket(unix_socket: &Path, errors: &mut Vec<String>) {
+  if !cfg!(unix) {
+    errors.push("UNIX_SOCKET: only supported on Unix platforms".into());
+  }
+  if matches!(parse_strict("USE_HTTPS", false), Ok(true)) {
+    errors.push("UNIX_SOCKET: cannot be combined with USE_HTTPS".into());
+  }
+  if unix_socket.exists() && !is_socket_file(unix_socket) {
+    errors.push(format!(
+      "UNIX_SOCKET: '{}' already exists and is not a socket",
+      unix_socket.display()
+    ));
+  }
+}
+
+/// Test if the path is a Unix domain socket
+#[cfg(unix)]
+pub fn is_socket_file(path: &Path) -> bool {
+  use std::os::unix::fs::FileTypeExt;
+  fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket())
+}
+
+#[cfg(not(unix))]
+pub fn is_socket_file(_: &Path) -> bool {
+  false
+}
+
+///
+/// Validate the current configuration, returning a list of ALL problems found
+///
+/// This does not clamp any values, so it should be run before any of the getter functions.
+///
+pub fn validate() -> Result<(), Vec<String>> {
+  let mut errors = Vec::new();
+
+  // Basic server variables
+  if let Err(e) = parse_strict("PORT", DEFAULT_PORT) {
+    errors.push(e);
+  }
+  if let Some(unix_socket) = get_unix_socket() {
+    check_unix_socket(&unix_socket, &mut errors);
+  }
+
+  // HTTPS and SSL/TLS encryption
+  match parse_strict("USE_HTTPS", false) {
+    Ok(true) => {
+      match get_key_file() {
+        Some(key_file) => check_file_readable("KEY_FILE", &key_file, &mut errors),
+        None => errors.push("KEY_FILE: must be set when USE_HTTPS is enabled".into()),
+      }
+      match get_cert_file() {
+        Some(cert_file) => check_file_readable("CERT_FILE", &cert_file, &mut errors),
+        None => errors.push("CERT_FILE: must be set when USE_HTTPS is enabled".into()),
+      }
+    },
+    Ok(false) => {},
+    Err(e) => errors.push(e),
+  }
+
+  // Cross-origin requests
+  let allowed_origins = split_allowed_origins();
+  if allowed_origins.len() > 1 && allowed_origins.iter().any(|origin| origin == ANY_ORIGIN) {
+    errors.push("ALLOWED_ORIGINS: '*' cannot be combined with other origins".into());
+  }
+  for origin in allowed_origins.iter().filter(|origin| !is_valid_origin(origin)) {
+    errors.push(format!("ALLOWED_ORIGINS: invalid origin '{}'", origin));
+  }
+
+  // JSON Web Tokens
+  if let Err(e) = parse_strict("MAX_TOKEN_LIFETIME_MINUTES", DEFAULT_MAX_TOKEN_LIFETIME_MINUTES) {
+    errors.push(e);
+  }
+
+  // WebSocket subprotocol is sent as a comma-separated header token
+  let ws_protocol = get_ws_protocol();
+  if ws_protocol.is_empty() {
+    errors.push("WS_PROTOCOL: cannot be empty".into());
+  } else if ws_protocol.contains(|c: char| c == ',' || c.is_whitespace()) {
+    errors.push(format!(
+      "WS_PROTOCOL: cannot contain commas or whitespace (got '{}')",
+      ws_protocol
+    ));
+  }
+
+  let heartbeat_interval = parse_strict("HEARTBEAT_INTERVAL_SECONDS", DEFAULT_HEARTBEAT_INTERVAL_SECONDS);
+  let heartbeat_timeout = parse_strict("HEARTBEAT_TIMEOUT_SECONDS", DEFAULT_HEARTBEAT_TIMEOUT_SECONDS);
+  match (heartbeat_interval, heartbeat_timeout) {
+    (Ok(interval), Ok(timeout)) => {
+      if interval < 1 {
+        errors.push("HEARTBEAT_INTERVAL_SECONDS: cannot be less than 1".into());
+      }
+      if timeout <= interval {
+        errors.push(format!(
+          "HEARTBEAT_TIMEOUT_SECONDS: must be larger than HEARTBEAT_INTERVAL_SECONDS ({} <= {})",
+          timeout, interval
+        ));
+      }
+    },
+    (interval, timeout) => {
+      errors.extend(interval.err());
+      errors.extend(timeout.err());
+    },
+  }
+  if let Err(e) = parse_strict("PLAYER_IDLE_TIMEOUT_SECONDS", DEFAULT_PLAYER_IDLE_TIMEOUT_SECONDS) {
+    errors.push(e);
+  }
+  if let Err(e) = parse_strict("MAX_MESSAGES_PER_SECOND", DEFAULT_MAX_MESSAGES_PER_SECOND) {
+    errors.push(e);
+  }
+  match parse_strict("MAX_MESSAGE_BYTES", DEFAULT_MAX_MESSAGE_BYTES) {
+    Ok(max_message_bytes) if max_message_bytes < 1 => errors.push("MAX_MESSAGE_BYTES: cannot be less than 1".into()),
+    Ok(_) => {},
+    Err(e) => errors.push(e),
+  }
+
+  if let Err(e) = parse_strict("USE_DELTA_UPDATES", false) {
+    errors.push(e);
+  }
+  if let Err(e) = parse_strict("REGISTRATION_DELTAS", false) {
+    errors.push(e);
+  }
+
+  // Lua engine code
+  check_file_readable("LUA_FILE", &get_lua_file(), &mut errors);
+  if let Err(e) = parse_strict("LUA_SANDBOX", true) {
+    errors.push(e);
+  }
+  if let Err(e) = parse_strict("STRICT_GAME_STATE", false) {
+    errors.push(e);
+  }
+  match parse_strict("MAX_ENGINE_TRIES", DEFAULT_MAX_ENGINE_TRIES) {
+    Ok(max_engine_tries) if max_engine_tries < 1 => errors.push("MAX_ENGINE_TRIES: cannot be less than 1".into()),
+    Ok(_) => {},
+    Err(e) => errors.push(e),
+  }
+  if let Err(e) = parse_strict("LUA_TIMEOUT_MS", DEFAULT_LUA_TIMEOUT_MS) {
+    errors.push(e);
+  }
+
+  // Game configuration variables
+  let min_players = parse_strict("MIN_PLAYERS_NEEDED", DEFAULT_MIN_PLAYERS);
+  let max_players = parse_strict("MAX_PLAYERS_ALLOWED", DEFAULT_MAX_PLAYERS);
+  match (min_players, max_players) {
+    (Ok(min_players), Ok(max_players)) => {
+      if min_players < 2 {
+        errors.push(format!(
+          "MIN_PLAYERS_NEEDED: cannot be less than 2 (got {})",
+          min_players
+        ));
+      }
+      if max_players < min_players {
+        errors.push(format!(
+          "MAX_PLAYERS_ALLOWED: cannot be smaller than MIN_PLAYERS_NEEDED ({} < {})",
+          max_players, min_players
+        ));
+      }
+    },
+    (min_players, max_players) => {
+      errors.extend(min_players.err());
+      errors.extend(max_players.err());
+    },
+  }
+
+  match parse_strict("LOBBY_WAIT_SECONDS", DEFAULT_LOBBY_WAIT_SECONDS) {
+    Ok(lobby_wait_seconds) if lobby_wait_seconds < 1 => errors.push("LOBBY_WAIT_SECONDS: cannot be less than 1".into()),
+    Ok(_) => {},
+    Err(e) => errors.push(e),
+  }
+  if let Err(e) = parse_strict("START_WHEN_FULL", true) {
+    errors.push(e);
+  }
+  if let Err(e) = parse_strict("FILL_WITH_BOTS", false) {
+    errors.push(e);
+  }
+  match parse_strict("BOT_FILL_SECONDS", DEFAULT_BOT_FILL_SECONDS) {
+    Ok(bot_fill_seconds) if bot_fill_seconds < 1 => errors.push("BOT_FILL_SECONDS: cannot be less than 1".into()),
+    Ok(_) => {},
+    Err(e) => errors.push(e),
+  }
+
+  if let Err(e) = parse_strict("CONNECT_GRACE_SECONDS", DEFAULT_CONNECT_GRACE_SECONDS) {
+    errors.push(e);
+  }
+  if let Err(e) = parse_strict("UNREGISTER_ON_DISCONNECT", false) {
+    errors.push(e);
+  }
+  if let Err(e) = parse_strict("RECONNECT_REPLACES_OLD", false) {
+    errors.push(e);
+  }
+
+  match parse_strict("TICKS_PER_GAME", DEFAULT_TICK_PER_GAME) {
+    Ok(ticks_per_game) if ticks_per_game < 30 => errors.push(format!(
+      "TICKS_PER_GAME: cannot be less than 30 (got {})",
+      ticks_per_game
+    )),
+    Ok(_) => {},
+    Err(e) => errors.push(e),
+  }
+
+  match parse_strict("SECONDS_PER_TICK", DEFAULT_SECONDS_PER_TICK) {
+    Ok(seconds_per_tick) if seconds_per_tick < 1 => errors.push("SECONDS_PER_TICK: cannot be less than 1".into()),
+    Ok(_) => {},
+    Err(e) => errors.push(e),
+  }
+  if let Err(e) = parse_strict("ACTION_REPLACE_MODE", false) {
+    errors.push(e);
+  }
+
+  // Arena size
+  for (env_name, default) in [
+    ("ARENA_WIDTH", DEFAULT_ARENA_WIDTH),
+    ("ARENA_HEIGHT", DEFAULT_ARENA_HEIGHT),
+  ] {
+    match parse_strict(env_name, default) {
+      Ok(size) if size < 1 => errors.push(format!("{}: cannot be less than 1", env_name)),
+      Ok(_) => {},
+      Err(e) => errors.push(e),
+    }
+  }
+
+  // Logging and Prometheus metrics
+  if let Err(e) = parse_strict("LOG_FORMAT", LogFormat::Pretty) {
+    errors.push(e);
+  }
+  if let Err(e) = parse_strict("LOG_LEVEL", LevelFilter::Info) {
+    errors.push(e);
+  }
+  if let Err(e) = parse_strict("ENABLE_METRICS", false) {
+    errors.push(e);
+  }
+
+  // Match replays
+  if let Err(e) = parse_strict("RECORD_REPLAYS", false) {
+    errors.push(e);
+  }
+  if env::var("REPLAY_DIR").is_ok_and(|dir| dir.is_empty()) {
+    errors.push("REPLAY_DIR: cannot be empty".into());
+  }
+
+  if errors.is_empty() {
+    Ok(())
+  } else {
+    Err(errors)
+  }
+}
+
+///
+/// Get the value of every configuration variable, after the .env files and the command-line options are applied
+///   Secret values are masked and unset variables are left empty
+///
+pub fn get_effective_config() -> Vec<(&'static str, String)> {
+  CONFIG_VARIABLES
+    .iter()
+    .map(|name| {
+      let value = match env::var(name) {
+        Ok(_) if SECRET_VARIABLES.contains(name) => "********".into(),
+        Ok(value) => value,
+        Err(_) => String::new(),
+      };
+      (*name, value)
+    })
+    .collect()
+}
+
 ///
 /// Load the .env files into the current environment
 ///
@@ -138,6 +887,13 @@ pub fn load_environment_from_env_files() {
   }
 }
 
+//
+// Configuration validation
+//
+pub fn is_lenient() -> bool {
+  parse_with_warning("LENIENT", false)
+}
+
 //
 // Basic Server Variables
 //
@@ -149,6 +905,10 @@ pub fn get_port() -> u16 {
   parse_with_warning("PORT", DEFAULT_PORT)
 }
 
+pub fn get_unix_socket() -> Option<PathBuf> {
+  env::var_os("UNIX_SOCKET").map(PathBuf::from)
+}
+
 //
 // HTTPS and SSL/TLS Encryption
 //
@@ -164,6 +924,47 @@ pub fn get_cert_file() -> Option<String> {
   env::var("CERT_FILE").ok()
 }
 
+//
+// Cross-origin resource sharing (CORS)
+//
+/// Get the list of origins allowed to make cross-origin requests
+///   An empty list means only same-origin requests are allowed, and `["*"]` allows any origin
+pub fn get_allowed_origins() -> Vec<String> {
+  let allowed_origins = split_allowed_origins();
+  if allowed_origins.iter().any(|origin| origin == ANY_ORIGIN) {
+    return vec![ANY_ORIGIN.into()];
+  }
+
+  allowed_origins
+    .into_iter()
+    .filter(|origin| {
+      let valid = is_valid_origin(origin);
+      if !valid {
+        log::warn!("ALLOWED_ORIGINS: ignoring invalid origin '{}'", origin);
+      }
+      valid
+    })
+    .collect()
+}
+
+fn split_allowed_origins() -> Vec<String> {
+  env::var("ALLOWED_ORIGINS")
+    .unwrap_or_default()
+    .split(',')
+    .map(str::trim)
+    .filter(|origin| !origin.is_empty())
+    .map(String::from)
+    .collect()
+}
+
+/// Origins must be a scheme and host, like "https://example.com"
+fn is_valid_origin(origin: &str) -> bool {
+  origin == ANY_ORIGIN
+    || Uri::from_str(origin)
+      .map(|uri| uri.scheme().is_some() && uri.host().is_some())
+      .unwrap_or(false)
+}
+
 //
 // JSON Web Token values
 //
@@ -171,6 +972,72 @@ pub fn get_jwt_secret() -> String {
   env::var("JWT_SECRET").unwrap_or_else(|_| DEFAULT_JWT_SECRET.into())
 }
 
+pub fn get_max_token_lifetime_minutes() -> u32 {
+  parse_with_warning("MAX_TOKEN_LIFETIME_MINUTES", DEFAULT_MAX_TOKEN_LIFETIME_MINUTES)
+}
+
+//
+// WebSocket communication
+//
+pub fn get_ws_protocol() -> String {
+  env::var("WS_PROTOCOL").unwrap_or_else(|_| DEFAULT_WS_PROTOCOL.into())
+}
+
+pub fn get_heartbeat_interval_seconds() -> u32 {
+  let heartbeat_interval = parse_with_warning("HEARTBEAT_INTERVAL_SECONDS", DEFAULT_HEARTBEAT_INTERVAL_SECONDS);
+  if heartbeat_interval < 1 {
+    log::warn!("HEARTBEAT_INTERVAL_SECONDS cannot be less than 1, using minimum value '1'");
+    1
+  } else {
+    heartbeat_interval
+  }
+}
+
+pub fn get_heartbeat_timeout_seconds() -> u32 {
+  let heartbeat_interval = get_heartbeat_interval_seconds();
+  let heartbeat_timeout = parse_with_warning("HEARTBEAT_TIMEOUT_SECONDS", DEFAULT_HEARTBEAT_TIMEOUT_SECONDS);
+  if heartbeat_timeout <= heartbeat_interval {
+    log::warn!(
+      "HEARTBEAT_TIMEOUT_SECONDS must be larger than HEARTBEAT_INTERVAL_SECONDS, using value '{}' instead",
+      heartbeat_interval * 2
+    );
+    heartbeat_interval * 2
+  } else {
+    heartbeat_timeout
+  }
+}
+
+pub fn get_player_idle_timeout_seconds() -> u32 {
+  parse_with_warning("PLAYER_IDLE_TIMEOUT_SECONDS", DEFAULT_PLAYER_IDLE_TIMEOUT_SECONDS)
+}
+
+pub fn get_max_messages_per_second() -> u32 {
+  parse_with_warning("MAX_MESSAGES_PER_SECOND", DEFAULT_MAX_MESSAGES_PER_SECOND)
+}
+
+pub fn get_max_message_bytes() -> usize {
+  let max_message_bytes = parse_with_warning("MAX_MESSAGE_BYTES", DEFAULT_MAX_MESSAGE_BYTES);
+  if max_message_bytes < 1 {
+    log::warn!("MAX_MESSAGE_BYTES cannot be less than 1, using minimum value '1'");
+    1
+  } else {
+    max_message_bytes
+  }
+}
+
+/// Largest websocket frame the server will read before closing the connection
+pub fn get_max_frame_bytes() -> usize {
+  get_max_message_bytes().saturating_mul(MAX_FRAME_SIZE_MULTIPLIER)
+}
+
+pub fn use_delta_updates() -> bool {
+  parse_with_warning("USE_DELTA_UPDATES", false)
+}
+
+pub fn use_registration_deltas() -> bool {
+  parse_with_warning("REGISTRATION_DELTAS", false)
+}
+
 //
 // Lua engine code
 //
@@ -178,6 +1045,39 @@ pub fn get_lua_file() -> String {
   env::var("LUA_FILE").unwrap_or_else(|_| DEFAULT_LUA_FILE.into())
 }
 
+/// Get the extra directories to search for Lua modules
+///   Both ";" and the platform path separator (":" on Unix) can separate the directories
+pub fn get_lua_paths() -> Vec<PathBuf> {
+  env::var("LUA_PATHS")
+    .unwrap_or_default()
+    .split(';')
+    .flat_map(|paths| env::split_paths(paths).collect::<Vec<_>>())
+    .filter(|path| !path.as_os_str().is_empty())
+    .collect()
+}
+
+pub fn use_lua_sandbox() -> bool {
+  parse_with_warning("LUA_SANDBOX", true)
+}
+
+pub fn use_strict_game_state() -> bool {
+  parse_with_warning("STRICT_GAME_STATE", false)
+}
+
+pub fn get_max_engine_tries() -> usize {
+  let max_engine_tries = parse_with_warning("MAX_ENGINE_TRIES", DEFAULT_MAX_ENGINE_TRIES);
+  if max_engine_tries < 1 {
+    log::warn!("MAX_ENGINE_TRIES cannot be less than 1, using minimum value '1'");
+    1
+  } else {
+    max_engine_tries
+  }
+}
+
+pub fn get_lua_timeout_ms() -> u64 {
+  parse_with_warning("LUA_TIMEOUT_MS", DEFAULT_LUA_TIMEOUT_MS)
+}
+
 //
 // Game Configuration Variables
 //
@@ -205,6 +1105,36 @@ pub fn get_lobby_wait_time_seconds() -> u32 {
   }
 }
 
+pub fn start_when_full() -> bool {
+  parse_with_warning("START_WHEN_FULL", true)
+}
+
+pub fn fill_with_bots() -> bool {
+  parse_with_warning("FILL_WITH_BOTS", false)
+}
+
+pub fn get_bot_fill_seconds() -> u32 {
+  let bot_fill_seconds = parse_with_warning("BOT_FILL_SECONDS", DEFAULT_BOT_FILL_SECONDS);
+  if bot_fill_seconds < 1 {
+    log::warn!("BOT_FILL_SECONDS cannot be less than 1, using minimum value '1'");
+    1
+  } else {
+    bot_fill_seconds
+  }
+}
+
+pub fn get_connect_grace_seconds() -> u32 {
+  parse_with_warning("CONNECT_GRACE_SECONDS", DEFAULT_CONNECT_GRACE_SECONDS)
+}
+
+pub fn unregister_on_disconnect() -> bool {
+  parse_with_warning("UNREGISTER_ON_DISCONNECT", false)
+}
+
+pub fn reconnect_replaces_old() -> bool {
+  parse_with_warning("RECONNECT_REPLACES_OLD", false)
+}
+
 pub fn get_ticks_per_game() -> u32 {
   let ticks_per_game = parse_with_warning("TICKS_PER_GAME", DEFAULT_TICK_PER_GAME);
   if ticks_per_game < 30 {
@@ -224,3 +1154,58 @@ pub fn get_seconds_per_tick() -> u32 {
     seconds_per_tick
   }
 }
+
+pub fn action_replace_mode() -> bool {
+  parse_with_warning("ACTION_REPLACE_MODE", false)
+}
+
+pub fn get_arena_width() -> u32 {
+  let arena_width = parse_with_warning("ARENA_WIDTH", DEFAULT_ARENA_WIDTH);
+  if arena_width < 1 {
+    log::warn!("ARENA_WIDTH cannot be less than 1, using minimum value '1'");
+    1
+  } else {
+    arena_width
+  }
+}
+
+pub fn get_arena_height() -> u32 {
+  let arena_height = parse_with_warning("ARENA_HEIGHT", DEFAULT_ARENA_HEIGHT);
+  if arena_height < 1 {
+    log::warn!("ARENA_HEIGHT cannot be less than 1, using minimum value '1'");
+    1
+  } else {
+    arena_height
+  }
+}
+
+//
+// Logging and Prometheus metrics
+//
+pub fn get_log_format() -> LogFormat {
+  parse_with_warning("LOG_FORMAT", LogFormat::Pretty)
+}
+
+/// Get the minimum log level, or the given default if LOG_LEVEL isn't set
+pub fn get_log_level(default_level: LevelFilter) -> LevelFilter {
+  parse_with_warning("LOG_LEVEL", default_level)
+}
+
+pub fn enable_metrics() -> bool {
+  parse_with_warning("ENABLE_METRICS", false)
+}
+
+//
+// Match replays
+//
+pub fn record_replays() -> bool {
+  parse_with_warning("RECORD_REPLAYS", false)
+}
+
+pub fn get_replay_dir() -> PathBuf {
+  env::var("REPLAY_DIR")
+    .ok()
+    .filter(|dir| !dir.is_empty())
+    .unwrap_or_else(|| DEFAULT_REPLAY_DIR.into())
+    .into()
+}
@@ -1,4 +1,4 @@
-use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use actix_web::{http::header, http::StatusCode, HttpResponse, ResponseError};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
@@ -16,6 +16,7 @@ pub struct ErrorResponse {
   description: String,
   error_code: GlobalErrorCode,
   developer_notes: Option<String>,
+  retry_after_ms: Option<u64>,
 }
 
 /// Helper type so the JSON has the `"type": "error"` JSON tag
@@ -31,6 +32,9 @@ enum WrappedErrorResponse {
     #[cfg_attr(not(debug_assertions), serde(skip_serializing))]
     #[serde(skip_serializing_if = "Option::is_none")]
     developer_notes: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retry_after_ms: Option<u64>,
   },
 }
 
@@ -41,11 +45,13 @@ impl From<WrappedErrorResponse> for ErrorResponse {
         description,
         error_code,
         developer_notes,
+        retry_after_ms,
       } => Self {
         status_code: StatusCode::INTERNAL_SERVER_ERROR,
         description,
         error_code,
         developer_notes,
+        retry_after_ms,
       },
     }
   }
@@ -57,6 +63,7 @@ impl From<ErrorResponse> for WrappedErrorResponse {
       description: error.description,
       error_code: error.error_code,
       developer_notes: error.developer_notes,
+      retry_after_ms: error.retry_after_ms,
     }
   }
 }
@@ -73,6 +80,15 @@ impl ErrorResponse {
       description,
       error_code,
       developer_notes: Some(developer_notes),
+      retry_after_ms: None,
+    }
+  }
+
+  /// Suggest how long the client should wait before trying again
+  pub fn with_retry_after_ms(self, retry_after_ms: u64) -> Self {
+    Self {
+      retry_after_ms: Some(retry_after_ms),
+      ..self
     }
   }
 
@@ -91,6 +107,10 @@ impl ErrorResponse {
   pub fn get_developer_notes(&self) -> Option<&String> {
     self.developer_notes.as_ref()
   }
+
+  pub fn get_retry_after_ms(&self) -> Option<u64> {
+    self.retry_after_ms
+  }
 }
 
 impl fmt::Display for ErrorResponse {
@@ -105,6 +125,13 @@ impl fmt::Display for ErrorResponse {
 
 impl ResponseError for ErrorResponse {
   fn error_response(&self) -> HttpResponse {
-    HttpResponse::build(self.status_code).json(&self)
+    let mut response = HttpResponse::build(self.status_code);
+
+    // HTTP only supports whole seconds, so round up
+    if let Some(retry_after_ms) = self.retry_after_ms {
+      response.insert_header((header::RETRY_AFTER, retry_after_ms.div_ceil(1000)));
+    }
+
+    response.json(self)
   }
 }
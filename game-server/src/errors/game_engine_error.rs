@@ -6,11 +6,21 @@ use std::io;
 #[derive(Debug)]
 pub enum GameEngineError {
   FailedToReadLuaFile(io::Error),
+  FailedToStartRuntime(io::Error),
   FailedToRunLuaFile(rlua::Error),
   MissingRequiredLuaMethod(&'static str, rlua::Error),
   FailedToRunMethod(&'static str, rlua::Error),
+  LuaTimeout(&'static str, u64),
   JSONToLua(rlua::Error),
   LuaToJSON(rlua::Error),
+  LuaTableTooDeep(usize),
+  InvalidPlayfieldSize {
+    expected: (u32, u32),
+    found: (usize, usize),
+  },
+  InvariantViolation(String),
+  InvalidGameState(String),
+  EngineThreadPanicked,
 }
 
 impl GameEngineError {
@@ -20,6 +30,10 @@ impl GameEngineError {
         format!("Failed to read Lua file: {}", error)
       },
 
+      GameEngineError::FailedToStartRuntime(error) => {
+        format!("Failed to start the runtime for player actions: {}", error)
+      },
+
       GameEngineError::FailedToRunLuaFile(error) => {
         format!("Failed to run Lua file: {}", error)
       },
@@ -32,6 +46,10 @@ impl GameEngineError {
         format!("Failed to run method {}: {}", method, error)
       },
 
+      GameEngineError::LuaTimeout(method, timeout_ms) => {
+        format!("Method {} did not finish within {} ms", method, timeout_ms)
+      },
+
       GameEngineError::JSONToLua(error) => {
         format!("Failed to serialize JSON to Lua value: {}", error)
       },
@@ -39,6 +57,30 @@ impl GameEngineError {
       GameEngineError::LuaToJSON(error) => {
         format!("Failed to serialize Lua to JSON value: {}", error)
       },
+
+      GameEngineError::LuaTableTooDeep(max_depth) => {
+        format!(
+          "Lua table is nested more than {} levels deep (is there a reference cycle?)",
+          max_depth
+        )
+      },
+
+      GameEngineError::InvalidPlayfieldSize { expected, found } => {
+        format!(
+          "Playfield size {}x{} does not match the arena size {}x{} (rows x columns)",
+          found.0, found.1, expected.0, expected.1
+        )
+      },
+
+      GameEngineError::InvariantViolation(why) => {
+        format!("Game state invariant violated: {}", why)
+      },
+
+      GameEngineError::InvalidGameState(why) => {
+        format!("Invalid game state returned by the Lua code: {}", why)
+      },
+
+      GameEngineError::EngineThreadPanicked => "Game engine thread panicked and cannot be restarted".into(),
     }
   }
 }
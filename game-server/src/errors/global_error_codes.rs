@@ -20,4 +20,11 @@ pub enum GlobalErrorCode {
   FailedToUnregister,
   AlreadyConnected,
   CannotSendAction,
+  EngineNotCrashed,
+  RateLimited,
+  MessageTooLarge,
+  InvalidReconnectToken,
+  GameEngineTimeout,
+  NotEnoughPlayers,
+  NotInMatch,
 }
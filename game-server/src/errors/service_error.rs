@@ -9,6 +9,7 @@ use std::{error, fmt};
 use uuid::Uuid;
 
 use crate::errors::*;
+use crate::game::{ServerState, MIN_PLAYERS_PER_GAME};
 
 /// Enumeration of all possible errors that can occur
 #[derive(Debug)]
@@ -20,17 +21,42 @@ pub enum ServiceError {
   QueryStringError(QueryPayloadError),
   JWTError(JWTError),
   JWTExtractorError(AuthenticationError<Bearer>),
+  JWTLifetimeTooLong {
+    max_minutes: u32,
+  },
   MissingWebsocketJWT,
   WebsocketJWTParseError(ToStrError),
   WebsocketError(WebsocketError),
   WebsocketMailboxError(MailboxError),
+  InvalidMessage {
+    message_type: Option<String>,
+    why: String,
+  },
   NotRegistered(Uuid),
   FailedToRegister(Uuid, String),
   FailedToUnregister(Uuid),
   AlreadyConnected(Uuid),
+  InvalidReconnectToken(Uuid),
   GameEngineError(GameEngineError),
   GameEngineCrash,
-  CannotSendAction { why: String },
+  GameEngineTimeout,
+  NotEnoughPlayers {
+    num_players: usize,
+  },
+  NotInMatch(Uuid),
+  InvalidLobbyWait,
+  CannotSendAction {
+    why: String,
+  },
+  EngineNotCrashed(ServerState),
+  RateLimited {
+    retry_after_ms: u64,
+  },
+  MessageTooLarge {
+    max_bytes: usize,
+    received_bytes: Option<usize>,
+  },
+  MetricsError(prometheus::Error),
 }
 
 impl ServiceError {
@@ -85,6 +111,13 @@ impl ServiceError {
         format!("{}", error),
       ),
 
+      ServiceError::JWTLifetimeTooLong { max_minutes } => ErrorResponse::new(
+        StatusCode::UNAUTHORIZED,
+        "Invalid JWT Token".into(),
+        GlobalErrorCode::InvalidJWTToken,
+        format!("Token is valid for longer than the {} minute limit", max_minutes),
+      ),
+
       ServiceError::MissingWebsocketJWT => ErrorResponse::new(
         StatusCode::UNAUTHORIZED,
         "Invalid JWT Token".into(),
@@ -99,6 +132,16 @@ impl ServiceError {
         format!("{}", error),
       ),
 
+      ServiceError::InvalidMessage { message_type, why } => ErrorResponse::new(
+        StatusCode::BAD_REQUEST,
+        match message_type {
+          Some(message_type) => format!("Invalid \"{}\" message: {}", message_type, why),
+          None => format!("Invalid message: {}", why),
+        },
+        GlobalErrorCode::JSONPayloadError,
+        "Check the message against the fields listed in Protocol.md".into(),
+      ),
+
       ServiceError::WebsocketError(error) => ErrorResponse::new(
         StatusCode::INTERNAL_SERVER_ERROR,
         "Unexpected websocket error".into(),
@@ -141,6 +184,13 @@ impl ServiceError {
         format!("Player ID: {}", player_id),
       ),
 
+      ServiceError::InvalidReconnectToken(player_id) => ErrorResponse::new(
+        StatusCode::UNAUTHORIZED,
+        "Reconnection token has already been used".into(),
+        GlobalErrorCode::InvalidReconnectToken,
+        format!("Player ID: {}", player_id),
+      ),
+
       ServiceError::GameEngineError(error) => ErrorResponse::new(
         StatusCode::INTERNAL_SERVER_ERROR,
         "Internal game engine error".into(),
@@ -149,18 +199,81 @@ impl ServiceError {
       ),
 
       ServiceError::GameEngineCrash => ErrorResponse::new(
-        StatusCode::INTERNAL_SERVER_ERROR,
+        StatusCode::SERVICE_UNAVAILABLE,
         "Game engine has crashed".into(),
         GlobalErrorCode::GameEngineCrash,
         "".into(),
       ),
 
+      ServiceError::GameEngineTimeout => ErrorResponse::new(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "Game engine has crashed: the Lua code took too long to run".into(),
+        GlobalErrorCode::GameEngineTimeout,
+        "".into(),
+      ),
+
+      ServiceError::NotEnoughPlayers { num_players } => ErrorResponse::new(
+        StatusCode::CONFLICT,
+        "Game cancelled: not enough players to start a round".into(),
+        GlobalErrorCode::NotEnoughPlayers,
+        format!("Players: {}, Minimum: {}", num_players, MIN_PLAYERS_PER_GAME),
+      ),
+
+      ServiceError::NotInMatch(player_id) => ErrorResponse::new(
+        StatusCode::CONFLICT,
+        "Player is not in the current match".into(),
+        GlobalErrorCode::NotInMatch,
+        format!("Player ID: {}", player_id),
+      ),
+
+      ServiceError::InvalidLobbyWait => ErrorResponse::new(
+        StatusCode::BAD_REQUEST,
+        "Lobby wait time cannot be less than 1 second".into(),
+        GlobalErrorCode::StructValidationError,
+        "".into(),
+      ),
+
       ServiceError::CannotSendAction { why } => ErrorResponse::new(
         StatusCode::CONFLICT,
         format!("Cannot send action: {}", why),
         GlobalErrorCode::CannotSendAction,
         "".into(),
       ),
+
+      ServiceError::EngineNotCrashed(state) => ErrorResponse::new(
+        StatusCode::CONFLICT,
+        "Game engine can only be rebooted after a fatal error".into(),
+        GlobalErrorCode::EngineNotCrashed,
+        format!("Server state: {:?}", state),
+      ),
+
+      ServiceError::RateLimited { retry_after_ms } => ErrorResponse::new(
+        StatusCode::TOO_MANY_REQUESTS,
+        "Too many messages, extra messages are being dropped".into(),
+        GlobalErrorCode::RateLimited,
+        format!("Retry after: {} ms", retry_after_ms),
+      )
+      .with_retry_after_ms(*retry_after_ms),
+
+      ServiceError::MessageTooLarge {
+        max_bytes,
+        received_bytes,
+      } => ErrorResponse::new(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        "Message is too large".into(),
+        GlobalErrorCode::MessageTooLarge,
+        match received_bytes {
+          Some(received_bytes) => format!("Limit: {} bytes, Received: {} bytes", max_bytes, received_bytes),
+          None => format!("Limit: {} bytes, Received: too large to read", max_bytes),
+        },
+      ),
+
+      ServiceError::MetricsError(error) => ErrorResponse::new(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Failed to encode metrics".into(),
+        GlobalErrorCode::UnknownError,
+        format!("{}", error),
+      ),
     }
   }
 }
@@ -15,6 +15,7 @@ pub enum WebsocketError {
   ClientError(WsClientError),
   ProtocolError(WsProtocolError),
   JSONError(serde_json::Error),
+  MsgpackError(rmp_serde::decode::Error),
   UnexpectedFrame(Frame),
   UnsupportedFrameType(String),
   WebsocketClosed,
@@ -49,11 +50,11 @@ impl WebsocketError {
 
   /// Serialize and send JSON data through the steam
   pub async fn send_json<T: Serialize>(stream: &mut Framed<BoxedSocket, Codec>, data: &T) -> Result<(), Self> {
-    let json_string = serde_json::to_string(data).map_err(|e| Self::JSONError(e))?;
+    let json_string = serde_json::to_string(data).map_err(Self::JSONError)?;
     stream
       .send(Message::Text(json_string.into()))
       .await
-      .map_err(|e| Self::ProtocolError(e))
+      .map_err(Self::ProtocolError)
   }
 
   /// Attempts to read the next websocket packet as a JSON data structure.
@@ -66,7 +67,7 @@ impl WebsocketError {
         stream
           .send(Message::Close(Some((CloseCode::Error, format!("{}", e)).into())))
           .await
-          .map_err(|e| Self::ProtocolError(e))?;
+          .map_err(Self::ProtocolError)?;
 
         return Err(Self::ProtocolError(e));
       },
@@ -80,7 +81,7 @@ impl WebsocketError {
     };
 
     // Parse the JSON
-    serde_json::from_slice::<T>(text.as_ref()).map_err(|e| Self::JSONError(e))
+    serde_json::from_slice::<T>(text.as_ref()).map_err(Self::JSONError)
   }
 }
 
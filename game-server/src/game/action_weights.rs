@@ -0,0 +1,61 @@
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::protocol::{actions::*, PlayerAction, TaggedRequest};
+
+/// Relative chance of the random players picking each action
+#[derive(Debug, Clone, Copy)]
+pub struct ActionWeights {
+  move_weight: u32,
+  attack_weight: u32,
+  drop_weight: u32,
+}
+
+impl Default for ActionWeights {
+  fn default() -> Self {
+    Self {
+      move_weight: 5,
+      attack_weight: 5,
+      drop_weight: 2,
+    }
+  }
+}
+
+impl ActionWeights {
+  pub fn new(move_weight: u32, attack_weight: u32, drop_weight: u32) -> Self {
+    Self {
+      move_weight,
+      attack_weight,
+      drop_weight,
+    }
+  }
+
+  /// Test if no action can ever be picked
+  pub fn is_empty(&self) -> bool {
+    self.move_weight == 0 && self.attack_weight == 0 && self.drop_weight == 0
+  }
+
+  /// Randomly pick an action (and random direction if necessary)
+  ///   Panics if the weights are empty
+  pub fn pick_action<R: Rng + ?Sized>(&self, rng: &mut R) -> PlayerAction {
+    let direction = *[Direction::Up, Direction::Down, Direction::Left, Direction::Right]
+      .choose(rng)
+      .unwrap();
+
+    [
+      (
+        TaggedRequest::new(PlayerActionEnum::Move(MoveAction { direction })),
+        self.move_weight,
+      ),
+      (
+        TaggedRequest::new(PlayerActionEnum::Attack(AttackAction { direction })),
+        self.attack_weight,
+      ),
+      (TaggedRequest::new(PlayerActionEnum::DropWeapon), self.drop_weight),
+    ]
+    .choose_weighted(rng, |(_, w)| *w)
+    .unwrap()
+    .clone()
+    .0
+  }
+}
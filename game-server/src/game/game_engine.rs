@@ -0,0 +1,59 @@
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::errors::GameEngineError;
+use crate::game::GamePlayer;
+
+/// Handle to the thread running the Lua game engine
+///   The thread gives back the game player when it stops, so it can be restarted after a fatal error
+#[derive(Clone)]
+pub struct GameEngine(Arc<Mutex<GameEngineThread>>);
+
+enum GameEngineThread {
+  Running(JoinHandle<GamePlayer>),
+  Stopped(Box<GamePlayer>),
+  Panicked,
+}
+
+impl GameEngine {
+  /// Run the game engine on a separate thread
+  pub fn spawn(game_player: GamePlayer) -> Self {
+    Self(Arc::new(Mutex::new(Self::spawn_thread(game_player))))
+  }
+
+  fn spawn_thread(mut game_player: GamePlayer) -> GameEngineThread {
+    GameEngineThread::Running(thread::spawn(move || {
+      game_player.run_game();
+      game_player
+    }))
+  }
+
+  /// Test if the game engine thread is still running
+  pub fn is_alive(&self) -> bool {
+    match *self.0.lock().unwrap() {
+      GameEngineThread::Running(ref handle) => !handle.is_finished(),
+      GameEngineThread::Stopped(_) | GameEngineThread::Panicked => false,
+    }
+  }
+
+  /// Reload the Lua code and start a new game engine thread
+  ///   This should only be called after the game engine has crashed, since it waits for the old thread to stop
+  pub fn restart(&self) -> Result<(), GameEngineError> {
+    let mut engine_thread = self.0.lock().unwrap();
+    let mut game_player = match mem::replace(&mut *engine_thread, GameEngineThread::Panicked) {
+      GameEngineThread::Running(handle) => handle.join().map_err(|_| GameEngineError::EngineThreadPanicked)?,
+      GameEngineThread::Stopped(game_player) => *game_player,
+      GameEngineThread::Panicked => return Err(GameEngineError::EngineThreadPanicked),
+    };
+
+    // Keep the game player around to try again later
+    if let Err(e) = game_player.reload() {
+      *engine_thread = GameEngineThread::Stopped(Box::new(game_player));
+      return Err(e);
+    }
+
+    *engine_thread = Self::spawn_thread(game_player);
+    Ok(())
+  }
+}
@@ -2,41 +2,170 @@ use actix::prelude::*;
 use rlua::prelude::*;
 use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::sync::{mpsc::Receiver, Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use tokio::runtime::{Builder as RuntimeBuilder, Runtime};
+use tokio::sync::mpsc::Receiver as ActionReceiver;
 use uuid::Uuid;
 
-use crate::actors::{shared_messages::*, GameMediatorActor};
+use crate::actors::{mediator_messages::RejectAction, shared_messages::*, GameMediatorActor};
 use crate::config;
 use crate::errors::GameEngineError;
+use crate::jwt::JWTPlayerData;
+use crate::metrics::Metrics;
 use crate::protocol::{game::GameState, PlayerAction};
 
-const MAX_TRIES: usize = 5;
+/// Maximum number of player actions waiting for the game engine
+///   Once the queue is full, new actions are rejected until the engine catches up
+pub const ACTION_QUEUE_CAPACITY: usize = 1024;
+
+/// A round with fewer players would be won before it even starts
+pub const MIN_PLAYERS_PER_GAME: usize = 2;
+
+/// How often the Lua code checks if it has run past LUA_TIMEOUT_MS
+const TIMEOUT_CHECK_INSTRUCTIONS: u32 = 10_000;
+
+/// Removes the Lua globals that can touch the host system (see LUA_SANDBOX)
+///   Requiring pure-Lua modules from "package.path" still works, but C modules cannot be loaded
+const SANDBOX_LUA_CODE: &str = r#"
+  os, io, dofile, loadfile = nil, nil, nil, nil
+  package.loaded.os, package.loaded.io = nil, nil
+  package.loadlib, package.cpath = nil, ""
+  for i = #package.searchers, 3, -1 do
+    table.remove(package.searchers, i)
+  end
+"#;
+
+/// Replaces "pcall" and "xpcall" so they never return more than two values from the C side
+///   rlua's versions push the status onto a full Lua stack when the called function returns many values
+///   (like runaway recursion through "pcall"), which aborts the whole process instead of raising an error
+///   Packing the results into a table keeps the stack small, then they are unpacked again in Lua
+const GUARD_LUA_CODE: &str = r#"
+  local pcall, xpcall, pack, unpack = pcall, xpcall, table.pack, table.unpack
+
+  local function finish(ok, results)
+    if not ok then
+      return false, results
+    end
+    return true, unpack(results, 1, results.n)
+  end
+
+  _G.pcall = function(f, ...)
+    return finish(pcall(function(...) return pack(f(...)) end, ...))
+  end
+
+  _G.xpcall = function(f, handler, ...)
+    return finish(xpcall(function(...) return pack(f(...)) end, handler, ...))
+  end
+"#;
+
+/// Where the Lua game code is loaded from
+enum LuaSource {
+  File(PathBuf),
+  Code(String), // In-memory script, such as in the integration tests
+}
 
 /// Encapsulates the logic of running the Lua game engine on a given thread
 pub struct GamePlayer {
   lua: Lua,
-  recv_start_game: Receiver<Vec<Uuid>>,
-  recv_player_actions: Receiver<(Uuid, PlayerAction)>,
+  lua_source: LuaSource,
+  recv_start_game: Receiver<(Uuid, Vec<Uuid>, HashMap<Uuid, JWTPlayerData>)>,
+  recv_player_actions: ActionReceiver<(Uuid, PlayerAction)>,
+  runtime: Option<Runtime>, // Only used to wait on the player actions, taken when dropped
   mediator_addr: Addr<GameMediatorActor>,
+  metrics: Metrics,
+  max_tries: usize,
+  strict_game_state: bool,
+  lua_timeout_ms: u64,
 
+  match_id: Uuid,
   player_order: Arc<Vec<Uuid>>,
+  player_names: Arc<HashMap<Uuid, String>>,
   players_remaining: Arc<Mutex<HashSet<Uuid>>>,
+  forced_winners: Arc<Mutex<Option<HashSet<Uuid>>>>, // Only set if Lua ends the game early
   ticks_per_game: u32,
   seconds_per_tick: u32,
-  ticks_left: u32,
+  current_tick: u32,
+  ticks_left: Arc<Mutex<u32>>, // Lua can change the time remaining
+  arena_width: u32,
+  arena_height: u32,
 }
 
 #[derive(Clone)]
 struct GamePlayerUserData {
   mediator_addr: Addr<GameMediatorActor>,
+  match_id: Uuid,
   player_order: Arc<Vec<Uuid>>,
+  player_names: Arc<HashMap<Uuid, String>>,
   players_remaining: Arc<Mutex<HashSet<Uuid>>>,
+  forced_winners: Arc<Mutex<Option<HashSet<Uuid>>>>, // Only set if Lua ends the game early
   ticks_per_game: u32,
-  ticks_left: u32,
+  seconds_per_tick: u32,
+  current_tick: u32,
+  ticks_left: Arc<Mutex<u32>>, // Lua can change the time remaining
+  arena_width: u32,
+  arena_height: u32,
+  read_only: bool, // ViewFor() can look at the game, but not change it
+}
+
+/// Add the parent directory of the Lua file and any LUA_PATHS directories to the Lua path
+///   Silently fail on errors
+pub fn add_lua_paths(ctx: LuaContext, lua_file: &Path) {
+  // Modules next to the Lua file are found first
+  if let Some(parent_dir) = lua_file.parent() {
+    add_lua_path(ctx, parent_dir, true);
+  }
+
+  // Shared modules are searched last
+  for lua_path in config::get_lua_paths() {
+    if lua_path.is_dir() {
+      add_lua_path(ctx, &lua_path, false);
+    } else {
+      log::warn!("Lua path directory '{}' does not exist, skipping", lua_path.display());
+    }
+  }
+}
+
+fn add_lua_path(ctx: LuaContext, dir: &Path, prepend: bool) {
+  let dir = match dir.join("?.lua").to_str() {
+    Some(dir) => dir.to_owned(),
+    None => return,
+  };
+
+  log::debug!("Adding directory '{}' to Lua path", dir);
+  let lua_code = if prepend {
+    format!(r#"package.path = [[{};]] .. package.path"#, dir)
+  } else {
+    format!(r#"package.path = package.path .. [[;{}]]"#, dir)
+  };
+
+  if let Err(e) = ctx.load(&lua_code).exec() {
+    log::warn!("Failed to update the Lua path: {}", e);
+  }
+}
+
+/// Remove the dangerous globals from the Lua environment
+///   This must happen before running any untrusted code, or else it could keep its own references
+pub fn sandbox_lua(ctx: LuaContext) -> Result<(), GameEngineError> {
+  log::debug!("Sandboxing the Lua environment");
+  ctx
+    .load(SANDBOX_LUA_CODE)
+    .exec()
+    .map_err(GameEngineError::FailedToRunLuaFile)
+}
+
+/// Make runaway recursion through "pcall" or "xpcall" raise a normal Lua error
+///   Plain recursion is already safe, since Lua raises a "stack overflow" error on its own
+pub fn guard_lua_recursion(ctx: LuaContext) -> Result<(), GameEngineError> {
+  ctx
+    .load(GUARD_LUA_CODE)
+    .exec()
+    .map_err(GameEngineError::FailedToRunLuaFile)
 }
 
 impl GamePlayer {
@@ -44,28 +173,105 @@ impl GamePlayer {
   ///   This validates the lua code when it is loaded
   pub fn new(
     lua_file: impl AsRef<Path>,
-    recv_start_game: Receiver<Vec<Uuid>>,
-    recv_player_actions: Receiver<(Uuid, PlayerAction)>,
+    recv_start_game: Receiver<(Uuid, Vec<Uuid>, HashMap<Uuid, JWTPlayerData>)>,
+    recv_player_actions: ActionReceiver<(Uuid, PlayerAction)>,
     mediator_addr: Addr<GameMediatorActor>,
+    metrics: Metrics,
   ) -> Result<Self, GameEngineError> {
+    let lua_source = LuaSource::File(lua_file.as_ref().to_path_buf());
+    Self::from_source(lua_source, recv_start_game, recv_player_actions, mediator_addr, metrics)
+  }
+
+  /// Construct a new game player object that runs Lua code from a string instead of a file
+  ///   Only the LUA_PATHS directories are searched for modules, since there is no parent directory
+  pub fn from_lua_code(
+    lua_code: impl Into<String>,
+    recv_start_game: Receiver<(Uuid, Vec<Uuid>, HashMap<Uuid, JWTPlayerData>)>,
+    recv_player_actions: ActionReceiver<(Uuid, PlayerAction)>,
+    mediator_addr: Addr<GameMediatorActor>,
+    metrics: Metrics,
+  ) -> Result<Self, GameEngineError> {
+    Self::from_source(
+      LuaSource::Code(lua_code.into()),
+      recv_start_game,
+      recv_player_actions,
+      mediator_addr,
+      metrics,
+    )
+  }
+
+  fn from_source(
+    lua_source: LuaSource,
+    recv_start_game: Receiver<(Uuid, Vec<Uuid>, HashMap<Uuid, JWTPlayerData>)>,
+    recv_player_actions: ActionReceiver<(Uuid, PlayerAction)>,
+    mediator_addr: Addr<GameMediatorActor>,
+    metrics: Metrics,
+  ) -> Result<Self, GameEngineError> {
+    let lua = Self::load_lua(&lua_source)?;
+    let runtime = RuntimeBuilder::new_current_thread()
+      .enable_time()
+      .build()
+      .map_err(GameEngineError::FailedToStartRuntime)?;
+
+    Ok(Self {
+      lua,
+      lua_source,
+      recv_start_game,
+      recv_player_actions,
+      runtime: Some(runtime),
+      mediator_addr,
+      metrics,
+      max_tries: config::get_max_engine_tries(),
+      strict_game_state: config::use_strict_game_state(),
+      lua_timeout_ms: config::get_lua_timeout_ms(),
+      match_id: Uuid::nil(),
+      player_order: Arc::default(),
+      player_names: Arc::default(),
+      players_remaining: Arc::default(),
+      forced_winners: Arc::default(),
+      ticks_per_game: config::get_ticks_per_game(),
+      seconds_per_tick: config::get_seconds_per_tick(),
+      current_tick: 0,
+      ticks_left: Arc::default(),
+      arena_width: config::get_arena_width(),
+      arena_height: config::get_arena_height(),
+    })
+  }
+
+  /// Make sure the Lua file can be loaded, without starting a game engine
+  pub fn validate_lua_file(lua_file: impl AsRef<Path>) -> Result<(), GameEngineError> {
+    Self::load_lua(&LuaSource::File(lua_file.as_ref().to_path_buf())).map(drop)
+  }
+
+  /// Reload the Lua code from scratch, such as after a fatal error
+  ///   Any messages left over from the crashed game are discarded
+  pub fn reload(&mut self) -> Result<(), GameEngineError> {
+    self.lua = Self::load_lua(&self.lua_source)?;
+    self.recv_start_game.try_iter().for_each(drop);
+    while self.recv_player_actions.try_recv().is_ok() {}
+
+    Ok(())
+  }
+
+  /// Create a new Lua state and run the Lua code
+  fn load_lua(lua_source: &LuaSource) -> Result<Lua, GameEngineError> {
     // Read and execute the Lua code
-    let lua_code = fs::read_to_string(&lua_file).map_err(GameEngineError::FailedToReadLuaFile)?;
+    let (lua_file, lua_code) = match lua_source {
+      LuaSource::File(lua_file) => (
+        lua_file.as_path(),
+        fs::read_to_string(lua_file).map_err(GameEngineError::FailedToReadLuaFile)?,
+      ),
+      LuaSource::Code(lua_code) => (Path::new(""), lua_code.clone()),
+    };
 
     let lua = Lua::new();
     lua.context::<_, Result<(), GameEngineError>>(|ctx| {
-      // Add the parent directory (if it exists) to the Lua path
-      //  Silently fail on errors
-      if let Some(parent_dir) = lua_file.as_ref().parent() {
-        if let Some(parent_dir) = parent_dir.join("?.lua").to_str() {
-          log::debug!("Adding directory '{}' to Lua path", parent_dir);
-          if let Err(e) = ctx
-            .load(&format!(r#"package.path = [[{};]] .. package.path"#, parent_dir))
-            .exec()
-          {
-            log::warn!("Failed to update the Lua path: {}", e);
-          }
-        }
+      add_lua_paths(ctx, lua_file);
+
+      if config::use_lua_sandbox() {
+        sandbox_lua(ctx)?;
       }
+      guard_lua_recursion(ctx)?;
 
       // Run the file
       ctx
@@ -86,17 +292,7 @@ impl GamePlayer {
       Ok(())
     })?;
 
-    Ok(Self {
-      lua,
-      recv_start_game,
-      recv_player_actions,
-      mediator_addr,
-      player_order: Arc::default(),
-      players_remaining: Arc::default(),
-      ticks_per_game: config::get_ticks_per_game(),
-      seconds_per_tick: config::get_seconds_per_tick(),
-      ticks_left: 0,
-    })
+    Ok(lua)
   }
 
   /// Get the user data from the game state
@@ -104,10 +300,27 @@ impl GamePlayer {
   fn get_user_data(&self) -> GamePlayerUserData {
     GamePlayerUserData {
       mediator_addr: self.mediator_addr.clone(),
+      match_id: self.match_id,
       player_order: self.player_order.clone(),
+      player_names: self.player_names.clone(),
       players_remaining: self.players_remaining.clone(),
+      forced_winners: self.forced_winners.clone(),
       ticks_per_game: self.ticks_per_game,
-      ticks_left: self.ticks_left,
+      seconds_per_tick: self.seconds_per_tick,
+      current_tick: self.current_tick,
+      ticks_left: self.ticks_left.clone(),
+      arena_width: self.arena_width,
+      arena_height: self.arena_height,
+      read_only: false,
+    }
+  }
+
+  /// Context for ViewFor(), which can't kill players or change the game clock
+  ///   Otherwise it could end the round after the tick already decided the game keeps going
+  fn get_read_only_user_data(&self) -> GamePlayerUserData {
+    GamePlayerUserData {
+      read_only: true,
+      ..self.get_user_data()
     }
   }
 
@@ -115,7 +328,9 @@ impl GamePlayer {
   pub fn run_game(&mut self) {
     if let Err(e) = self.run_internal() {
       log::error!("Fatal error: {}", e.get_developer_notes());
-      self.mediator_addr.do_send(GameEngineCrash);
+      self.mediator_addr.do_send(GameEngineCrash {
+        timed_out: matches!(e, GameEngineError::LuaTimeout(..)),
+      });
     }
   }
 
@@ -123,8 +338,23 @@ impl GamePlayer {
   ///
   /// 1. There are 2 or more players left in the game
   /// 2. AND there is time left on the clock
+  /// 3. AND the Lua code has not ended the game early
   fn is_round_running(&self) -> bool {
-    self.ticks_left > 0 && self.players_remaining.lock().unwrap().len() > 1
+    self.get_ticks_left() > 0
+      && self.players_remaining.lock().unwrap().len() > 1
+      && self.forced_winners.lock().unwrap().is_none()
+  }
+
+  fn get_ticks_left(&self) -> u32 {
+    *self.ticks_left.lock().unwrap()
+  }
+
+  /// Winners picked by the Lua code, or else all players still alive
+  fn get_winners(&self) -> HashSet<Uuid> {
+    match *self.forced_winners.lock().unwrap() {
+      Some(ref winners) => winners.clone(),
+      None => self.players_remaining.lock().unwrap().clone(),
+    }
   }
 
   ///
@@ -134,56 +364,83 @@ impl GamePlayer {
     log::info!("Waiting for players to register for the game");
     loop {
       // Wait for the mediator to say the game is ready to start
-      let player_order = match self.recv_start_game.recv() {
-        Ok(order) => order,
+      let (match_id, player_order, players) = match self.recv_start_game.recv() {
+        Ok(start) => start,
         Err(_) => {
           log::info!("Channel closed: 'start_game', stopping game engine...");
           return Ok(());
         },
       };
 
+      // The mediator should never send this, but a round with one player would end right away
+      if player_order.len() < MIN_PLAYERS_PER_GAME {
+        log::warn!(match_id:% = match_id; "Only {} player(s) in the game, refusing to start", player_order.len());
+        self.mediator_addr.do_send(GameCancelled {
+          num_players: player_order.len(),
+        });
+        continue;
+      }
+
       // Initialize the game!
-      log::info!("Initializing game engine...");
-      let initial_state = Self::trap_errors(MAX_TRIES, || self.init_game(&player_order))?;
-      self
-        .mediator_addr
-        .do_send(Init::new(initial_state, self.ticks_left, self.seconds_per_tick));
+      log::info!(match_id:% = match_id; "Initializing game engine...");
+      self.match_id = match_id;
+      let initial_state = Self::trap_errors(self.max_tries, || self.init_game(&player_order, &players))?;
+      let player_views = Self::trap_errors(self.max_tries, || self.view_for_players(&initial_state))?;
+      self.mediator_addr.do_send(
+        Init::new(
+          self.match_id,
+          &self.player_order,
+          initial_state,
+          self.get_ticks_left(),
+          self.seconds_per_tick,
+        )
+        .with_player_views(player_views),
+      );
+
+      // Ticks are scheduled from fixed deadlines, so the time spent in Lua doesn't add up over a long game
+      let tick_period = Duration::from_secs(self.seconds_per_tick as u64);
+      let mut next_tick = Instant::now() + tick_period;
 
       // Run until there is no time left
       while self.is_round_running() {
-        // Sleep for "seconds_per_tick" seconds before running the next tick
-        thread::sleep(Duration::from_secs(self.seconds_per_tick as u64));
-        self.ticks_left -= 1;
+        // Wait until the next deadline before running the next tick
+        let player_actions = self.collect_player_actions(next_tick)?;
+        next_tick = Self::next_deadline(next_tick, tick_period);
+        self.current_tick += 1;
+        *self.ticks_left.lock().unwrap() -= 1;
+        let ticks_left = self.get_ticks_left();
         log::info!(
           "Game engine running - {} tick{} remaining",
-          self.ticks_left,
-          if self.ticks_left == 1 { "" } else { "s" }
+          ticks_left,
+          if ticks_left == 1 { "" } else { "s" }
         );
 
-        // Read the list of player actions from the channel
-        //  Filter any actions for players that have died (just to be extra safe)
-        let players_remaining = self.players_remaining.lock().unwrap();
-        let player_actions: HashMap<_, _> = self
-          .recv_player_actions
-          .try_iter()
-          .filter(|(id, _)| players_remaining.contains(id))
-          .collect();
-        drop(players_remaining);
-
         // Update the game state
-        let next_state = Self::trap_errors(MAX_TRIES, || self.tick_game(&player_actions))?;
+        let tick_timer = self.metrics.tick_duration_seconds.start_timer();
+        let next_state = Self::trap_errors(self.max_tries, || self.tick_game(&player_actions))?;
+        tick_timer.observe_duration();
 
         // Notify the mediator of the change
+        //  The final game state is shown to everyone, so the views are only needed while the game is running
         if self.is_round_running() {
-          self.mediator_addr.do_send(NextState::new(
-            next_state,
-            player_actions,
-            self.ticks_left,
-            self.seconds_per_tick,
-          ));
+          let player_views = Self::trap_errors(self.max_tries, || self.view_for_players(&next_state))?;
+          let state_hash = next_state.state_hash();
+          self.mediator_addr.do_send(
+            NextState::new(
+              self.match_id,
+              next_state,
+              player_actions,
+              self.current_tick,
+              self.get_ticks_left(),
+              self.seconds_per_tick,
+              state_hash,
+            )
+            .with_player_views(player_views),
+          );
         } else {
           self.mediator_addr.do_send(GameEnded::new(
-            self.players_remaining.lock().unwrap().clone(),
+            self.match_id,
+            self.get_winners(),
             next_state,
             player_actions,
           ));
@@ -197,29 +454,177 @@ impl GamePlayer {
   ///
   /// Handle game initialization with the given player order
   ///
-  fn init_game(&mut self, player_order: &Vec<Uuid>) -> Result<GameState, GameEngineError> {
+  fn init_game(
+    &mut self,
+    player_order: &[Uuid],
+    players: &HashMap<Uuid, JWTPlayerData>,
+  ) -> Result<GameState, GameEngineError> {
     // Initialize game player variables
-    self.player_order = Arc::new(player_order.clone());
-    self.ticks_left = self.ticks_per_game;
+    self.player_order = Arc::new(player_order.to_vec());
+    self.player_names = Arc::new(
+      players
+        .iter()
+        .map(|(id, data)| (*id, data.get_name().clone()))
+        .collect(),
+    );
+    self.current_tick = 0;
+    self.ticks_left = Arc::new(Mutex::new(self.ticks_per_game));
     self.players_remaining = Arc::new(Mutex::new(player_order.iter().cloned().collect()));
+    self.forced_winners = Arc::default();
 
     // Run the Lua Init() method and return the initial game state as JSON
-    self.lua.context::<_, Result<_, GameEngineError>>(|ctx| {
-      let init = ctx
-        .globals()
-        .get::<_, LuaFunction>("Init")
-        .map_err(|e| GameEngineError::MissingRequiredLuaMethod("Init", e))?;
+    self.with_timeout("Init", || {
+      self.lua.context::<_, Result<_, GameEngineError>>(|ctx| {
+        let init = ctx
+          .globals()
+          .get::<_, LuaFunction>("Init")
+          .map_err(|e| GameEngineError::MissingRequiredLuaMethod("Init", e))?;
+
+        let user_data = self.get_user_data();
+        let player_order: Vec<_> = self.player_order.iter().map(Uuid::to_string).collect();
+        let players: HashMap<String, LuaValue> = players
+          .iter()
+          .map(|(id, data)| {
+            let id = id.to_string();
+            let value = rlua_serde::to_value(ctx, data).map_err(GameEngineError::JSONToLua)?;
+            Ok((id, value))
+          })
+          .collect::<Result<_, _>>()?;
+
+        let lua_game_state = init
+          .call::<_, LuaValue>((user_data, player_order, players))
+          .map_err(|e| GameEngineError::FailedToRunMethod("Init", e))?;
+
+        let json_game_state = GameState::from_lua(lua_game_state, self.strict_game_state)?;
+        json_game_state.validate_playfield_size(self.arena_height, self.arena_width)?;
+
+        Ok(json_game_state)
+      })
+    })
+  }
+
+  ///
+  /// Get the deadline for the tick after the given deadline
+  ///   If the engine fell more than a whole tick behind, the missed ticks are skipped
+  ///   instead of running them back-to-back (which wouldn't give players any time to act)
+  ///
+  fn next_deadline(deadline: Instant, tick_period: Duration) -> Instant {
+    let mut next_deadline = deadline + tick_period;
+    let now = Instant::now();
+    if next_deadline <= now {
+      log::warn!("Game engine fell behind, skipping ahead to the next tick");
+      while next_deadline <= now {
+        next_deadline += tick_period;
+      }
+    }
 
-      let user_data = self.get_user_data();
-      let player_order: Vec<_> = self.player_order.iter().map(Uuid::to_string).collect();
+    next_deadline
+  }
 
-      let lua_game_state = init
-        .call::<_, LuaValue>((user_data, player_order))
-        .map_err(|e| GameEngineError::FailedToRunMethod("Init", e))?;
+  ///
+  /// Read the player actions from the channel until the deadline for the next tick
+  ///   Actions rejected by the Lua ValidateAction() method are reported right away,
+  ///   so the player still has a chance to send a different action during the tick
+  ///
+  fn collect_player_actions(&mut self, deadline: Instant) -> Result<HashMap<Uuid, PlayerAction>, GameEngineError> {
+    let mut player_actions = HashMap::new();
+
+    while let Some(time_left) = deadline.checked_duration_since(Instant::now()) {
+      // The timer must be created inside the runtime
+      let recv_player_actions = &mut self.recv_player_actions;
+      let next_action = self
+        .runtime
+        .as_ref()
+        .expect("runtime is only taken when dropped")
+        .block_on(async { tokio::time::timeout(time_left, recv_player_actions.recv()).await });
+
+      let (player_id, action) = match next_action {
+        Ok(Some(player_action)) => player_action,
+        Err(_) => break, // Time for the next tick
+        Ok(None) => {
+          thread::sleep(time_left);
+          break;
+        },
+      };
+
+      // Filter any actions for players that have died (just to be extra safe)
+      if !self.players_remaining.lock().unwrap().contains(&player_id) {
+        continue;
+      }
+
+      if Self::trap_errors(self.max_tries, || self.validate_action(&player_id, &action))? {
+        player_actions.insert(player_id, action);
+      } else {
+        log::debug!(player_id:% = player_id; "Player action rejected by the game engine");
+        self.mediator_addr.do_send(RejectAction {
+          player_id,
+          tag: action.tag,
+        });
+      }
+    }
+
+    Ok(player_actions)
+  }
 
-      let json_game_state: GameState = rlua_serde::from_value(lua_game_state).map_err(GameEngineError::LuaToJSON)?;
+  ///
+  /// Run the optional Lua ValidateAction() method to check if the player is allowed to take the action
+  ///   Every action is allowed if the method isn't defined
+  ///
+  fn validate_action(&self, player_id: &Uuid, action: &PlayerAction) -> Result<bool, GameEngineError> {
+    self.with_timeout("ValidateAction", || {
+      self.lua.context(|ctx| {
+        let validate_action = ctx
+          .globals()
+          .get::<_, Option<LuaFunction>>("ValidateAction")
+          .map_err(|e| GameEngineError::FailedToRunMethod("ValidateAction", e))?;
+
+        let validate_action = match validate_action {
+          Some(validate_action) => validate_action,
+          None => return Ok(true),
+        };
+
+        let action = rlua_serde::to_value(ctx, action).map_err(GameEngineError::JSONToLua)?;
+        validate_action
+          .call::<_, bool>((self.get_user_data(), player_id.to_string(), action))
+          .map_err(|e| GameEngineError::FailedToRunMethod("ValidateAction", e))
+      })
+    })
+  }
 
-      Ok(json_game_state)
+  ///
+  /// Run the optional Lua ViewFor() method to get the game state that each player is allowed to see
+  ///   Returns an empty map if the method isn't defined, so every player sees the full game state
+  ///
+  fn view_for_players(&self, game_state: &GameState) -> Result<PlayerViews, GameEngineError> {
+    self.with_timeout("ViewFor", || {
+      self.lua.context(|ctx| {
+        let view_for = ctx
+          .globals()
+          .get::<_, Option<LuaFunction>>("ViewFor")
+          .map_err(|e| GameEngineError::FailedToRunMethod("ViewFor", e))?;
+
+        let view_for = match view_for {
+          Some(view_for) => view_for,
+          None => return Ok(PlayerViews::new()),
+        };
+
+        self
+          .player_order
+          .iter()
+          .map(|player_id| {
+            // Every call gets a fresh copy, in case the Lua code modifies the state
+            let lua_game_state = rlua_serde::to_value(ctx, game_state).map_err(GameEngineError::JSONToLua)?;
+            let lua_view = view_for
+              .call::<_, LuaValue>((self.get_read_only_user_data(), lua_game_state, player_id.to_string()))
+              .map_err(|e| GameEngineError::FailedToRunMethod("ViewFor", e))?;
+
+            let view = GameState::from_lua(lua_view, self.strict_game_state)?;
+            view.validate_playfield_size(self.arena_height, self.arena_width)?;
+
+            Ok((*player_id, Arc::new(view)))
+          })
+          .collect()
+      })
     })
   }
 
@@ -228,30 +633,68 @@ impl GamePlayer {
   ///
   /// Does NOT handle the logic for "seconds left"
   fn tick_game(&mut self, player_actions: &HashMap<Uuid, PlayerAction>) -> Result<GameState, GameEngineError> {
-    self.lua.context(|ctx| {
-      let player_actions: HashMap<String, LuaValue> = player_actions
-        .iter()
-        .map(|(id, action)| {
-          let id = id.to_string();
-          let value = rlua_serde::to_value(ctx, action).map_err(GameEngineError::JSONToLua)?;
-          Ok((id, value))
-        })
-        .collect::<Result<_, _>>()?;
-
-      let update = ctx
-        .globals()
-        .get::<_, LuaFunction>("Update")
-        .map_err(|e| GameEngineError::MissingRequiredLuaMethod("Update", e))?;
+    self.with_timeout("Update", || {
+      self.lua.context(|ctx| {
+        let player_actions: HashMap<String, LuaValue> = player_actions
+          .iter()
+          .map(|(id, action)| {
+            let id = id.to_string();
+            let value = rlua_serde::to_value(ctx, action).map_err(GameEngineError::JSONToLua)?;
+            Ok((id, value))
+          })
+          .collect::<Result<_, _>>()?;
+
+        let update = ctx
+          .globals()
+          .get::<_, LuaFunction>("Update")
+          .map_err(|e| GameEngineError::MissingRequiredLuaMethod("Update", e))?;
+
+        let user_data = self.get_user_data();
+        let lua_game_state = update
+          .call::<_, LuaValue>((user_data, player_actions))
+          .map_err(|e| GameEngineError::FailedToRunMethod("Update", e))?;
+
+        let json_game_state = GameState::from_lua(lua_game_state, self.strict_game_state)?;
+        json_game_state.validate_playfield_size(self.arena_height, self.arena_width)?;
+
+        Ok(json_game_state)
+      })
+    })
+  }
 
-      let user_data = self.get_user_data();
-      let lua_game_state = update
-        .call::<_, LuaValue>((user_data, player_actions))
-        .map_err(|e| GameEngineError::FailedToRunMethod("Update", e))?;
+  /// Run a Lua method, stopping it with a LuaTimeout error if it runs longer than LUA_TIMEOUT_MS
+  fn with_timeout<F, R>(&self, method: &'static str, func: F) -> Result<R, GameEngineError>
+  where
+    F: FnOnce() -> Result<R, GameEngineError>,
+  {
+    if self.lua_timeout_ms == 0 {
+      return func();
+    }
 
-      let json_game_state: GameState = rlua_serde::from_value(lua_game_state).map_err(GameEngineError::LuaToJSON)?;
+    // The hook keeps raising errors past the deadline, so the Lua code can't just pcall() it away
+    let deadline = Instant::now() + Duration::from_millis(self.lua_timeout_ms);
+    let timed_out = Arc::new(AtomicBool::new(false));
+    let hook_timed_out = timed_out.clone();
+    let triggers = LuaHookTriggers {
+      every_nth_instruction: Some(TIMEOUT_CHECK_INSTRUCTIONS),
+      ..Default::default()
+    };
+    self.lua.set_hook(triggers, move |_, _| {
+      if Instant::now() < deadline {
+        return Ok(());
+      }
 
-      Ok(json_game_state)
-    })
+      hook_timed_out.store(true, Ordering::Relaxed);
+      Err(LuaError::RuntimeError("Lua method timed out".into()))
+    });
+
+    let result = func();
+    self.lua.remove_hook();
+
+    if timed_out.load(Ordering::Relaxed) {
+      return Err(GameEngineError::LuaTimeout(method, self.lua_timeout_ms));
+    }
+    result
   }
 
   /// Helper function to retry a given number of times before throwing an error
@@ -264,7 +707,7 @@ impl GamePlayer {
       match func() {
         Ok(r) => return Ok(r),
         Err(e) => {
-          tries = tries + 1;
+          tries += 1;
           log::error!(
             "Game engine error: {} (Attempt {} / {})",
             e.get_developer_notes(),
@@ -272,7 +715,8 @@ impl GamePlayer {
             max_tries
           );
 
-          if tries >= max_tries {
+          // Running a method that hangs again would only hold up the game even longer
+          if tries >= max_tries || matches!(e, GameEngineError::LuaTimeout(..)) {
             return Err(e);
           }
         },
@@ -281,20 +725,87 @@ impl GamePlayer {
   }
 }
 
+impl Drop for GamePlayer {
+  /// The game player can be dropped inside the Actix runtime (like when the server stops after an engine crash),
+  ///   where Tokio refuses to block while shutting down the action runtime
+  fn drop(&mut self) {
+    if let Some(runtime) = self.runtime.take() {
+      runtime.shutdown_background();
+    }
+  }
+}
+
+impl GamePlayerUserData {
+  /// Round up, so the game never ends sooner than Lua asked for
+  fn seconds_to_ticks(&self, seconds: u32) -> u32 {
+    seconds.div_ceil(self.seconds_per_tick)
+  }
+
+  fn check_writable(&self, method: &str) -> LuaResult<()> {
+    if self.read_only {
+      return Err(LuaError::RuntimeError(format!(
+        "{}() cannot be called from ViewFor()",
+        method
+      )));
+    }
+    Ok(())
+  }
+}
+
 //
 // Helper context methods that get passed into Lua
 //
 impl LuaUserData for GamePlayerUserData {
   fn add_methods<'lua, M: LuaUserDataMethods<'lua, Self>>(methods: &mut M) {
     methods.add_method("notifyPlayerKilled", |_, this, uuid: String| {
+      this.check_writable("notifyPlayerKilled")?;
       let player_id: Uuid = Uuid::from_str(&uuid).map_err(|_| LuaError::RuntimeError("Invalid UUID".into()))?;
 
+      // Catch Lua bugs that pass the wrong ID, instead of broadcasting a bogus message
+      if !this.player_order.contains(&player_id) {
+        let valid_ids: Vec<_> = this.player_order.iter().map(Uuid::to_string).collect();
+        log::warn!(
+          "notifyPlayerKilled() called with unknown player {} (valid players: {})",
+          player_id,
+          valid_ids.join(", ")
+        );
+        return Err(LuaError::RuntimeError(format!(
+          "Player {} is not in the game",
+          player_id
+        )));
+      }
+
       // Update the internal list of players remaining
       this.players_remaining.lock().unwrap().remove(&player_id);
 
       // Also notify the mediator
-      this.mediator_addr.do_send(PlayerKilled::new(player_id));
+      this.mediator_addr.do_send(PlayerKilled::new(this.match_id, player_id));
+
+      Ok(())
+    });
 
+    methods.add_method("forceGameEnd", |_, this, winners: Vec<String>| {
+      this.check_writable("forceGameEnd")?;
+      if this.current_tick == 0 {
+        return Err(LuaError::RuntimeError(
+          "forceGameEnd() can only be called from Update()".into(),
+        ));
+      }
+
+      let mut winner_ids = HashSet::with_capacity(winners.len());
+      for uuid in winners.iter() {
+        let player_id: Uuid = Uuid::from_str(uuid).map_err(|_| LuaError::RuntimeError("Invalid UUID".into()))?;
+        if !this.player_order.contains(&player_id) {
+          return Err(LuaError::RuntimeError(format!(
+            "Player {} is not in the game",
+            player_id
+          )));
+        }
+        winner_ids.insert(player_id);
+      }
+
+      // The game loop stops once the current tick finishes
+      *this.forced_winners.lock().unwrap() = Some(winner_ids);
       Ok(())
     });
 
@@ -302,6 +813,11 @@ impl LuaUserData for GamePlayerUserData {
       Ok(this.player_order.iter().map(Uuid::to_string).collect::<Vec<_>>())
     });
 
+    methods.add_method("getPlayerName", |_, this, uuid: String| {
+      let player_id: Uuid = Uuid::from_str(&uuid).map_err(|_| LuaError::RuntimeError("Invalid UUID".into()))?;
+      Ok(this.player_names.get(&player_id).cloned())
+    });
+
     methods.add_method("getPlayersRemaining", |_, this, _: ()| {
       Ok(
         this
@@ -315,7 +831,31 @@ impl LuaUserData for GamePlayerUserData {
     });
 
     methods.add_method("getTicksLeft", |_, this, _: ()| {
-      Ok((this.ticks_left, this.ticks_per_game))
+      Ok((*this.ticks_left.lock().unwrap(), this.ticks_per_game))
+    });
+
+    methods.add_method("setTimeRemaining", |_, this, seconds: u32| {
+      this.check_writable("setTimeRemaining")?;
+      let ticks = this.seconds_to_ticks(seconds);
+      if ticks == 0 && this.current_tick == 0 {
+        return Err(LuaError::RuntimeError("Cannot end the game from Init()".into()));
+      }
+
+      *this.ticks_left.lock().unwrap() = ticks;
+      Ok(())
+    });
+
+    methods.add_method("addTime", |_, this, seconds: u32| {
+      this.check_writable("addTime")?;
+      let mut ticks_left = this.ticks_left.lock().unwrap();
+      *ticks_left = ticks_left.saturating_add(this.seconds_to_ticks(seconds));
+      Ok(())
+    });
+
+    methods.add_method("getCurrentTick", |_, this, _: ()| Ok(this.current_tick));
+
+    methods.add_method("getArenaSize", |_, this, _: ()| {
+      Ok((this.arena_height, this.arena_width))
     });
   }
 }
@@ -1,8 +1,14 @@
 //
 // Data types needed for running the game
 //
+mod action_weights;
+mod game_engine;
 mod game_player;
 mod server_state;
 
-pub use game_player::GamePlayer;
+pub use action_weights::ActionWeights;
+pub use game_engine::GameEngine;
+pub use game_player::{
+  add_lua_paths, guard_lua_recursion, sandbox_lua, GamePlayer, ACTION_QUEUE_CAPACITY, MIN_PLAYERS_PER_GAME,
+};
 pub use server_state::ServerState;
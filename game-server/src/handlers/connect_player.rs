@@ -1,55 +1,132 @@
 use actix::Addr;
 use actix_web::{web, HttpRequest, HttpResponse};
 use actix_web_actors::ws::WsResponseBuilder;
-use std::sync::mpsc::Sender;
+use chrono::Duration;
+use tokio::sync::mpsc::Sender;
 use uuid::Uuid;
 
-use crate::actors::mediator_messages::{Connect, ConnectResponse};
+use crate::actors::mediator_messages::{Connect, ConnectResponse, GetServerState, GetServerStateResponse, Reconnect};
+use crate::actors::websocket_messages::SendReconnectToken;
 use crate::actors::{GameMediatorActor, WebsocketActor};
+use crate::config;
 use crate::errors::{ServiceError, WebsocketError};
-use crate::jwt::PlayerWebsocketToken;
-use crate::protocol::PlayerAction;
-use crate::WS_PROTOCOL;
+use crate::game::ServerState;
+use crate::jwt::{
+  JWTPlayerData, JWTSecret, PlayerWebsocketToken, ReconnectData, ReconnectToken, RECONNECT_EXPIRATION_MIN,
+};
+use crate::protocol::{ConnectQuery, PlayerAction};
 
 pub async fn connect_player(
   token: PlayerWebsocketToken,
   mediator: web::Data<Addr<GameMediatorActor>>,
   send_player_actions: web::Data<Sender<(Uuid, PlayerAction)>>,
+  jwt_secret: web::Data<JWTSecret>,
+  query: web::Query<ConnectQuery>,
   req: HttpRequest,
   payload: web::Payload,
 ) -> Result<HttpResponse, ServiceError> {
-  let player_id = token.get_id();
-  let player_name = token.get_data().get_name().clone();
+  let token = token.into_inner();
+  start_player_connection(
+    token.get_id(),
+    token.into_data(),
+    None,
+    mediator,
+    send_player_actions,
+    jwt_secret,
+    query,
+    req,
+    payload,
+  )
+  .await
+}
+
+/// Start the websocket actor for a player client and connect it with the mediator
+///   If a reconnection nonce is given, the mediator only accepts the connection if it matches
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn start_player_connection(
+  player_id: Uuid,
+  player_data: JWTPlayerData,
+  reconnect_nonce: Option<Uuid>,
+  mediator: web::Data<Addr<GameMediatorActor>>,
+  send_player_actions: web::Data<Sender<(Uuid, PlayerAction)>>,
+  jwt_secret: web::Data<JWTSecret>,
+  query: web::Query<ConnectQuery>,
+  req: HttpRequest,
+  payload: web::Payload,
+) -> Result<HttpResponse, ServiceError> {
+  let ws_protocol = config::get_ws_protocol();
+  let connection_id = Uuid::new_v4();
+
+  // Refuse the connection before upgrading, so clients get a clear signal to back off
+  let GetServerStateResponse(state) = mediator
+    .send(GetServerState)
+    .await
+    .map_err(ServiceError::WebsocketMailboxError)?;
+  if state == ServerState::FatalError {
+    return Err(ServiceError::GameEngineCrash);
+  }
 
   // Start the websocket actor to manage the communication
-  log::debug!("Connecting player \"{}\" (ID: {})", player_name, player_id);
-  log::debug!("Starting actor to handle websocket communication...");
+  log::debug!(player_id:% = player_id, connection_id:% = connection_id; "Connecting player \"{}\" (ID: {})", player_data.get_name(), player_id);
+  log::debug!(player_id:% = player_id, connection_id:% = connection_id; "Starting actor to handle websocket communication...");
   let (addr, response) = WsResponseBuilder::new(
     WebsocketActor::new(
-      token.into_inner(),
+      player_id,
+      connection_id,
+      player_data.clone(),
       mediator.as_ref().clone(),
       send_player_actions.as_ref().clone(),
+      query.encoding,
     ),
     &req,
     payload,
   )
-  .protocols(&[WS_PROTOCOL])
+  .protocols(&[&ws_protocol])
+  .frame_size(config::get_max_frame_bytes())
   .start_with_addr()
   .map_err(|e| ServiceError::WebsocketError(WebsocketError::from(e)))?;
 
   // Register the actor with the mediator -- might return an error
-  log::debug!("Registering actor with the game mediator...");
-  let connect_response = mediator
-    .send(Connect(player_id, addr.clone()))
-    .await
-    .map_err(ServiceError::WebsocketMailboxError)?;
+  log::debug!(player_id:% = player_id, connection_id:% = connection_id; "Registering actor with the game mediator...");
+  let connect_response = match reconnect_nonce {
+    Some(nonce) => {
+      mediator
+        .send(Reconnect {
+          id: player_id,
+          nonce,
+          addr: addr.clone(),
+        })
+        .await
+    },
+    None => mediator.send(Connect(player_id, addr.clone())).await,
+  }
+  .map_err(ServiceError::WebsocketMailboxError)?;
 
   // The message handler will close the actor if there is an error
   addr.do_send(connect_response);
-  match connect_response {
-    ConnectResponse::Ok(_) => {},
+  let nonce = match connect_response {
+    ConnectResponse::Ok(_, nonce) => nonce,
     ConnectResponse::NotRegistered => return Err(ServiceError::NotRegistered(player_id)),
     ConnectResponse::AlreadyConnected => return Err(ServiceError::AlreadyConnected(player_id)),
+    ConnectResponse::InvalidReconnectToken => return Err(ServiceError::InvalidReconnectToken(player_id)),
+  };
+
+  // Give the client a short-lived token it can use to reconnect, instead of the full player JWT
+  //  (Never longer than the server allows, or the token would be rejected)
+  let expiration_min = match config::get_max_token_lifetime_minutes() as i64 {
+    0 => RECONNECT_EXPIRATION_MIN,
+    max_minutes => RECONNECT_EXPIRATION_MIN.min(max_minutes),
+  };
+  let reconnect_token = ReconnectToken::new(
+    player_id,
+    Duration::minutes(expiration_min),
+    ReconnectData::new(nonce, player_data),
+  );
+  match reconnect_token.encode(&jwt_secret.get_encoding_key()) {
+    Ok(token) => addr.do_send(SendReconnectToken(token)),
+    Err(e) => {
+      log::error!(player_id:% = player_id, connection_id:% = connection_id; "Failed to encode the reconnection token: {}", e)
+    },
   }
 
   // Connection is golden!
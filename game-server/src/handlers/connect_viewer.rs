@@ -1,31 +1,51 @@
 use actix::Addr;
 use actix_web::{web, HttpRequest, HttpResponse};
 use actix_web_actors::ws::WsResponseBuilder;
+use uuid::Uuid;
 
-use crate::actors::mediator_messages::ConnectViewer;
+use crate::actors::mediator_messages::{ConnectViewer, GetServerState, GetServerStateResponse};
 use crate::actors::{GameMediatorActor, ViewerActor};
+use crate::config;
 use crate::errors::{ServiceError, WebsocketError};
+use crate::game::ServerState;
 use crate::jwt::ViewerWebsocketToken;
-use crate::WS_PROTOCOL;
+use crate::protocol::ConnectQuery;
 
 pub async fn connect_viewer(
   token: ViewerWebsocketToken,
   mediator: web::Data<Addr<GameMediatorActor>>,
+  query: web::Query<ConnectQuery>,
   req: HttpRequest,
   payload: web::Payload,
 ) -> Result<HttpResponse, ServiceError> {
   let viewer_id = token.get_id();
+  let connection_id = Uuid::new_v4();
+  let ws_protocol = config::get_ws_protocol();
+
+  // Refuse the connection before upgrading, so clients get a clear signal to back off
+  let GetServerStateResponse(state) = mediator
+    .send(GetServerState)
+    .await
+    .map_err(ServiceError::WebsocketMailboxError)?;
+  if state == ServerState::FatalError {
+    return Err(ServiceError::GameEngineCrash);
+  }
 
   // Start the websocket actor to manage the communication
-  log::debug!("Connecting viewer {}", viewer_id);
-  log::debug!("Starting actor to handle websocket communication...");
-  let (addr, response) = WsResponseBuilder::new(ViewerActor::new(viewer_id, mediator.as_ref().clone()), &req, payload)
-    .protocols(&[WS_PROTOCOL])
-    .start_with_addr()
-    .map_err(|e| ServiceError::WebsocketError(WebsocketError::from(e)))?;
+  log::debug!(viewer_id:% = viewer_id, connection_id:% = connection_id; "Connecting viewer {}", viewer_id);
+  log::debug!(viewer_id:% = viewer_id, connection_id:% = connection_id; "Starting actor to handle websocket communication...");
+  let (addr, response) = WsResponseBuilder::new(
+    ViewerActor::new(viewer_id, connection_id, mediator.as_ref().clone(), query.encoding),
+    &req,
+    payload,
+  )
+  .protocols(&[&ws_protocol])
+  .frame_size(config::get_max_frame_bytes())
+  .start_with_addr()
+  .map_err(|e| ServiceError::WebsocketError(WebsocketError::from(e)))?;
 
   // Register the actor with the mediator -- might return an error
-  log::debug!("Registering viewer with the game mediator...");
+  log::debug!(viewer_id:% = viewer_id, connection_id:% = connection_id; "Registering viewer with the game mediator...");
   let connect_response = mediator
     .send(ConnectViewer(addr.clone()))
     .await
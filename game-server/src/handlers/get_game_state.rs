@@ -0,0 +1,56 @@
+use actix::Addr;
+use actix_web::{web, HttpResponse};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::actors::mediator_messages::{GetGameState, GetGameStateResponse};
+use crate::actors::GameMediatorActor;
+use crate::errors::ServiceError;
+use crate::game::ServerState;
+use crate::jwt::ViewerToken;
+use crate::protocol::{GameState, RegistrationUpdateEnum};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GameStateResponse<'a> {
+  state: ServerState,
+
+  #[serde(skip_serializing_if = "Option::is_none")]
+  registration: Option<RegistrationUpdateEnum>,
+
+  #[serde(skip_serializing_if = "Option::is_none")]
+  match_id: Option<Uuid>,
+
+  #[serde(skip_serializing_if = "Option::is_none")]
+  game_state: Option<&'a GameState>,
+
+  #[serde(skip_serializing_if = "Option::is_none")]
+  ticks_left: Option<u32>,
+
+  #[serde(skip_serializing_if = "Option::is_none")]
+  seconds_per_tick: Option<u32>,
+}
+
+pub async fn get_game_state(
+  _token: ViewerToken,
+  mediator: web::Data<Addr<GameMediatorActor>>,
+) -> Result<HttpResponse, ServiceError> {
+  let GetGameStateResponse {
+    server_state,
+    registration,
+    match_id,
+    latest_state,
+  } = mediator
+    .send(GetGameState)
+    .await
+    .map_err(ServiceError::WebsocketMailboxError)?;
+
+  Ok(HttpResponse::Ok().json(GameStateResponse {
+    state: server_state,
+    registration,
+    match_id,
+    game_state: latest_state.as_ref().map(|s| s.get_game_state().as_ref()),
+    ticks_left: latest_state.as_ref().map(|s| s.get_ticks_left()),
+    seconds_per_tick: latest_state.as_ref().map(|s| s.get_seconds_per_tick()),
+  }))
+}
@@ -0,0 +1,9 @@
+use actix_web::{web, HttpResponse};
+
+use crate::errors::ServiceError;
+use crate::metrics::Metrics;
+
+pub async fn get_metrics(metrics: web::Data<Metrics>) -> Result<HttpResponse, ServiceError> {
+  let body = metrics.encode().map_err(ServiceError::MetricsError)?;
+  Ok(HttpResponse::Ok().content_type(prometheus::TEXT_FORMAT).body(body))
+}
@@ -0,0 +1,23 @@
+use actix::Addr;
+use actix_web::{web, HttpResponse};
+
+use crate::actors::mediator_messages::GetRegisteredPlayers;
+use crate::actors::GameMediatorActor;
+use crate::errors::ServiceError;
+use crate::jwt::ViewerToken;
+use crate::protocol::QueryResponse;
+
+pub async fn get_registered_players(
+  _token: ViewerToken,
+  mediator: web::Data<Addr<GameMediatorActor>>,
+) -> Result<HttpResponse, ServiceError> {
+  let registered = mediator
+    .send(GetRegisteredPlayers)
+    .await
+    .map_err(ServiceError::WebsocketMailboxError)?;
+
+  Ok(HttpResponse::Ok().json(QueryResponse::RegisteredPlayers {
+    players: registered.players,
+    player_order: registered.player_order,
+  }))
+}
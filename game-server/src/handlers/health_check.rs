@@ -0,0 +1,37 @@
+use actix::Addr;
+use actix_web::{web, HttpResponse};
+use serde::Serialize;
+
+use crate::actors::mediator_messages::{GetServerState, GetServerStateResponse};
+use crate::actors::GameMediatorActor;
+use crate::errors::ServiceError;
+use crate::game::{GameEngine, ServerState};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HealthResponse {
+  state: ServerState,
+  engine_alive: bool,
+}
+
+pub async fn health_check(
+  mediator: web::Data<Addr<GameMediatorActor>>,
+  game_engine: web::Data<GameEngine>,
+) -> Result<HttpResponse, ServiceError> {
+  let GetServerStateResponse(state) = mediator
+    .send(GetServerState)
+    .await
+    .map_err(ServiceError::WebsocketMailboxError)?;
+
+  let response = HealthResponse {
+    state,
+    engine_alive: game_engine.is_alive(),
+  };
+
+  // Orchestrators should restart the server if the game engine has died
+  if state == ServerState::FatalError || !response.engine_alive {
+    Ok(HttpResponse::ServiceUnavailable().json(response))
+  } else {
+    Ok(HttpResponse::Ok().json(response))
+  }
+}
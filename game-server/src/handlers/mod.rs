@@ -1,8 +1,41 @@
 //
 // All API handlers for the server
 //
+use actix_web::{web, Scope};
+
 mod connect_player;
 mod connect_viewer;
+mod get_game_state;
+mod get_metrics;
+mod get_registered_players;
+mod health_check;
+mod reboot_engine;
+mod reconnect_player;
+mod set_lobby_wait;
 
 pub use connect_player::connect_player;
 pub use connect_viewer::connect_viewer;
+pub use get_game_state::get_game_state;
+pub use get_metrics::get_metrics;
+pub use get_registered_players::get_registered_players;
+pub use health_check::health_check;
+pub use reboot_engine::reboot_engine;
+pub use reconnect_player::reconnect_player;
+pub use set_lobby_wait::set_lobby_wait;
+
+/// All routes under "/api/v1"
+///   The app data (JWT secret, mediator, action channel, game engine, and metrics) must be set by the caller
+pub fn api_v1() -> Scope {
+  web::scope("/api/v1")
+    .route("/play", web::get().to(connect_player))
+    .route("/reconnect", web::get().to(reconnect_player))
+    .route("/view", web::get().to(connect_viewer))
+    .route("/health", web::get().to(health_check))
+    .route("/state", web::get().to(get_game_state))
+    .route("/players", web::get().to(get_registered_players))
+    .service(
+      web::scope("/admin")
+        .route("/reboot", web::post().to(reboot_engine))
+        .route("/lobby-wait", web::post().to(set_lobby_wait)),
+    )
+}
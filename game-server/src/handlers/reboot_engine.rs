@@ -0,0 +1,30 @@
+use actix::Addr;
+use actix_web::{web, HttpResponse};
+
+use crate::actors::mediator_messages::{RebootEngine, RebootEngineResponse};
+use crate::actors::GameMediatorActor;
+use crate::errors::ServiceError;
+use crate::game::{GameEngine, ServerState};
+use crate::jwt::AdminToken;
+use crate::protocol::QueryResponse;
+
+pub async fn reboot_engine(
+  token: AdminToken,
+  mediator: web::Data<Addr<GameMediatorActor>>,
+  game_engine: web::Data<GameEngine>,
+) -> Result<HttpResponse, ServiceError> {
+  log::info!(admin_id:% = token.get_id(); "Admin {} requested a game engine reboot", token.get_id());
+
+  match mediator
+    .send(RebootEngine(game_engine.as_ref().clone()))
+    .await
+    .map_err(ServiceError::WebsocketMailboxError)?
+  {
+    RebootEngineResponse::Success => Ok(HttpResponse::Ok().json(QueryResponse::ServerState {
+      state: ServerState::Registration,
+      match_id: None,
+    })),
+    RebootEngineResponse::NotCrashed(state) => Err(ServiceError::EngineNotCrashed(state)),
+    RebootEngineResponse::Failed(e) => Err(ServiceError::GameEngineError(e)),
+  }
+}
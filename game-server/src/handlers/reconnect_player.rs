@@ -0,0 +1,38 @@
+use actix::Addr;
+use actix_web::{web, HttpRequest, HttpResponse};
+use tokio::sync::mpsc::Sender;
+use uuid::Uuid;
+
+use super::connect_player::start_player_connection;
+use crate::actors::GameMediatorActor;
+use crate::errors::ServiceError;
+use crate::jwt::{JWTSecret, ReconnectWebsocketToken};
+use crate::protocol::{ConnectQuery, PlayerAction};
+
+pub async fn reconnect_player(
+  token: ReconnectWebsocketToken,
+  mediator: web::Data<Addr<GameMediatorActor>>,
+  send_player_actions: web::Data<Sender<(Uuid, PlayerAction)>>,
+  jwt_secret: web::Data<JWTSecret>,
+  query: web::Query<ConnectQuery>,
+  req: HttpRequest,
+  payload: web::Payload,
+) -> Result<HttpResponse, ServiceError> {
+  let token = token.into_inner();
+  let player_id = token.get_id();
+  let data = token.into_data();
+
+  log::debug!(player_id:% = player_id; "Player is reconnecting with a reconnection token");
+  start_player_connection(
+    player_id,
+    data.get_player_data().clone(),
+    Some(data.get_nonce()),
+    mediator,
+    send_player_actions,
+    jwt_secret,
+    query,
+    req,
+    payload,
+  )
+  .await
+}
@@ -0,0 +1,47 @@
+use actix::Addr;
+use actix_web::{web, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::actors::mediator_messages::{SetLobbyWait, SetLobbyWaitResponse};
+use crate::actors::GameMediatorActor;
+use crate::errors::ServiceError;
+use crate::jwt::AdminToken;
+
+#[derive(Debug, Deserialize)]
+pub struct SetLobbyWaitRequest {
+  seconds: u32,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LobbyWaitResponse {
+  lobby_wait_seconds: u32,
+
+  #[serde(skip_serializing_if = "Option::is_none")]
+  seconds_left: Option<u32>,
+}
+
+pub async fn set_lobby_wait(
+  token: AdminToken,
+  body: web::Json<SetLobbyWaitRequest>,
+  mediator: web::Data<Addr<GameMediatorActor>>,
+) -> Result<HttpResponse, ServiceError> {
+  let seconds = body.into_inner().seconds;
+  log::info!(admin_id:% = token.get_id(); "Admin {} requested a lobby wait time of {}s", token.get_id(), seconds);
+  if seconds < 1 {
+    return Err(ServiceError::InvalidLobbyWait);
+  }
+
+  let SetLobbyWaitResponse {
+    lobby_wait_secs,
+    seconds_left,
+  } = mediator
+    .send(SetLobbyWait { seconds })
+    .await
+    .map_err(ServiceError::WebsocketMailboxError)?;
+
+  Ok(HttpResponse::Ok().json(LobbyWaitResponse {
+    lobby_wait_seconds: lobby_wait_secs,
+    seconds_left,
+  }))
+}
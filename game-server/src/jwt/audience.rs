@@ -10,6 +10,14 @@ pub struct Player;
 #[derive(Deserialize)]
 pub struct Viewer;
 
+/// Route is only available to server admins
+#[derive(Deserialize)]
+pub struct Admin;
+
+/// Short-lived token used by player clients to reconnect
+#[derive(Deserialize)]
+pub struct Reconnect;
+
 /// Generic trait shared by all audience types
 ///
 /// An audience specifies which routes a JWT can access
@@ -37,3 +45,13 @@ impl Audience for Viewer {
   const TEXT: &'static str = "viewer";
   const ACCEPTS: &'static [&'static str] = &["viewer"];
 }
+
+impl Audience for Admin {
+  const TEXT: &'static str = "admin";
+  const ACCEPTS: &'static [&'static str] = &["admin"];
+}
+
+impl Audience for Reconnect {
+  const TEXT: &'static str = "reconnect";
+  const ACCEPTS: &'static [&'static str] = &["reconnect"];
+}
@@ -1,18 +1,63 @@
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 /// Other fields used by JWT for players
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct JWTPlayerData {
   name: String,
+
+  // Only set by the server for bots added to fill the lobby
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  is_bot: bool,
 }
 
 impl JWTPlayerData {
   pub fn new(name: impl Into<String>) -> Self {
-    Self { name: name.into() }
+    Self {
+      name: name.into(),
+      is_bot: false,
+    }
+  }
+
+  pub fn with_bot_flag(self, is_bot: bool) -> Self {
+    Self { is_bot, ..self }
   }
 
   pub fn get_name(&self) -> &String {
     &self.name
   }
+
+  pub fn is_bot(&self) -> bool {
+    self.is_bot
+  }
+}
+
+/// Other fields used by JWT for reconnecting players
+///   The nonce is stored by the server, so each token can only be used once
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReconnectData {
+  nonce: Uuid,
+
+  #[serde(flatten)]
+  player_data: JWTPlayerData,
+}
+
+impl ReconnectData {
+  pub fn new(nonce: Uuid, player_data: JWTPlayerData) -> Self {
+    Self { nonce, player_data }
+  }
+
+  pub fn get_nonce(&self) -> Uuid {
+    self.nonce
+  }
+
+  pub fn get_player_data(&self) -> &JWTPlayerData {
+    &self.player_data
+  }
+
+  pub fn into_player_data(self) -> JWTPlayerData {
+    self.player_data
+  }
 }
@@ -1,7 +1,7 @@
 use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
 use actix_web_httpauth::extractors::bearer::BearerAuth;
 use chrono::{offset::Utc, Duration};
-use jsonwebtoken::{decode, encode, Algorithm, EncodingKey, Header, Validation};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::future::Future;
 use std::marker::PhantomData;
@@ -9,6 +9,7 @@ use std::pin::Pin;
 use uuid::Uuid;
 
 use super::JWTSecret;
+use crate::config;
 use crate::errors::ServiceError;
 use crate::jwt::{Audience, JWT_ISSUER};
 
@@ -53,6 +54,11 @@ impl<A: Audience, T> JWTToken<A, T> {
     self.sub
   }
 
+  /// Expiration time (as UTC timestamp)
+  pub fn get_expiration(&self) -> i64 {
+    self.exp
+  }
+
   pub fn get_data(&self) -> &T {
     &self.user_data
   }
@@ -60,6 +66,15 @@ impl<A: Audience, T> JWTToken<A, T> {
   pub fn into_data(self) -> T {
     self.user_data
   }
+
+  /// Reject tokens that are valid for longer than the server allows (0 for no limit)
+  pub fn validate_lifetime(&self, max_minutes: u32) -> Result<(), ServiceError> {
+    if max_minutes > 0 && self.exp - self.iat > (max_minutes as i64) * 60 {
+      return Err(ServiceError::JWTLifetimeTooLong { max_minutes });
+    }
+
+    Ok(())
+  }
 }
 
 impl<A, T> JWTToken<A, T>
@@ -69,7 +84,22 @@ where
 {
   /// Encode the JSON Web Token into a string
   pub fn encode(&self, key: &EncodingKey) -> Result<String, jsonwebtoken::errors::Error> {
-    Ok(encode(&Header::new(Algorithm::HS256), self, key)?)
+    encode(&Header::new(Algorithm::HS256), self, key)
+  }
+
+  /// Decode the JSON Web Token string, validating the signature, expiration, issuer, and audience
+  ///   The maximum lifetime depends on the server configuration, so it must be checked separately
+  pub fn decode(token: &str, key: &DecodingKey) -> Result<Self, jsonwebtoken::errors::Error> {
+    let validation = Validation {
+      algorithms: vec![Algorithm::HS256],
+      validate_exp: true,
+      leeway: 15,
+      aud: Some(A::accepts()),
+      iss: Some(JWT_ISSUER.into()),
+      ..Default::default()
+    };
+
+    decode::<Self>(token, key, &validation).map(|token_data| token_data.claims)
   }
 }
 
@@ -91,19 +121,11 @@ where
       let bearer_token = BearerAuth::extract(&req).await?;
       let jwt_public_key = req.app_data::<web::Data<JWTSecret>>().expect("JWTSecret should be set");
 
-      // Validation parameters,
-      let validation = Validation {
-        algorithms: vec![Algorithm::HS256],
-        validate_exp: true,
-        leeway: 15,
-        aud: Some(A::accepts()),
-        iss: Some(JWT_ISSUER.into()),
-        ..Default::default()
-      };
-
       // Decode and validate the JWT
-      let token_data = decode::<Self>(bearer_token.token(), &jwt_public_key.get_decoding_key(), &validation)?;
-      Ok(token_data.claims)
+      let token = Self::decode(bearer_token.token(), &jwt_public_key.get_decoding_key())?;
+      token.validate_lifetime(config::get_max_token_lifetime_minutes())?;
+
+      Ok(token)
     })
   }
 }
@@ -15,12 +15,15 @@ pub use websocket_token::*;
 
 // Other JWT constants
 pub const JWT_ISSUER: &str = "game-server";
-pub const JWT_EXPIRATION_MIN: i64 = 10;
+pub const RECONNECT_EXPIRATION_MIN: i64 = 10;
 
 // Type aliases for the different JWT tokens
 pub type PlayerToken = JWTToken<audience::Player, JWTPlayerData>;
 pub type ViewerToken = JWTToken<audience::Viewer, ()>;
+pub type AdminToken = JWTToken<audience::Admin, ()>;
+pub type ReconnectToken = JWTToken<audience::Reconnect, ReconnectData>;
 
 /// Type aliases for the different JWT websocket tokens
 pub type PlayerWebsocketToken = JWTWebsocketToken<audience::Player, JWTPlayerData>;
 pub type ViewerWebsocketToken = JWTWebsocketToken<audience::Viewer, ()>;
+pub type ReconnectWebsocketToken = JWTWebsocketToken<audience::Reconnect, ReconnectData>;
@@ -1,12 +1,11 @@
 use actix_web::{dev::Payload, web, FromRequest, HttpRequest};
 use futures::future::{ready, Ready};
-use jsonwebtoken::{decode, Algorithm, Validation};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::ops::{Deref, DerefMut};
 
+use crate::config;
 use crate::errors::ServiceError;
-use crate::jwt::{Audience, JWTSecret, JWTToken, JWT_ISSUER};
-use crate::WS_PROTOCOL;
+use crate::jwt::{Audience, JWTSecret, JWTToken};
 
 /// Special JWT token that deserializes from the 'Sec-WebSocket-Protocol' header
 #[derive(Debug, Clone, Deserialize)]
@@ -57,32 +56,25 @@ where
         Some(token) => token,
       }
       .to_str()
-      .or_else(|e| Err(ServiceError::WebsocketJWTParseError(e)))?;
+      .map_err(ServiceError::WebsocketJWTParseError)?;
 
       // The bearer token will be the longest protocol that isn't the WS_PROTOCOL string
+      let ws_protocol = config::get_ws_protocol();
       let bearer_token = req_protocols
         .split(',')
         .map(|protocol| protocol.trim())
-        .filter(|protocol| protocol != &WS_PROTOCOL)
+        .filter(|protocol| protocol != &ws_protocol)
         .max_by_key(|protocol| protocol.len())
         .ok_or_else(|| ServiceError::MissingWebsocketJWT)?;
 
       // Get the enccryption key from the app data
       let jwt_secret = req.app_data::<web::Data<JWTSecret>>().expect("JWTSecret should be set");
 
-      // Validation parameters,
-      let validation = Validation {
-        algorithms: vec![Algorithm::HS256],
-        validate_exp: true,
-        leeway: 15,
-        aud: Some(A::accepts()),
-        iss: Some(JWT_ISSUER.into()),
-        ..Default::default()
-      };
-
       // Decode and validate the JWT
-      let token_data = decode::<Self>(bearer_token, &jwt_secret.get_decoding_key(), &validation)?;
-      Ok(token_data.claims)
+      let token = JWTToken::decode(bearer_token, &jwt_secret.get_decoding_key())?;
+      token.validate_lifetime(config::get_max_token_lifetime_minutes())?;
+
+      Ok(Self(token))
     })();
 
     ready(result)
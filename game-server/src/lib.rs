@@ -5,6 +5,10 @@ pub mod errors;
 pub mod game;
 pub mod handlers;
 pub mod jwt;
+pub mod logging;
+pub mod metrics;
 pub mod protocol;
+pub mod ssl;
 
-pub const WS_PROTOCOL: &str = "game-server";
+#[cfg(feature = "testing")]
+pub mod testing;
@@ -0,0 +1,96 @@
+//
+// Configure the logger output for all executables
+//
+use chrono::{SecondsFormat, Utc};
+use log::kv::{self, Key, Value, VisitSource};
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use serde_json::{Map as JSONMap, Value as JSONValue};
+use simple_logger::SimpleLogger;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// Format used for every log message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+  /// Human-readable lines (the default)
+  Pretty,
+
+  /// One JSON object per line, for log aggregators
+  Json,
+}
+
+impl FromStr for LogFormat {
+  type Err = String;
+
+  fn from_str(input: &str) -> Result<Self, Self::Err> {
+    match input.to_lowercase().as_str() {
+      "pretty" => Ok(LogFormat::Pretty),
+      "json" => Ok(LogFormat::Json),
+      _ => Err(format!("unknown log format '{}'", input)),
+    }
+  }
+}
+
+impl fmt::Display for LogFormat {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      LogFormat::Pretty => write!(f, "pretty"),
+      LogFormat::Json => write!(f, "json"),
+    }
+  }
+}
+
+/// Install the global logger with the given output format and maximum log level
+pub fn init(format: LogFormat, level: LevelFilter) -> Result<(), SetLoggerError> {
+  match format {
+    LogFormat::Pretty => SimpleLogger::new().init()?,
+    LogFormat::Json => log::set_boxed_logger(Box::new(JsonLogger))?,
+  }
+
+  log::set_max_level(level);
+  Ok(())
+}
+
+/// Logger that writes each record as a single-line JSON object
+///   Any key-value pairs attached to the log macro (like `player_id`) are included as extra fields
+struct JsonLogger;
+
+impl Log for JsonLogger {
+  fn enabled(&self, metadata: &Metadata) -> bool {
+    metadata.level() <= log::max_level()
+  }
+
+  fn log(&self, record: &Record) {
+    if !self.enabled(record.metadata()) {
+      return;
+    }
+
+    let mut fields = JSONMap::new();
+    fields.insert(
+      "timestamp".into(),
+      Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true).into(),
+    );
+    fields.insert("level".into(), record.level().as_str().into());
+    fields.insert("target".into(), record.target().into());
+    fields.insert("message".into(), record.args().to_string().into());
+    record.key_values().visit(&mut FieldVisitor(&mut fields)).ok();
+
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, "{}", JSONValue::Object(fields)).ok();
+  }
+
+  fn flush(&self) {
+    io::stdout().flush().ok();
+  }
+}
+
+/// Copies the key-value pairs from a log record into the JSON object
+struct FieldVisitor<'a>(&'a mut JSONMap<String, JSONValue>);
+
+impl<'kvs> VisitSource<'kvs> for FieldVisitor<'_> {
+  fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+    self.0.insert(key.to_string(), value.to_string().into());
+    Ok(())
+  }
+}
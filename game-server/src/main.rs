@@ -1,52 +1,110 @@
-use actix::Actor;
+use actix::{Actor, Addr};
+use actix_cors::Cors;
+use actix_web::dev::ServerHandle;
 use actix_web::{middleware, web, App, HttpResponse, HttpServer};
-use game_server::actors::GameMediatorActor;
+use game_server::actors::{mediator_messages::Shutdown, GameMediatorActor};
 use log::LevelFilter;
-use rustls::{Certificate, PrivateKey, ServerConfig};
-use rustls_pemfile::{certs, pkcs8_private_keys};
-use simple_logger::SimpleLogger;
+use rustls::ServerConfig;
+use std::fs;
+use std::path::Path;
 use std::sync::mpsc::channel;
-use std::thread;
-use std::{fs::File, io::BufReader};
 use structopt::StructOpt;
+use tokio::sync::mpsc;
 
 use game_server::config;
 use game_server::errors::ServiceError;
-use game_server::game::GamePlayer;
+use game_server::game::{GameEngine, GamePlayer, ACTION_QUEUE_CAPACITY};
 use game_server::handlers;
 use game_server::jwt::JWTSecret;
+use game_server::logging;
+use game_server::metrics::Metrics;
+use game_server::ssl;
 
 #[actix_web::main]
 async fn main() -> anyhow::Result<()> {
   // Parse ".env" configuration files and command-line arguments
   config::load_environment_from_env_files();
 
-  let opt = config::Opt::from_args();
+  let mut opt = config::Opt::from_args();
+  if let Some(config_file) = opt.get_config_file() {
+    // Values from the TOML file have the lowest priority, so re-parse the
+    //  command-line arguments after the file is loaded into the environment
+    config::Config::from_file(config_file)?.update_environment();
+    opt = config::Opt::from_args();
+  }
   opt.update_environment();
 
   // Configure the logger system
-  SimpleLogger::new().init()?;
-  if cfg!(debug_assertions) {
-    log::set_max_level(LevelFilter::Debug);
+  let default_log_level = if cfg!(debug_assertions) {
+    LevelFilter::Debug
   } else {
-    log::set_max_level(LevelFilter::Info);
+    LevelFilter::Info
+  };
+  logging::init(config::get_log_format(), default_log_level)?;
+
+  // Set after the logger is installed, so an invalid LOG_LEVEL can still be reported
+  log::set_max_level(config::get_log_level(default_log_level));
+
+  // Check everything without binding a socket
+  if opt.is_dry_run() {
+    return dry_run();
+  }
+
+  // Report all configuration problems at once before starting the server
+  if let Err(errors) = config::validate() {
+    if !config::is_lenient() {
+      return Err(anyhow::anyhow!("invalid configuration:\n  {}", errors.join("\n  ")));
+    }
+
+    for error in errors.iter() {
+      log::warn!("{}", error);
+    }
+  }
+
+  // Smoke test for the game files, without starting the server
+  if opt.should_validate_lua() {
+    let lua_file = config::get_lua_file();
+    GamePlayer::validate_lua_file(&lua_file)
+      .map_err(|e| anyhow::anyhow!("invalid Lua file '{}': {}", lua_file, e.get_developer_notes()))?;
+
+    log::info!("Lua file '{}' is valid", lua_file);
+    return Ok(());
   }
 
   // Channels for the game engine communication
   let (send_start_game, recv_start_game) = channel();
-  let (send_player_actions, recv_player_actions) = channel();
+  let (send_player_actions, recv_player_actions) = mpsc::channel(ACTION_QUEUE_CAPACITY);
+
+  // Shared registry for the Prometheus metrics
+  let metrics = Metrics::new()?;
+  let enable_metrics = config::enable_metrics();
 
   // Start the game mediator actor
-  let game_mediator = GameMediatorActor::new(send_start_game).start();
+  let game_mediator = GameMediatorActor::new(send_start_game, send_player_actions.clone(), metrics.clone()).start();
 
   // Load the Lua file into the game engine
   let lua_file = config::get_lua_file();
   log::info!("Loading Lua game engine from '{}'", lua_file);
-  let mut game_player = GamePlayer::new(lua_file, recv_start_game, recv_player_actions, game_mediator.clone())
-    .map_err(|e| anyhow::anyhow!("failed to start game engine: {}", e.get_developer_notes()))?;
+  let game_player = GamePlayer::new(
+    lua_file,
+    recv_start_game,
+    recv_player_actions,
+    game_mediator.clone(),
+    metrics.clone(),
+  )
+  .map_err(|e| anyhow::anyhow!("failed to start game engine: {}", e.get_developer_notes()))?;
 
   log::info!("Running game engine on a separate thread");
-  thread::spawn(move || game_player.run_game());
+  let game_engine = web::Data::new(GameEngine::spawn(game_player));
+
+  // Keep a handle to the mediator so the clients can be notified on shutdown
+  let shutdown_mediator = game_mediator.clone();
+
+  // Without any allowed origins, browsers fall back to the same-origin policy
+  let allowed_origins = config::get_allowed_origins();
+  if !allowed_origins.is_empty() {
+    log::info!("Allowing cross-origin requests from: {}", allowed_origins.join(", "));
+  }
 
   // Database connection pool and web server
   let mut server = HttpServer::new(move || {
@@ -57,6 +115,15 @@ async fn main() -> anyhow::Result<()> {
       .app_data(web::Data::new(game_mediator.clone()))
       // MPSC channel for sending player actions
       .app_data(web::Data::new(send_player_actions.clone()))
+      // Handle for checking if the game engine thread is still running (or restarting it)
+      .app_data(game_engine.clone())
+      // Prometheus metrics registry
+      .app_data(web::Data::new(metrics.clone()))
+      // Enable CORS (only if any origins are allowed)
+      .wrap(middleware::Condition::new(
+        !allowed_origins.is_empty(),
+        get_cors_configuration(&allowed_origins),
+      ))
       // Enable logger
       .wrap(middleware::Logger::default())
       // Configure error handlers
@@ -64,58 +131,167 @@ async fn main() -> anyhow::Result<()> {
       .app_data(web::FormConfig::default().error_handler(|err, _req| ServiceError::from(err).into()))
       .app_data(web::PathConfig::default().error_handler(|err, _req| ServiceError::from(err).into()))
       .app_data(web::QueryConfig::default().error_handler(|err, _req| ServiceError::from(err).into()))
-      .service(
-        web::scope("/api/v1")
-          .route("/play", web::get().to(handlers::connect_player))
-          .route("/view", web::get().to(handlers::connect_viewer)),
-      )
+      .service(handlers::api_v1())
+      .configure(|cfg| {
+        if enable_metrics {
+          cfg.route("/metrics", web::get().to(handlers::get_metrics));
+        }
+      })
       // Load all routes
-      .default_service(web::route().to(|| HttpResponse::NotFound()))
+      .default_service(web::route().to(HttpResponse::NotFound))
   });
 
-  // Possibly enable SSL
-  let ip_port = format!("{}:{}", config::get_host(), config::get_port());
-  server = if config::use_https() {
-    server.bind_rustls(ip_port, get_ssl_configuration()?)?
-  } else {
-    server.bind(ip_port)?
-  };
+  // Possibly listen on a Unix domain socket (for a local reverse proxy)
+  let unix_socket = config::get_unix_socket();
+  #[cfg(unix)]
+  if let Some(ref unix_socket) = unix_socket {
+    remove_stale_socket(unix_socket)?;
+    log::info!("Listening on Unix domain socket '{}'", unix_socket.display());
+    server = server.bind_uds(unix_socket)?;
+  }
+
+  // Otherwise use the host and port, and possibly enable SSL
+  if unix_socket.is_none() || cfg!(not(unix)) {
+    let ip_port = format!("{}:{}", config::get_host(), config::get_port());
+    server = if config::use_https() {
+      server.bind_rustls(ip_port, get_ssl_configuration()?)?
+    } else {
+      server.bind(ip_port)?
+    };
+  }
 
   // Run and listen for connections
-  Ok(server.run().await?)
+  //  (Signals are handled manually so clients can be closed gracefully first)
+  let server = server.disable_signals().run();
+  actix_web::rt::spawn(shutdown_on_signal(server.handle(), shutdown_mediator));
+
+  let result = server.await;
+  if let Some(unix_socket) = unix_socket {
+    fs::remove_file(unix_socket).ok();
+  }
+
+  Ok(result?)
 }
 
 ///
-/// Load and configure SSL if required
+/// Remove a socket file left behind by a server that didn't shut down cleanly
+///   Any other type of file is left alone, so binding fails instead
 ///
-fn get_ssl_configuration() -> anyhow::Result<ServerConfig> {
-  let key_filename = config::get_key_file().ok_or_else(|| anyhow::anyhow!("KEY_FILE environment variable not set"))?;
-  let cert_filename =
-    config::get_cert_file().ok_or_else(|| anyhow::anyhow!("CERT_FILE environment variable not set"))?;
+#[cfg(unix)]
+fn remove_stale_socket(unix_socket: &std::path::Path) -> anyhow::Result<()> {
+  if config::is_socket_file(unix_socket) {
+    log::info!("Removing stale Unix domain socket '{}'", unix_socket.display());
+    fs::remove_file(unix_socket).map_err(|e| anyhow::anyhow!("Failed to remove '{}': {}", unix_socket.display(), e))?;
+  }
 
-  // Init server config builder with safe defaults
-  let config = ServerConfig::builder().with_safe_defaults().with_no_client_auth();
+  Ok(())
+}
 
-  // Read the TLS key/cert files
-  let cert_file = &mut BufReader::new(
-    File::open(&key_filename).or_else(|e| Err(anyhow::anyhow!("Failed to open '{}': {}", key_filename, e)))?,
-  );
-  let key_file = &mut BufReader::new(
-    File::open(&cert_filename).or_else(|e| Err(anyhow::anyhow!("Failed to open '{}': {}", cert_filename, e)))?,
-  );
+///
+/// Report ALL configuration problems (including the Lua and SSL files) and print the effective configuration
+///   Invalid values are always an error, even in lenient mode
+///
+fn dry_run() -> anyhow::Result<()> {
+  let mut errors = config::validate().err().unwrap_or_default();
 
-  // Convert files to key/cert objects
-  let cert_chain = certs(cert_file)?.into_iter().map(Certificate).collect();
-  let mut keys: Vec<PrivateKey> = pkcs8_private_keys(key_file)?.into_iter().map(PrivateKey).collect();
+  // Only try to run the Lua file if it can be read in the first place
+  let lua_file = config::get_lua_file();
+  if !errors.iter().any(|e| e.starts_with("LUA_FILE:")) {
+    if let Err(e) = GamePlayer::validate_lua_file(&lua_file) {
+      errors.push(format!(
+        "LUA_FILE: invalid Lua file '{}': {}",
+        lua_file,
+        e.get_developer_notes()
+      ));
+    }
+  }
 
-  // Exit if no keys could be parsed
-  if keys.is_empty() {
-    Err(anyhow::anyhow!("Could not locate PKCS 8 private keys."))?;
+  if config::use_https()
+    && !errors
+      .iter()
+      .any(|e| e.starts_with("KEY_FILE:") || e.starts_with("CERT_FILE:"))
+  {
+    if let Err(e) = get_ssl_configuration() {
+      errors.push(format!("USE_HTTPS: failed to load the SSL configuration: {}", e));
+    }
   }
 
-  let config = config.with_single_cert(cert_chain, keys.remove(0))?;
-  log::debug!("Loaded SSL key file from: {}", key_filename);
-  log::debug!("Loaded SSL certificate chain file from: {}", cert_filename);
+  println!("Effective configuration:");
+  for (name, value) in config::get_effective_config() {
+    println!("  {}={}", name, value);
+  }
+
+  if !errors.is_empty() {
+    return Err(anyhow::anyhow!("invalid configuration:\n  {}", errors.join("\n  ")));
+  }
+
+  log::info!("Configuration is valid");
+  Ok(())
+}
+
+///
+/// Wait for a termination signal, then close all websockets before stopping the server
+///
+async fn shutdown_on_signal(server: ServerHandle, game_mediator: Addr<GameMediatorActor>) {
+  let signal = wait_for_signal().await;
+  log::info!("Received {}, shutting down the server...", signal);
+
+  let reason = String::from("Server is shutting down");
+  if let Err(e) = game_mediator.send(Shutdown { reason }).await {
+    log::error!("Failed to notify clients of shutdown: {}", e);
+  }
+
+  server.stop(true).await;
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() -> &'static str {
+  use actix_web::rt::signal::unix::{signal, SignalKind};
+  use futures::future::{select, Either};
+
+  let mut sigterm = match signal(SignalKind::terminate()) {
+    Ok(sigterm) => sigterm,
+    Err(e) => {
+      log::warn!("Unable to listen for SIGTERM: {}", e);
+      actix_web::rt::signal::ctrl_c().await.ok();
+      return "SIGINT";
+    },
+  };
+
+  let received = select(Box::pin(actix_web::rt::signal::ctrl_c()), Box::pin(sigterm.recv())).await;
+  match received {
+    Either::Left(_) => "SIGINT",
+    Either::Right(_) => "SIGTERM",
+  }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() -> &'static str {
+  actix_web::rt::signal::ctrl_c().await.ok();
+  "Ctrl-C"
+}
+
+///
+/// Build the CORS middleware from the list of allowed origins
+///
+fn get_cors_configuration(allowed_origins: &[String]) -> Cors {
+  let cors = Cors::default().allow_any_method().allow_any_header().max_age(3600);
+  if allowed_origins.iter().any(|origin| origin == config::ANY_ORIGIN) {
+    return cors.allow_any_origin();
+  }
+
+  allowed_origins
+    .iter()
+    .fold(cors, |cors, origin| cors.allowed_origin(origin))
+}
+
+///
+/// Load and configure SSL if required
+///
+fn get_ssl_configuration() -> anyhow::Result<ServerConfig> {
+  let key_filename = config::get_key_file().ok_or_else(|| anyhow::anyhow!("KEY_FILE environment variable not set"))?;
+  let cert_filename =
+    config::get_cert_file().ok_or_else(|| anyhow::anyhow!("CERT_FILE environment variable not set"))?;
 
-  Ok(config)
+  ssl::load_ssl_config(Path::new(&cert_filename), Path::new(&key_filename))
 }
@@ -0,0 +1,77 @@
+//
+// Prometheus metrics exported by the server
+//
+use prometheus::{Encoder, Histogram, HistogramOpts, IntCounter, IntGauge, Registry, TextEncoder};
+
+/// Shared registry with all server metrics
+///   Cloning is cheap, since every metric is reference counted internally
+#[derive(Clone)]
+pub struct Metrics {
+  registry: Registry,
+
+  pub registered_players: IntGauge,
+  pub connected_players: IntGauge,
+  pub connected_viewers: IntGauge,
+  pub games_started: IntCounter,
+  pub games_ended: IntCounter,
+  pub game_engine_crashes: IntCounter,
+  pub game_engine_timeouts: IntCounter,
+  pub messages_dropped: IntCounter,
+  pub tick_duration_seconds: Histogram,
+}
+
+impl Metrics {
+  /// Create and register all metrics
+  pub fn new() -> prometheus::Result<Self> {
+    let registry = Registry::new_custom(Some("game_server".into()), None)?;
+
+    let metrics = Self {
+      registered_players: IntGauge::new("registered_players", "Players registered for the current game")?,
+      connected_players: IntGauge::new("connected_players", "Player websocket actors currently connected")?,
+      connected_viewers: IntGauge::new("connected_viewers", "Viewer websocket actors currently connected")?,
+      games_started: IntCounter::new("games_started_total", "Number of games started")?,
+      games_ended: IntCounter::new("games_ended_total", "Number of games that finished normally")?,
+      game_engine_crashes: IntCounter::new("game_engine_crashes_total", "Number of fatal game engine crashes")?,
+      game_engine_timeouts: IntCounter::new(
+        "game_engine_timeouts_total",
+        "Number of game engine crashes caused by a Lua method running past LUA_TIMEOUT_MS",
+      )?,
+      messages_dropped: IntCounter::new(
+        "messages_dropped_total",
+        "Broadcast messages dropped because a client mailbox was full",
+      )?,
+      tick_duration_seconds: Histogram::with_opts(HistogramOpts::new(
+        "tick_duration_seconds",
+        "Time spent running the Lua Update() method for each game tick",
+      ))?,
+      registry,
+    };
+
+    metrics
+      .registry
+      .register(Box::new(metrics.registered_players.clone()))?;
+    metrics.registry.register(Box::new(metrics.connected_players.clone()))?;
+    metrics.registry.register(Box::new(metrics.connected_viewers.clone()))?;
+    metrics.registry.register(Box::new(metrics.games_started.clone()))?;
+    metrics.registry.register(Box::new(metrics.games_ended.clone()))?;
+    metrics
+      .registry
+      .register(Box::new(metrics.game_engine_crashes.clone()))?;
+    metrics
+      .registry
+      .register(Box::new(metrics.game_engine_timeouts.clone()))?;
+    metrics.registry.register(Box::new(metrics.messages_dropped.clone()))?;
+    metrics
+      .registry
+      .register(Box::new(metrics.tick_duration_seconds.clone()))?;
+
+    Ok(metrics)
+  }
+
+  /// Encode all metrics using the Prometheus text format
+  pub fn encode(&self) -> prometheus::Result<String> {
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+  }
+}
@@ -5,7 +5,7 @@ use serde::{Deserialize, Serialize};
 pub type PlayerAction = TaggedRequest<PlayerActionEnum>;
 
 /// Enum of the actual actions taken
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "camelCase")]
 pub enum PlayerActionEnum {
   Move(MoveAction),
@@ -24,20 +24,20 @@ pub enum Direction {
 }
 
 /// Move the player in the game
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MoveAction {
   pub direction: Direction,
 }
 
 /// Attack / Shoot in a given direction
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AttackAction {
   pub direction: Direction,
 }
 
 /// Drop a weapon - Just declare an empty struct
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DropWeaponAction {}
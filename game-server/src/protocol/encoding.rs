@@ -0,0 +1,21 @@
+use serde::Deserialize;
+
+/// Format used for all messages sent over the websocket
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum Encoding {
+  /// JSON text frames (the default)
+  #[default]
+  #[serde(rename = "json")]
+  Json,
+
+  /// MessagePack binary frames
+  #[serde(rename = "msgpack")]
+  MessagePack,
+}
+
+/// Query string parameters when connecting to the websocket
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ConnectQuery {
+  #[serde(default)]
+  pub encoding: Encoding,
+}
@@ -1,46 +1,85 @@
+use rlua::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_json::{Map as JSONMap, Value as JSONValue};
 use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
 
+use crate::errors::GameEngineError;
 use crate::protocol::PlayerAction;
 
+/// Deeper tables are rejected before converting Lua values to JSON
+///   Converting is recursive, so a runaway (or cyclic) table would otherwise overflow the stack
+const MAX_LUA_TABLE_DEPTH: usize = 32;
+
+/// 64-bit FNV-1a constants, used for the game state hash
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
 /// Notify the mediator that the game state has been updated
+///   Borrows the game state so large states don't need to be cloned before serializing
 #[derive(Debug, Clone, Serialize)]
 #[serde(tag = "type", rename_all = "camelCase")]
-pub enum GameStateUpdate {
+pub enum GameStateUpdate<'a> {
   /// Game has just been initialized (returns the initial game state)
   #[serde(rename_all = "camelCase")]
   Init {
-    game_state: GameState,
+    match_id: Uuid,
+    game_state: &'a GameState,
     ticks_left: u32,
     seconds_per_tick: u32,
+    player_order: &'a [Uuid],
+    started_at: &'a str,
+    protocol_version: u32,
   },
 
   /// Game has been updated
   #[serde(rename_all = "camelCase")]
   NextState {
-    game_state: GameState,
-    actions_taken: HashMap<Uuid, PlayerAction>,
+    match_id: Uuid,
+    game_state: &'a GameState,
+    actions_taken: &'a HashMap<Uuid, PlayerAction>,
+    current_tick: u32,
+    ticks_left: u32,
+    seconds_per_tick: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state_hash: Option<&'a str>,
+  },
+
+  /// Game has been updated, but only the changes since the last state are sent
+  #[serde(rename_all = "camelCase")]
+  Delta {
+    match_id: Uuid,
+    changes: GameStateDelta,
+    actions_taken: &'a HashMap<Uuid, PlayerAction>,
+    current_tick: u32,
     ticks_left: u32,
     seconds_per_tick: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state_hash: Option<&'a str>,
   },
 
   /// Sent every time a player is killed
   #[serde(rename_all = "camelCase")]
-  PlayerKilled { id: Uuid },
+  PlayerKilled { match_id: Uuid, id: Uuid },
 
   /// Sent when the game is over (returns the final game state)
   #[serde(rename_all = "camelCase")]
   GameEnded {
-    winners: HashSet<Uuid>,
-    game_state: GameState,
-    actions_taken: HashMap<Uuid, PlayerAction>,
+    match_id: Uuid,
+    winners: &'a HashSet<Uuid>,
+    game_state: &'a GameState,
+    actions_taken: &'a HashMap<Uuid, PlayerAction>,
+    elimination_order: &'a [Uuid],
+    stats: &'a HashMap<Uuid, PlayerStats>,
   },
+
+  /// Sent to every client right before the server process exits
+  #[serde(rename_all = "camelCase")]
+  ServerShutdown { reason: &'a str },
 }
 
 /// Get the current game state
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GameState {
   /// Static obstacles in the arena (like walls)
@@ -49,13 +88,351 @@ pub struct GameState {
 
   /// Map of player ID to details
   #[serde(default)]
-  players: HashMap<Uuid, JSONMap<String, JSONValue>>,
+  players: HashMap<Uuid, Player>,
 
   /// List of weapons in the arena
   #[serde(default)]
-  weapons: Vec<JSONMap<String, JSONValue>>,
+  weapons: Vec<Weapon>,
 
   /// List of items in the arena
   #[serde(default)]
-  items: Vec<JSONMap<String, JSONValue>>,
+  items: Vec<Item>,
+}
+
+/// Same fields as the game state, but any unknown fields are rejected (see STRICT_GAME_STATE)
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct StrictGameState {
+  #[serde(default)]
+  playfield: Vec<Vec<u32>>,
+
+  #[serde(default)]
+  players: HashMap<Uuid, Player>,
+
+  #[serde(default)]
+  weapons: Vec<Weapon>,
+
+  #[serde(default)]
+  items: Vec<Item>,
+}
+
+impl From<StrictGameState> for GameState {
+  fn from(state: StrictGameState) -> Self {
+    Self {
+      playfield: state.playfield,
+      players: state.players,
+      weapons: state.weapons,
+      items: state.items,
+    }
+  }
+}
+
+/// Details about a single player in the arena
+///   Any other fields returned by the Lua code are kept in "extra"
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Player {
+  /// Row in the playfield (starts at 1, like Lua arrays)
+  row: u32,
+
+  /// Column in the playfield (starts at 1, like Lua arrays)
+  col: u32,
+
+  /// Player is killed when this reaches 0
+  health: i32,
+
+  /// Weapon held by the player, if any
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  weapon: Option<JSONMap<String, JSONValue>>,
+
+  /// Game-specific details
+  #[serde(flatten)]
+  extra: JSONMap<String, JSONValue>,
+}
+
+/// Weapon lying on the ground in the arena
+///   Any other fields returned by the Lua code (like the type or ammo) are kept in "extra"
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Weapon {
+  /// Unique identifier for the weapon
+  id: String,
+
+  /// Row in the playfield (starts at 1, like Lua arrays)
+  row: u32,
+
+  /// Column in the playfield (starts at 1, like Lua arrays)
+  col: u32,
+
+  /// Game-specific details
+  #[serde(flatten)]
+  extra: JSONMap<String, JSONValue>,
+}
+
+/// Item lying on the ground in the arena
+///   Any other fields returned by the Lua code are kept in "extra"
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Item {
+  /// Unique identifier for the item
+  id: String,
+
+  /// Row in the playfield (starts at 1, like Lua arrays)
+  row: u32,
+
+  /// Column in the playfield (starts at 1, like Lua arrays)
+  col: u32,
+
+  /// Game-specific details
+  #[serde(flatten)]
+  extra: JSONMap<String, JSONValue>,
+}
+
+/// Only the parts of the game state that changed since the last update
+///   Fields that did not change are omitted from the JSON
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GameStateDelta {
+  /// Full playfield, only sent if any part of it changed
+  #[serde(skip_serializing_if = "Option::is_none")]
+  playfield: Option<Vec<Vec<u32>>>,
+
+  /// Players that were added or changed in any way
+  #[serde(skip_serializing_if = "HashMap::is_empty")]
+  players: HashMap<Uuid, Player>,
+
+  /// Players no longer in the game state
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  removed_players: Vec<Uuid>,
+
+  /// Full list of weapons, only sent if any weapon changed
+  #[serde(skip_serializing_if = "Option::is_none")]
+  weapons: Option<Vec<Weapon>>,
+
+  /// Full list of items, only sent if any item changed
+  #[serde(skip_serializing_if = "Option::is_none")]
+  items: Option<Vec<Item>>,
+}
+
+/// Statistics for a single player over the whole game round
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerStats {
+  /// Number of ticks that finished with the player still alive
+  ticks_survived: u32,
+
+  /// Number of actions the player sent to the game engine
+  actions_taken: u32,
+}
+
+impl GameState {
+  /// Convert the value returned by the Lua code into a game state
+  ///   In strict mode, unknown fields (like a misspelled "playfeild") are an error instead of being ignored
+  pub fn from_lua(lua_value: LuaValue, strict: bool) -> Result<Self, GameEngineError> {
+    check_lua_depth(&lua_value, MAX_LUA_TABLE_DEPTH)?;
+    if strict {
+      rlua_serde::from_value::<StrictGameState>(lua_value)
+        .map(Self::from)
+        .map_err(|e| GameEngineError::InvalidGameState(e.to_string()))
+    } else {
+      rlua_serde::from_value(lua_value).map_err(GameEngineError::LuaToJSON)
+    }
+  }
+
+  pub fn get_playfield(&self) -> &Vec<Vec<u32>> {
+    &self.playfield
+  }
+
+  pub fn get_players(&self) -> &HashMap<Uuid, Player> {
+    &self.players
+  }
+
+  pub fn get_weapons(&self) -> &Vec<Weapon> {
+    &self.weapons
+  }
+
+  pub fn get_items(&self) -> &Vec<Item> {
+    &self.items
+  }
+
+  /// Stable hash of the game state, as 16 hex digits
+  ///   Hashes the JSON with sorted keys, so the same game state always has the same hash on any run
+  ///   Returns None (and logs the error) if the game state can't be serialized, so no bogus hash is sent
+  pub fn state_hash(&self) -> Option<String> {
+    let value = serde_json::to_value(self)
+      .map_err(|e| log::error!("Failed to hash the game state: {}", e))
+      .ok()?;
+
+    let mut json = String::new();
+    write_canonical_json(&value, &mut json);
+
+    let hash = json.bytes().fold(FNV_OFFSET_BASIS, |hash, byte| {
+      (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    });
+    Some(format!("{:016x}", hash))
+  }
+
+  /// Compute the changes needed to turn the previous game state into this game state
+  pub fn diff(&self, previous: &GameState) -> GameStateDelta {
+    GameStateDelta {
+      playfield: clone_if_changed(&self.playfield, &previous.playfield),
+      players: self
+        .players
+        .iter()
+        .filter(|(id, player)| previous.players.get(id) != Some(player))
+        .map(|(id, player)| (*id, player.clone()))
+        .collect(),
+      removed_players: previous
+        .players
+        .keys()
+        .filter(|id| !self.players.contains_key(id))
+        .copied()
+        .collect(),
+      weapons: clone_if_changed(&self.weapons, &previous.weapons),
+      items: clone_if_changed(&self.items, &previous.items),
+    }
+  }
+
+  /// Make sure the playfield grid matches the configured arena size
+  pub fn validate_playfield_size(&self, rows: u32, cols: u32) -> Result<(), GameEngineError> {
+    let invalid_size = |found_cols: usize| GameEngineError::InvalidPlayfieldSize {
+      expected: (rows, cols),
+      found: (self.playfield.len(), found_cols),
+    };
+
+    if self.playfield.len() != rows as usize {
+      return Err(invalid_size(self.playfield.first().map(Vec::len).unwrap_or(0)));
+    }
+
+    match self.playfield.iter().find(|row| row.len() != cols as usize) {
+      Some(row) => Err(invalid_size(row.len())),
+      None => Ok(()),
+    }
+  }
+}
+
+impl Player {
+  /// Get the (row, col) position in the playfield
+  pub fn get_position(&self) -> (u32, u32) {
+    (self.row, self.col)
+  }
+
+  pub fn get_health(&self) -> i32 {
+    self.health
+  }
+
+  pub fn get_weapon(&self) -> Option<&JSONMap<String, JSONValue>> {
+    self.weapon.as_ref()
+  }
+
+  pub fn get_extra(&self) -> &JSONMap<String, JSONValue> {
+    &self.extra
+  }
+}
+
+impl PlayerStats {
+  /// Count one more game tick
+  pub fn record_tick(&mut self, survived: bool, took_action: bool) {
+    self.ticks_survived += survived as u32;
+    self.actions_taken += took_action as u32;
+  }
+
+  pub fn get_ticks_survived(&self) -> u32 {
+    self.ticks_survived
+  }
+
+  pub fn get_actions_taken(&self) -> u32 {
+    self.actions_taken
+  }
+}
+
+impl Weapon {
+  pub fn get_id(&self) -> &str {
+    &self.id
+  }
+
+  /// Get the (row, col) position in the playfield
+  pub fn get_position(&self) -> (u32, u32) {
+    (self.row, self.col)
+  }
+
+  pub fn get_extra(&self) -> &JSONMap<String, JSONValue> {
+    &self.extra
+  }
+}
+
+impl Item {
+  pub fn get_id(&self) -> &str {
+    &self.id
+  }
+
+  /// Get the (row, col) position in the playfield
+  pub fn get_position(&self) -> (u32, u32) {
+    (self.row, self.col)
+  }
+
+  pub fn get_extra(&self) -> &JSONMap<String, JSONValue> {
+    &self.extra
+  }
+}
+
+/// Make sure a Lua value isn't nested more than "depth_left" tables deep
+fn check_lua_depth(value: &LuaValue, depth_left: usize) -> Result<(), GameEngineError> {
+  let table = match value {
+    LuaValue::Table(table) => table,
+    _ => return Ok(()),
+  };
+
+  if depth_left == 0 {
+    return Err(GameEngineError::LuaTableTooDeep(MAX_LUA_TABLE_DEPTH));
+  }
+
+  for pair in table.clone().pairs::<LuaValue, LuaValue>() {
+    let (key, value) = pair.map_err(GameEngineError::LuaToJSON)?;
+    check_lua_depth(&key, depth_left - 1)?;
+    check_lua_depth(&value, depth_left - 1)?;
+  }
+
+  Ok(())
+}
+
+/// Write the JSON value with every object key sorted, since map iteration order isn't stable
+fn write_canonical_json(value: &JSONValue, out: &mut String) {
+  match value {
+    JSONValue::Array(values) => {
+      out.push('[');
+      for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+          out.push(',');
+        }
+        write_canonical_json(value, out);
+      }
+      out.push(']');
+    },
+    JSONValue::Object(map) => {
+      let mut keys: Vec<_> = map.keys().collect();
+      keys.sort();
+
+      out.push('{');
+      for (i, key) in keys.into_iter().enumerate() {
+        if i > 0 {
+          out.push(',');
+        }
+        out.push_str(&JSONValue::from(key.as_str()).to_string());
+        out.push(':');
+        write_canonical_json(&map[key], out);
+      }
+      out.push('}');
+    },
+    value => out.push_str(&value.to_string()),
+  }
+}
+
+/// Returns a copy of the current value only if it is different from the previous value
+fn clone_if_changed<T: Clone + PartialEq>(current: &T, previous: &T) -> Option<T> {
+  if current != previous {
+    Some(current.clone())
+  } else {
+    None
+  }
 }
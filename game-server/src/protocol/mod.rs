@@ -2,6 +2,7 @@
 // Data structures to faciliate communication to the game
 //
 pub mod actions;
+pub mod encoding;
 pub mod game;
 pub mod query;
 pub mod registration;
@@ -9,22 +10,30 @@ pub mod tagged_request;
 pub mod websocket;
 
 pub use actions::PlayerAction;
-pub use game::{GameState, GameStateUpdate};
-pub use query::QueryResponse;
+pub use encoding::{ConnectQuery, Encoding};
+pub use game::{GameState, GameStateDelta, GameStateUpdate, Item, Player, PlayerStats, Weapon};
+pub use query::{MatchResult, QueryReply, QueryRequest, QueryResponse, SentAction};
 pub use registration::RegistrationUpdateEnum;
 pub use tagged_request::TaggedRequest;
-pub use websocket::{ViewerMessage, WebsocketMessage};
+pub use websocket::{MessageType, ViewerMessage, WebsocketMessage};
 
+use bytes::Bytes;
 use bytestring::ByteString;
-use serde::Serialize;
+use serde::{de::DeserializeOwned, ser::Error as _, Serialize};
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+
+/// Version of the wire format, sent to clients in the game config, game starting, and init messages
+///   Bump this whenever the wire format changes, so old clients can detect the mismatch
+pub const PROTOCOL_VERSION: u32 = 2;
 
 /// Helpful trait to convert a serializable type into a ByteString
 pub trait ToBytestring {
   /// Serialize the object into a bytestring
-  fn to_bytestring(&self) -> ByteString;
+  fn to_bytestring(&self) -> serde_json::Result<ByteString>;
 
   /// Consume the object and convert into a bytestring
-  fn into_bytestring(self) -> ByteString
+  fn into_bytestring(self) -> serde_json::Result<ByteString>
   where
     Self: Sized,
   {
@@ -36,7 +45,168 @@ impl<T> ToBytestring for T
 where
   T: Serialize,
 {
-  fn to_bytestring(&self) -> ByteString {
-    serde_json::to_string(&self).unwrap().into()
+  fn to_bytestring(&self) -> serde_json::Result<ByteString> {
+    serde_json::to_string(&self).map(ByteString::from)
+  }
+}
+
+/// Helpful trait to convert a serializable type into MessagePack bytes
+pub trait ToMsgpack {
+  /// Serialize the object into MessagePack bytes
+  fn to_msgpack(&self) -> Result<Bytes, rmp_serde::encode::Error>;
+
+  /// Consume the object and convert into MessagePack bytes
+  fn into_msgpack(self) -> Result<Bytes, rmp_serde::encode::Error>
+  where
+    Self: Sized,
+  {
+    self.to_msgpack()
+  }
+}
+
+impl<T> ToMsgpack for T
+where
+  T: Serialize,
+{
+  fn to_msgpack(&self) -> Result<Bytes, rmp_serde::encode::Error> {
+    to_msgpack(&self).map(Bytes::from)
+  }
+}
+
+/// Serialize an object into MessagePack
+///   Structs are encoded as maps and UUIDs as strings, so the data matches the JSON messages
+pub fn to_msgpack<T>(data: &T) -> Result<Vec<u8>, rmp_serde::encode::Error>
+where
+  T: ?Sized + Serialize,
+{
+  let mut bytes = Vec::new();
+  data.serialize(
+    &mut rmp_serde::Serializer::new(&mut bytes)
+      .with_struct_map()
+      .with_human_readable(),
+  )?;
+  Ok(bytes)
+}
+
+/// Deserialize an object from MessagePack
+pub fn from_msgpack<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, rmp_serde::decode::Error> {
+  T::deserialize(&mut rmp_serde::Deserializer::from_read_ref(bytes).with_human_readable())
+}
+
+/// Object-safe version of the encoding traits, so a message can be serialized without knowing its type
+pub trait EncodeMessage {
+  fn encode_json(&self) -> serde_json::Result<ByteString>;
+  fn encode_msgpack(&self) -> Result<Bytes, rmp_serde::encode::Error>;
+}
+
+impl<T> EncodeMessage for T
+where
+  T: Serialize,
+{
+  fn encode_json(&self) -> serde_json::Result<ByteString> {
+    self.to_bytestring()
+  }
+
+  fn encode_msgpack(&self) -> Result<Bytes, rmp_serde::encode::Error> {
+    self.to_msgpack()
+  }
+}
+
+/// Builds a message and passes it to the encoder, as many times as the message needs to be encoded
+type MessageSource = dyn Fn(&mut dyn FnMut(&dyn EncodeMessage)) + Send + Sync;
+
+/// Message that has already been serialized for the clients
+///   Broadcast messages are serialized once, then cheaply cloned for every client
+///   MessagePack is only encoded the first time a MessagePack client needs it, and shared by all clones
+///   Serialization errors are logged here, and the encoding is left empty so sending it fails
+#[derive(Debug, Clone)]
+pub struct EncodedMessage {
+  json: Option<ByteString>,
+  msgpack: Arc<LazyMsgpack>,
+}
+
+/// Keeps the message source around until the MessagePack encoding is needed
+struct LazyMsgpack {
+  source: Box<MessageSource>,
+  bytes: OnceLock<Option<Bytes>>,
+}
+
+impl fmt::Debug for LazyMsgpack {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("LazyMsgpack").field("bytes", &self.bytes).finish()
+  }
+}
+
+impl EncodedMessage {
+  pub fn new<T>(data: T) -> Self
+  where
+    T: Serialize + Send + Sync + 'static,
+  {
+    Self::from_fn(move |encode| encode(&data))
+  }
+
+  /// Encode a message that borrows its data, like a game state shared through an Arc
+  ///   The source must build the same message every time it is called
+  pub fn from_fn<F>(source: F) -> Self
+  where
+    F: Fn(&mut dyn FnMut(&dyn EncodeMessage)) + Send + Sync + 'static,
+  {
+    let mut json = None;
+    source(&mut |message| {
+      json = message
+        .encode_json()
+        .map_err(|e| log::error!("Failed to serialize JSON message: {}", e))
+        .ok()
+    });
+
+    Self {
+      json,
+      msgpack: Arc::new(LazyMsgpack {
+        source: Box::new(source),
+        bytes: OnceLock::new(),
+      }),
+    }
+  }
+
+  /// Get the MessagePack encoding, serializing the message on first use
+  fn get_msgpack(&self) -> Option<Bytes> {
+    self
+      .msgpack
+      .bytes
+      .get_or_init(|| {
+        let mut bytes = None;
+        (self.msgpack.source)(&mut |message| {
+          bytes = message
+            .encode_msgpack()
+            .map_err(|e| log::error!("Failed to serialize MessagePack message: {}", e))
+            .ok()
+        });
+        bytes
+      })
+      .clone()
+  }
+}
+
+impl ToBytestring for EncodedMessage {
+  fn to_bytestring(&self) -> serde_json::Result<ByteString> {
+    self.clone().into_bytestring()
+  }
+
+  fn into_bytestring(self) -> serde_json::Result<ByteString> {
+    self
+      .json
+      .ok_or_else(|| serde_json::Error::custom("message could not be serialized into JSON"))
+  }
+}
+
+impl ToMsgpack for EncodedMessage {
+  fn to_msgpack(&self) -> Result<Bytes, rmp_serde::encode::Error> {
+    self.clone().into_msgpack()
+  }
+
+  fn into_msgpack(self) -> Result<Bytes, rmp_serde::encode::Error> {
+    self
+      .get_msgpack()
+      .ok_or_else(|| rmp_serde::encode::Error::custom("message could not be serialized into MessagePack"))
   }
 }
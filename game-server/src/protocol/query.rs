@@ -1,16 +1,24 @@
-use serde::Serialize;
-use std::collections::HashMap;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
 
+use crate::config;
 use crate::game::ServerState;
 use crate::jwt::JWTPlayerData;
+use crate::protocol::{GameState, Player, PlayerAction, PROTOCOL_VERSION};
 
 /// List of all responses to a query
 #[derive(Debug, Clone, Serialize)]
 #[serde(tag = "type", rename_all = "camelCase")]
 pub enum QueryResponse {
   #[serde(rename_all = "camelCase")]
-  ServerState { state: ServerState },
+  ServerState {
+    state: ServerState,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    match_id: Option<Uuid>,
+  },
 
   #[serde(rename_all = "camelCase")]
   RegisteredPlayers {
@@ -19,4 +27,159 @@ pub enum QueryResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
     player_order: Option<Vec<Uuid>>,
   },
+
+  #[serde(rename_all = "camelCase")]
+  GameConfig {
+    min_players_needed: usize,
+    max_players_allowed: usize,
+    ticks_per_game: u32,
+    seconds_per_tick: u32,
+    arena_width: u32,
+    arena_height: u32,
+    protocol_version: u32,
+  },
+
+  #[serde(rename_all = "camelCase")]
+  RecentResults { matches: Vec<MatchResult> },
+
+  #[serde(rename_all = "camelCase")]
+  Spectating { id: Option<Uuid>, player_view: bool },
+
+  #[serde(rename_all = "camelCase")]
+  TimeRemaining { ticks_left: u32, seconds_per_tick: u32 },
+
+  #[serde(rename_all = "camelCase")]
+  GameState {
+    server_state: ServerState,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    match_id: Option<Uuid>,
+
+    game_state: Option<GameState>,
+  },
+
+  #[serde(rename_all = "camelCase")]
+  MyActions { actions: Vec<SentAction> },
+
+  #[serde(rename_all = "camelCase")]
+  MyState {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    match_id: Option<Uuid>,
+
+    player: Option<Player>,
+  },
+
+  #[serde(rename_all = "camelCase")]
+  ReconnectToken { token: String },
+
+  #[serde(rename_all = "camelCase")]
+  ActionAccepted {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag: Option<String>,
+  },
+}
+
+/// Queries can include an optional request ID, which is echoed back in the response
+///   This lets clients match up responses when they send several queries at once
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryRequest {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub request_id: Option<String>,
+}
+
+/// Response to a query, along with the request ID from the query (if one was given)
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryReply {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  request_id: Option<String>,
+
+  #[serde(flatten)]
+  response: QueryResponse,
+}
+
+/// Summary of a finished game round
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchResult {
+  match_id: Uuid,
+  winners: HashSet<Uuid>,
+  players: HashMap<Uuid, JWTPlayerData>,
+  ended_at: String,
+}
+
+/// Player action that was accepted by the server
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SentAction {
+  tick: u32, // Current tick of the game when the action was received
+
+  #[serde(flatten)]
+  action: PlayerAction,
+}
+
+impl MatchResult {
+  pub fn new(match_id: Uuid, winners: HashSet<Uuid>, players: HashMap<Uuid, JWTPlayerData>) -> Self {
+    Self {
+      match_id,
+      winners,
+      players,
+      ended_at: Utc::now().to_rfc3339(),
+    }
+  }
+
+  pub fn get_match_id(&self) -> Uuid {
+    self.match_id
+  }
+
+  pub fn get_winners(&self) -> &HashSet<Uuid> {
+    &self.winners
+  }
+
+  pub fn get_players(&self) -> &HashMap<Uuid, JWTPlayerData> {
+    &self.players
+  }
+
+  pub fn get_ended_at(&self) -> &str {
+    &self.ended_at
+  }
+}
+
+impl SentAction {
+  pub fn new(tick: u32, action: PlayerAction) -> Self {
+    Self { tick, action }
+  }
+
+  pub fn get_tick(&self) -> u32 {
+    self.tick
+  }
+
+  pub fn get_action(&self) -> &PlayerAction {
+    &self.action
+  }
+}
+
+impl QueryResponse {
+  /// Attach the request ID from the query that asked for this response
+  pub fn reply_to(self, query: QueryRequest) -> QueryReply {
+    QueryReply {
+      request_id: query.request_id,
+      response: self,
+    }
+  }
+
+  /// Game rules are fixed for the entire server run, so read them directly from the configuration
+  pub fn game_config() -> Self {
+    let min_players_needed = config::get_min_players_needed();
+    QueryResponse::GameConfig {
+      min_players_needed,
+      max_players_allowed: config::get_max_players_allowed().max(min_players_needed),
+      ticks_per_game: config::get_ticks_per_game(),
+      seconds_per_tick: config::get_seconds_per_tick(),
+      arena_width: config::get_arena_width(),
+      arena_height: config::get_arena_height(),
+      protocol_version: PROTOCOL_VERSION,
+    }
+  }
 }
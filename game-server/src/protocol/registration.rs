@@ -27,10 +27,31 @@ pub enum RegistrationUpdateEnum {
     seconds_left: u32,
   },
 
+  /// Sent every second during the countdown, the player list is only sent again when it changes
+  #[serde(rename_all = "camelCase")]
+  CountdownTick { seconds_left: u32 },
+
+  /// A single player registered (or updated their details), only sent if REGISTRATION_DELTAS is enabled
+  #[serde(rename_all = "camelCase")]
+  PlayerRegistered { id: Uuid, data: JWTPlayerData },
+
+  /// A single player unregistered, only sent if REGISTRATION_DELTAS is enabled
+  #[serde(rename_all = "camelCase")]
+  PlayerUnregistered { id: Uuid },
+
   /// Game is starting NOW!
   #[serde(rename_all = "camelCase")]
   GameStarting {
+    match_id: Uuid,
     players: HashMap<Uuid, JWTPlayerData>,
     player_order: Vec<Uuid>,
+    protocol_version: u32,
+  },
+
+  /// Game has started, but some registered players still need to connect
+  #[serde(rename_all = "camelCase")]
+  WaitingOnConnections {
+    missing_players: Vec<Uuid>,
+    seconds_left: u32,
   },
 }
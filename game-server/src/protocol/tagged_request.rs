@@ -3,7 +3,7 @@ use serde::{Deserialize, Serialize};
 use crate::protocol::actions::*;
 
 /// Some requests can include an optional tag, used by the clients
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TaggedRequest<T> {
   #[serde(skip_serializing_if = "Option::is_none")]
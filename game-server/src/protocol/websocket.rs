@@ -1,10 +1,12 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 use crate::protocol::actions::{AttackAction, DropWeaponAction, MoveAction};
-use crate::protocol::TaggedRequest;
+use crate::protocol::{QueryRequest, TaggedRequest};
 
 /// List of all messages that the player can sent to the WebSocket
-#[derive(Debug, Clone, Deserialize)]
+///   Also serializable, so the exact wire format can be checked against the clients
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "camelCase")]
 pub enum WebsocketMessage {
   // Registration
@@ -12,8 +14,12 @@ pub enum WebsocketMessage {
   Unregister,
 
   // Queries
-  GetServerState,
-  GetRegisteredPlayers,
+  GetServerState(QueryRequest),
+  GetRegisteredPlayers(QueryRequest),
+  GetTimeRemaining(QueryRequest),
+  GetGameConfig(QueryRequest),
+  GetMyState(QueryRequest),
+  GetMyActions(QueryRequest),
 
   // Player actions
   Move(TaggedRequest<MoveAction>),
@@ -25,6 +31,42 @@ pub enum WebsocketMessage {
 #[derive(Debug, Clone, Deserialize)]
 #[serde(tag = "type", rename_all = "camelCase")]
 pub enum ViewerMessage {
-  GetServerState,
-  GetRegisteredPlayers,
+  GetServerState(QueryRequest),
+  GetRegisteredPlayers(QueryRequest),
+  GetTimeRemaining(QueryRequest),
+  GetGameConfig(QueryRequest),
+  GetGameState(QueryRequest),
+  GetRecentResults {
+    limit: Option<usize>,
+
+    #[serde(flatten)]
+    query: QueryRequest,
+  },
+
+  // Viewer camera
+  SpectatePlayer {
+    id: Option<Uuid>,
+
+    #[serde(flatten)]
+    query: QueryRequest,
+  },
+}
+
+/// Just the "type" field of a message, used to give a more helpful error when the full message fails to parse
+#[derive(Debug, Clone, Deserialize)]
+pub struct MessageType {
+  #[serde(rename = "type")]
+  pub message_type: String,
+}
+
+impl MessageType {
+  /// Get the message type from a JSON message, if it has one
+  pub fn from_json(text: &str) -> Option<String> {
+    serde_json::from_str::<Self>(text).ok().map(|m| m.message_type)
+  }
+
+  /// Get the message type from a MessagePack message, if it has one
+  pub fn from_msgpack(bytes: &[u8]) -> Option<String> {
+    super::from_msgpack::<Self>(bytes).ok().map(|m| m.message_type)
+  }
 }
@@ -0,0 +1,50 @@
+//
+// Loading the SSL configuration for serving over HTTPS
+//
+use rustls::{Certificate, PrivateKey, ServerConfig};
+use rustls_pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+
+///
+/// Load the certificate chain and private key PEM files into a server configuration
+///
+pub fn load_ssl_config(cert: &Path, key: &Path) -> anyhow::Result<ServerConfig> {
+  // Init server config builder with safe defaults
+  let config = ServerConfig::builder().with_safe_defaults().with_no_client_auth();
+
+  // Read the TLS key/cert files
+  let cert_file =
+    &mut BufReader::new(File::open(cert).map_err(|e| anyhow::anyhow!("Failed to open '{}': {}", cert.display(), e))?);
+  let key_pem = fs::read(key).map_err(|e| anyhow::anyhow!("Failed to open '{}': {}", key.display(), e))?;
+
+  // Convert files to key/cert objects
+  //  Try PKCS 8 ("BEGIN PRIVATE KEY") first, then fall back to PKCS 1 ("BEGIN RSA PRIVATE KEY")
+  let cert_chain: Vec<_> = certs(cert_file)?.into_iter().map(Certificate).collect();
+  let (mut keys, key_format) = match pkcs8_private_keys(&mut key_pem.as_slice())? {
+    keys if !keys.is_empty() => (keys, "PKCS 8"),
+    _ => (rsa_private_keys(&mut key_pem.as_slice())?, "PKCS 1"),
+  };
+
+  // Exit if no certificates or keys could be parsed
+  //  rustls would otherwise accept an empty certificate chain and fail every handshake later
+  if cert_chain.is_empty() {
+    Err(anyhow::anyhow!(
+      "Could not locate any certificates in '{}'",
+      cert.display()
+    ))?;
+  }
+  if keys.is_empty() {
+    Err(anyhow::anyhow!(
+      "Could not locate any PKCS 8 or PKCS 1 (RSA) private keys in '{}'",
+      key.display()
+    ))?;
+  }
+
+  let config = config.with_single_cert(cert_chain, PrivateKey(keys.remove(0)))?;
+  log::debug!("Loaded SSL key file ({}) from: {}", key_format, key.display());
+  log::debug!("Loaded SSL certificate chain file from: {}", cert.display());
+
+  Ok(config)
+}
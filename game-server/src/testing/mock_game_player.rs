@@ -0,0 +1,162 @@
+use actix::Addr;
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::Receiver;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver as ActionReceiver;
+use uuid::Uuid;
+
+use crate::actors::{shared_messages::*, GameMediatorActor};
+use crate::game::MIN_PLAYERS_PER_GAME;
+use crate::jwt::JWTPlayerData;
+use crate::protocol::{game::GameState, PlayerAction};
+
+/// Single step of a scripted game, run in order after the Init message
+#[derive(Debug, Clone)]
+pub enum MockStep {
+  /// Wait one tick, then send the game state along with any actions queued since the last tick
+  NextState(GameState),
+
+  /// Kill a player, given by their position in the player order
+  KillPlayer(usize),
+
+  /// Report a fatal error and stop, like the Lua engine does after running out of tries
+  Crash,
+}
+
+/// Scripted game played by the mock game engine
+///   The same script is played from the start for every match
+#[derive(Debug, Clone, Default)]
+pub struct MockScript {
+  pub initial_state: GameState,
+  pub steps: Vec<MockStep>,
+  pub tick_delay: Duration, // Time to wait before each "NextState" step
+}
+
+/// Stand-in for the Lua game engine that follows the same message contract:
+///   Wait for the start signal, then send Init, NextState, PlayerKilled, and GameEnded to the mediator
+///
+///   The game always ends once the script runs out of steps, with every player still alive as a winner
+pub struct MockGamePlayer {
+  script: MockScript,
+  recv_start_game: Receiver<(Uuid, Vec<Uuid>, HashMap<Uuid, JWTPlayerData>)>,
+  recv_player_actions: ActionReceiver<(Uuid, PlayerAction)>,
+  mediator_addr: Addr<GameMediatorActor>,
+}
+
+impl MockGamePlayer {
+  pub fn new(
+    script: MockScript,
+    recv_start_game: Receiver<(Uuid, Vec<Uuid>, HashMap<Uuid, JWTPlayerData>)>,
+    recv_player_actions: ActionReceiver<(Uuid, PlayerAction)>,
+    mediator_addr: Addr<GameMediatorActor>,
+  ) -> Self {
+    Self {
+      script,
+      recv_start_game,
+      recv_player_actions,
+      mediator_addr,
+    }
+  }
+
+  /// Run the mock game engine on a separate thread
+  pub fn spawn(mut self) -> JoinHandle<()> {
+    thread::spawn(move || self.run_game())
+  }
+
+  /// Play the script for every match, until the start channel is closed
+  pub fn run_game(&mut self) {
+    while let Ok((match_id, player_order, _)) = self.recv_start_game.recv() {
+      if player_order.len() < MIN_PLAYERS_PER_GAME {
+        self.mediator_addr.do_send(GameCancelled {
+          num_players: player_order.len(),
+        });
+        continue;
+      }
+
+      log::info!(match_id:% = match_id; "Playing scripted game with {} step(s)", self.script.steps.len());
+      if !self.play_match(match_id, &player_order) {
+        return;
+      }
+    }
+
+    log::info!("Channel closed: 'start_game', stopping mock game engine...");
+  }
+
+  /// Returns false if the script crashed the game engine
+  fn play_match(&mut self, match_id: Uuid, player_order: &[Uuid]) -> bool {
+    let seconds_per_tick = self.script.tick_delay.as_secs() as u32;
+    let mut ticks_left = self.count_ticks();
+    let mut current_tick = 0;
+    let mut players_remaining: HashSet<Uuid> = player_order.iter().copied().collect();
+    let mut game_state = self.script.initial_state.clone();
+    let mut player_actions = HashMap::new();
+
+    self.mediator_addr.do_send(Init::new(
+      match_id,
+      player_order,
+      game_state.clone(),
+      ticks_left,
+      seconds_per_tick,
+    ));
+
+    for step in self.script.steps.clone() {
+      match step {
+        MockStep::NextState(next_state) => {
+          thread::sleep(self.script.tick_delay);
+          player_actions = self.collect_player_actions(&players_remaining);
+          current_tick += 1;
+          ticks_left -= 1;
+          game_state = next_state;
+
+          let state_hash = game_state.state_hash();
+          self.mediator_addr.do_send(NextState::new(
+            match_id,
+            game_state.clone(),
+            player_actions.clone(),
+            current_tick,
+            ticks_left,
+            seconds_per_tick,
+            state_hash,
+          ));
+        },
+
+        MockStep::KillPlayer(index) => match player_order.get(index) {
+          Some(player_id) if players_remaining.remove(player_id) => {
+            self.mediator_addr.do_send(PlayerKilled::new(match_id, *player_id));
+          },
+          _ => log::warn!("Mock game engine cannot kill player #{}, skipping", index),
+        },
+
+        MockStep::Crash => {
+          log::error!("Fatal error: scripted crash");
+          self.mediator_addr.do_send(GameEngineCrash { timed_out: false });
+          return false;
+        },
+      }
+    }
+
+    self
+      .mediator_addr
+      .do_send(GameEnded::new(match_id, players_remaining, game_state, player_actions));
+    true
+  }
+
+  /// Number of ticks in the script
+  fn count_ticks(&self) -> u32 {
+    let steps = self.script.steps.iter();
+    steps.filter(|step| matches!(step, MockStep::NextState(_))).count() as u32
+  }
+
+  /// Take every action queued since the last tick, keeping the latest action from each player still alive
+  fn collect_player_actions(&mut self, players_remaining: &HashSet<Uuid>) -> HashMap<Uuid, PlayerAction> {
+    let mut player_actions = HashMap::new();
+    while let Ok((player_id, action)) = self.recv_player_actions.try_recv() {
+      if players_remaining.contains(&player_id) {
+        player_actions.insert(player_id, action);
+      }
+    }
+
+    player_actions
+  }
+}
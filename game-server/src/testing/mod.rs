@@ -0,0 +1,11 @@
+//
+// Helpers for running the game server inside integration tests
+//   Only compiled with the "testing" feature
+//
+mod mock_game_player;
+mod test_client;
+mod test_server;
+
+pub use mock_game_player::{MockGamePlayer, MockScript, MockStep};
+pub use test_client::{TestClient, RECV_TIMEOUT};
+pub use test_server::{TestServer, TEST_JWT_SECRET};
@@ -0,0 +1,111 @@
+use actix_codec::Framed;
+use awc::ws::{CloseReason, Codec, Frame, Message};
+use awc::BoxedSocket;
+use futures::{SinkExt, StreamExt};
+use serde::Serialize;
+use serde_json::Value;
+use std::time::Duration;
+
+use crate::config;
+
+/// How long to wait for the next message before giving up
+pub const RECV_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Websocket client for talking to a test server, using the JSON encoding
+pub struct TestClient {
+  framed: Framed<BoxedSocket, Codec>,
+}
+
+impl TestClient {
+  /// Open a websocket connection, passing the token in the "Sec-WebSocket-Protocol" header
+  pub async fn connect(url: &str, token: &str) -> anyhow::Result<Self> {
+    let (response, framed) = awc::Client::new()
+      .ws(url)
+      .protocols([config::get_ws_protocol().as_str(), token])
+      .connect()
+      .await
+      .map_err(|e| anyhow::anyhow!("Failed to connect to '{}': {}", url, e))?;
+
+    log::debug!("Connected to '{}' ({})", url, response.status());
+    Ok(Self { framed })
+  }
+
+  /// Send a message as JSON text
+  pub async fn send(&mut self, message: &impl Serialize) -> anyhow::Result<()> {
+    let json = serde_json::to_string(message)?;
+    self.framed.send(Message::Text(json.into())).await?;
+    Ok(())
+  }
+
+  /// Wait for the next JSON message
+  ///   Pings are answered automatically, and a close frame is returned as an error
+  pub async fn recv(&mut self) -> anyhow::Result<Value> {
+    tokio::time::timeout(RECV_TIMEOUT, self.next_message())
+      .await
+      .map_err(|_| anyhow::anyhow!("Timed out waiting for a message"))?
+  }
+
+  /// Wait for the next message with the given "type", skipping any other messages
+  pub async fn recv_type(&mut self, message_type: &str) -> anyhow::Result<Value> {
+    let find_message = async {
+      loop {
+        let message = self.next_message().await?;
+        if message["type"] == message_type {
+          return Ok(message);
+        }
+      }
+    };
+
+    tokio::time::timeout(RECV_TIMEOUT, find_message)
+      .await
+      .map_err(|_| anyhow::anyhow!("Timed out waiting for a \"{}\" message", message_type))?
+  }
+
+  /// Wait for the server to close the connection, skipping any other messages
+  ///   Returns the close reason sent by the server (if any)
+  pub async fn recv_close(&mut self) -> anyhow::Result<Option<CloseReason>> {
+    let find_close = async {
+      loop {
+        if let Frame::Close(reason) = self.next_frame().await? {
+          return Ok(reason);
+        }
+      }
+    };
+
+    tokio::time::timeout(RECV_TIMEOUT, find_close)
+      .await
+      .map_err(|_| anyhow::anyhow!("Timed out waiting for the websocket to close"))?
+  }
+
+  async fn next_message(&mut self) -> anyhow::Result<Value> {
+    loop {
+      match self.next_frame().await? {
+        Frame::Text(bytes) => return Ok(serde_json::from_slice(&bytes)?),
+        Frame::Close(reason) => return Err(anyhow::anyhow!("Websocket closed: {:?}", reason)),
+        _ => {},
+      }
+    }
+  }
+
+  /// Wait for the next frame, answering any pings along the way
+  async fn next_frame(&mut self) -> anyhow::Result<Frame> {
+    loop {
+      let frame = self
+        .framed
+        .next()
+        .await
+        .ok_or_else(|| anyhow::anyhow!("Websocket connection ended"))??;
+
+      match frame {
+        Frame::Ping(bytes) => self.framed.send(Message::Pong(bytes)).await?,
+        frame => return Ok(frame),
+      }
+    }
+  }
+
+  /// Close the connection normally
+  pub async fn close(mut self) -> anyhow::Result<()> {
+    self.framed.send(Message::Close(None)).await?;
+    Ok(())
+  }
+}
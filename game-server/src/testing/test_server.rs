@@ -0,0 +1,190 @@
+use actix::{Actor, Addr};
+use actix_web::dev::ServerHandle;
+use actix_web::{web, App, HttpServer};
+use chrono::Duration;
+use std::net::SocketAddr;
+use std::sync::mpsc::channel;
+use tokio::sync::mpsc::{self, Sender};
+use uuid::Uuid;
+
+use crate::actors::{GameMediatorActor, MediatorConfig};
+use crate::game::{GameEngine, GamePlayer, ACTION_QUEUE_CAPACITY};
+use crate::handlers;
+use crate::jwt::{JWTPlayerData, JWTSecret, PlayerToken, ViewerToken};
+use crate::metrics::Metrics;
+use crate::protocol::PlayerAction;
+use crate::testing::{MockGamePlayer, MockScript, TestClient};
+
+/// Secret used to sign and check every token for the test server
+pub const TEST_JWT_SECRET: &str = "test-secret";
+
+/// Full game server running inside the current process, listening on a random local port
+///   Must be started from inside an Actix system, such as an "#[actix_web::test]" function
+///
+///   The mediator address and action channel are exposed so tests can register players or
+///   inject actions directly, without going through the websockets
+pub struct TestServer {
+  mediator: Addr<GameMediatorActor>,
+  send_player_actions: Sender<(Uuid, PlayerAction)>,
+  game_engine: Option<GameEngine>, // Not set when running the mock game engine
+  metrics: Metrics,
+  jwt_secret: JWTSecret,
+  addr: SocketAddr,
+  handle: ServerHandle,
+}
+
+impl TestServer {
+  /// Start the game mediator and a Lua game engine running the given code
+  ///   Settings for the game engine itself (ticks, arena size, ...) are still read from the environment
+  pub async fn start(lua_code: &str, settings: MediatorConfig) -> anyhow::Result<Self> {
+    // Same wiring as the real server
+    let (send_start_game, recv_start_game) = channel();
+    let (send_player_actions, recv_player_actions) = mpsc::channel(ACTION_QUEUE_CAPACITY);
+    let metrics = Metrics::new()?;
+
+    let mediator =
+      GameMediatorActor::new_with_config(send_start_game, send_player_actions.clone(), metrics.clone(), settings)
+        .start();
+
+    let game_player = GamePlayer::from_lua_code(
+      lua_code,
+      recv_start_game,
+      recv_player_actions,
+      mediator.clone(),
+      metrics.clone(),
+    )
+    .map_err(|e| anyhow::anyhow!("failed to start game engine: {}", e.get_developer_notes()))?;
+    let game_engine = GameEngine::spawn(game_player);
+
+    Self::listen(mediator, send_player_actions, Some(game_engine), metrics)
+  }
+
+  /// Start the game mediator with a mock game engine that plays the script, instead of running any Lua code
+  ///   The "/health" and "/admin/reboot" routes are not available, since there is no real game engine
+  pub async fn start_with_mock(script: MockScript, settings: MediatorConfig) -> anyhow::Result<Self> {
+    let (send_start_game, recv_start_game) = channel();
+    let (send_player_actions, recv_player_actions) = mpsc::channel(ACTION_QUEUE_CAPACITY);
+    let metrics = Metrics::new()?;
+
+    let mediator =
+      GameMediatorActor::new_with_config(send_start_game, send_player_actions.clone(), metrics.clone(), settings)
+        .start();
+
+    MockGamePlayer::new(script, recv_start_game, recv_player_actions, mediator.clone()).spawn();
+
+    Self::listen(mediator, send_player_actions, None, metrics)
+  }
+
+  /// Serve the API routes on a random local port, using the given mediator and game engine
+  fn listen(
+    mediator: Addr<GameMediatorActor>,
+    send_player_actions: Sender<(Uuid, PlayerAction)>,
+    game_engine: Option<GameEngine>,
+    metrics: Metrics,
+  ) -> anyhow::Result<Self> {
+    let jwt_secret = JWTSecret::new(TEST_JWT_SECRET);
+
+    let server = {
+      let jwt_secret = jwt_secret.clone();
+      let mediator = mediator.clone();
+      let send_player_actions = send_player_actions.clone();
+      let game_engine = game_engine.clone().map(web::Data::new);
+      let metrics = metrics.clone();
+
+      HttpServer::new(move || {
+        App::new()
+          .app_data(web::Data::new(jwt_secret.clone()))
+          .app_data(web::Data::new(mediator.clone()))
+          .app_data(web::Data::new(send_player_actions.clone()))
+          .configure(|cfg| {
+            if let Some(ref game_engine) = game_engine {
+              cfg.app_data(game_engine.clone());
+            }
+          })
+          .app_data(web::Data::new(metrics.clone()))
+          .service(handlers::api_v1())
+      })
+      .workers(1)
+      .disable_signals()
+      .bind(("127.0.0.1", 0))?
+    };
+
+    let addr = server.addrs()[0];
+    let server = server.run();
+    let handle = server.handle();
+    actix_web::rt::spawn(server);
+
+    log::debug!("Test server listening on {}", addr);
+    Ok(Self {
+      mediator,
+      send_player_actions,
+      game_engine,
+      metrics,
+      jwt_secret,
+      addr,
+      handle,
+    })
+  }
+
+  pub fn get_mediator(&self) -> &Addr<GameMediatorActor> {
+    &self.mediator
+  }
+
+  /// Sender for injecting player actions straight into the game engine queue
+  pub fn get_action_sender(&self) -> &Sender<(Uuid, PlayerAction)> {
+    &self.send_player_actions
+  }
+
+  pub fn get_game_engine(&self) -> Option<&GameEngine> {
+    self.game_engine.as_ref()
+  }
+
+  pub fn get_metrics(&self) -> &Metrics {
+    &self.metrics
+  }
+
+  pub fn get_addr(&self) -> SocketAddr {
+    self.addr
+  }
+
+  /// Full websocket URL for an API path, such as "/api/v1/play"
+  pub fn ws_url(&self, path: &str) -> String {
+    format!("ws://{}{}", self.addr, path)
+  }
+
+  /// Create a player token signed with the test secret
+  ///   Returns the new player ID along with the token
+  pub fn player_token(&self, name: &str) -> anyhow::Result<(Uuid, String)> {
+    let id = Uuid::new_v4();
+    let token =
+      PlayerToken::new(id, Duration::hours(1), JWTPlayerData::new(name)).encode(&self.jwt_secret.get_encoding_key())?;
+
+    Ok((id, token))
+  }
+
+  /// Create a viewer token signed with the test secret
+  pub fn viewer_token(&self) -> anyhow::Result<String> {
+    let token = ViewerToken::new(Uuid::new_v4(), Duration::hours(1), ()).encode(&self.jwt_secret.get_encoding_key())?;
+    Ok(token)
+  }
+
+  /// Connect a new player with a fresh token
+  ///   Returns the player ID along with the websocket client
+  pub async fn connect_player(&self, name: &str) -> anyhow::Result<(Uuid, TestClient)> {
+    let (id, token) = self.player_token(name)?;
+    let client = TestClient::connect(&self.ws_url("/api/v1/play"), &token).await?;
+    Ok((id, client))
+  }
+
+  /// Connect a new viewer with a fresh token
+  pub async fn connect_viewer(&self) -> anyhow::Result<TestClient> {
+    let token = self.viewer_token()?;
+    TestClient::connect(&self.ws_url("/api/v1/view"), &token).await
+  }
+
+  /// Stop listening and close any open connections
+  ///   The mediator and game engine stop once the Actix system shuts down
+  pub async fn stop(self) {
+    self.handle.stop(false).await;
+  }
+}
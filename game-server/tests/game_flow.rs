@@ -0,0 +1,411 @@
+//
+// End-to-end tests that run the mediator and a game engine over real websockets
+//   Run with "cargo test --features testing"
+//
+use awc::ws::CloseCode;
+use game_server::actors::mediator_messages::SetLobbyWait;
+use game_server::actors::MediatorConfig;
+use game_server::protocol::actions::{Direction, MoveAction, PlayerActionEnum};
+use game_server::protocol::{GameState, PlayerAction};
+use game_server::testing::{MockScript, MockStep, TestServer};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Empty arena that ends the game on the first tick, with the first player in the order as the winner
+const FIRST_PLAYER_WINS: &str = r#"
+  local function emptyArena(ctx)
+    local rows, cols = ctx:getArenaSize()
+    local playfield = {}
+    for row = 1, rows do
+      playfield[row] = {}
+      for col = 1, cols do
+        playfield[row][col] = 0
+      end
+    end
+    return { playfield = playfield }
+  end
+
+  function Init(ctx, players)
+    return emptyArena(ctx)
+  end
+
+  function Update(ctx, actions)
+    ctx:forceGameEnd({ ctx:getPlayerOrder()[1] })
+    return emptyArena(ctx)
+  end
+"#;
+
+/// Three-tick game where each player's view of the arena is covered in walls
+const FOG_OF_WAR: &str = r#"
+  local function arena(ctx, tile)
+    local rows, cols = ctx:getArenaSize()
+    local playfield = {}
+    for row = 1, rows do
+      playfield[row] = {}
+      for col = 1, cols do
+        playfield[row][col] = tile
+      end
+    end
+    return { playfield = playfield }
+  end
+
+  local ticks = 0
+
+  function Init(ctx, players)
+    return arena(ctx, 0)
+  end
+
+  function Update(ctx, actions)
+    ticks = ticks + 1
+    if ticks > 2 then
+      ctx:forceGameEnd({ ctx:getPlayerOrder()[1] })
+    end
+    return arena(ctx, 0)
+  end
+
+  function ViewFor(ctx, state, playerID)
+    return arena(ctx, 1)
+  end
+"#;
+
+/// ViewFor tries to end the game, which only Update is allowed to do
+const VIEW_FOR_ENDS_GAME: &str = r#"
+  local function emptyArena(ctx)
+    local rows, cols = ctx:getArenaSize()
+    local playfield = {}
+    for row = 1, rows do
+      playfield[row] = {}
+      for col = 1, cols do
+        playfield[row][col] = 0
+      end
+    end
+    return { playfield = playfield }
+  end
+
+  function Init(ctx, players)
+    return emptyArena(ctx)
+  end
+
+  function Update(ctx, actions)
+    return emptyArena(ctx)
+  end
+
+  function ViewFor(ctx, state, playerID)
+    if ctx:getCurrentTick() > 0 then
+      ctx:forceGameEnd({ playerID })
+    end
+    return state
+  end
+"#;
+
+fn two_player_settings() -> MediatorConfig {
+  MediatorConfig {
+    min_players_needed: 2,
+    max_players_allowed: 2,
+    lobby_wait_secs: 1,
+    start_when_full: true,
+    bot_fill_secs: None,
+    connect_grace_secs: 5,
+    unregister_on_disconnect: false,
+    reconnect_replaces_old: false,
+    use_delta_updates: false,
+    registration_deltas: false,
+    replay_dir: None,
+  }
+}
+
+#[actix_web::test]
+async fn registered_players_play_a_full_game() -> anyhow::Result<()> {
+  let server = TestServer::start(FIRST_PLAYER_WINS, two_player_settings()).await?;
+  let (_, mut alice) = server.connect_player("alice").await?;
+  let (_, mut bob) = server.connect_player("bob").await?;
+  let mut viewer = server.connect_viewer().await?;
+
+  for player in [&mut alice, &mut bob] {
+    player.send(&serde_json::json!({ "type": "register" })).await?;
+  }
+
+  let starting = viewer.recv_type("gameStarting").await?;
+  let init = viewer.recv_type("init").await?;
+  assert_eq!(init["matchId"], starting["matchId"]);
+
+  let ended = viewer.recv_type("gameEnded").await?;
+  assert_eq!(ended["matchId"], starting["matchId"]);
+  assert_eq!(ended["winners"], serde_json::json!([starting["playerOrder"][0]]));
+
+  for player in [&mut alice, &mut bob] {
+    player.recv_type("gameEnded").await?;
+  }
+
+  server.stop().await;
+  Ok(())
+}
+
+#[actix_web::test]
+async fn unregistered_player_is_kicked_when_the_game_starts() -> anyhow::Result<()> {
+  let server = TestServer::start(FIRST_PLAYER_WINS, two_player_settings()).await?;
+  let (_, mut alice) = server.connect_player("alice").await?;
+  let (_, mut bob) = server.connect_player("bob").await?;
+  let (_, mut carol) = server.connect_player("carol").await?;
+
+  for player in [&mut alice, &mut bob] {
+    player.send(&serde_json::json!({ "type": "register" })).await?;
+  }
+
+  // Carol never registered, so she gets an error and the connection is closed once the game starts
+  let error = carol.recv_type("error").await?;
+  assert_eq!(error["errorCode"], 11); // NotRegistered
+
+  let reason = carol.recv_close().await?;
+  assert_eq!(reason.map(|reason| reason.code), Some(CloseCode::Error));
+  alice.recv_type("gameStarting").await?;
+
+  server.stop().await;
+  Ok(())
+}
+
+#[actix_web::test]
+async fn mock_engine_broadcasts_the_scripted_game() -> anyhow::Result<()> {
+  let script = MockScript {
+    initial_state: GameState::default(),
+    steps: vec![
+      MockStep::NextState(GameState::default()),
+      MockStep::KillPlayer(1),
+      MockStep::NextState(GameState::default()),
+    ],
+    tick_delay: Duration::from_millis(200),
+  };
+
+  let server = TestServer::start_with_mock(script, two_player_settings()).await?;
+  let (_, mut alice) = server.connect_player("alice").await?;
+  let (_, mut bob) = server.connect_player("bob").await?;
+  let mut viewer = server.connect_viewer().await?;
+
+  for player in [&mut alice, &mut bob] {
+    player.send(&serde_json::json!({ "type": "register" })).await?;
+  }
+
+  let starting = viewer.recv_type("gameStarting").await?;
+  let first_player: Uuid = serde_json::from_value(starting["playerOrder"][0].clone())?;
+  let second_player = starting["playerOrder"][1].clone();
+  viewer.recv_type("init").await?;
+
+  // Skip the websockets and queue the action for the first tick directly
+  let action = PlayerAction::new_tagged(
+    PlayerActionEnum::Move(MoveAction {
+      direction: Direction::Up,
+    }),
+    "first",
+  );
+  server.get_action_sender().send((first_player, action)).await?;
+
+  let next_state = viewer.recv_type("nextState").await?;
+  assert_eq!(next_state["ticksLeft"], 1);
+  assert_eq!(
+    next_state["actionsTaken"][first_player.to_string()],
+    serde_json::json!({ "tag": "first", "type": "move", "direction": "up" })
+  );
+
+  let killed = viewer.recv_type("playerKilled").await?;
+  assert_eq!(killed["id"], second_player);
+
+  let ended = viewer.recv_type("gameEnded").await?;
+  assert_eq!(ended["winners"], serde_json::json!([first_player]));
+
+  server.stop().await;
+  Ok(())
+}
+
+#[actix_web::test]
+async fn viewer_spectates_a_players_view() -> anyhow::Result<()> {
+  for use_delta_updates in [false, true] {
+    let settings = MediatorConfig {
+      use_delta_updates,
+      ..two_player_settings()
+    };
+
+    let server = TestServer::start(FOG_OF_WAR, settings).await?;
+    let (_, mut alice) = server.connect_player("alice").await?;
+    let (_, mut bob) = server.connect_player("bob").await?;
+    let mut viewer = server.connect_viewer().await?;
+
+    for player in [&mut alice, &mut bob] {
+      player.send(&serde_json::json!({ "type": "register" })).await?;
+    }
+
+    let starting = viewer.recv_type("gameStarting").await?;
+    let init = viewer.recv_type("init").await?;
+    assert_eq!(init["gameState"]["playfield"][0][0], 0);
+
+    // Only players in the current match can be spectated
+    let stranger = Uuid::new_v4();
+    viewer
+      .send(&serde_json::json!({ "type": "spectatePlayer", "id": stranger }))
+      .await?;
+    let error = viewer.recv_type("error").await?;
+    assert_eq!(error["errorCode"], 22); // NotInMatch
+
+    let first_player = starting["playerOrder"][0].clone();
+    viewer
+      .send(&serde_json::json!({ "type": "spectatePlayer", "id": first_player, "requestId": "cam" }))
+      .await?;
+    let spectating = viewer.recv_type("spectating").await?;
+    assert_eq!(spectating["requestId"], "cam");
+    assert_eq!(spectating["id"], first_player);
+    assert_eq!(spectating["playerView"], true);
+
+    let next_state = viewer.recv_type("nextState").await?;
+    assert_eq!(next_state["gameState"]["playfield"][0][0], 1);
+
+    // With delta updates, the viewer gets the full state again before the next delta
+    viewer.send(&serde_json::json!({ "type": "spectatePlayer" })).await?;
+    if use_delta_updates {
+      let full_state = viewer.recv_type("nextState").await?;
+      assert_eq!(full_state["gameState"]["playfield"][0][0], 0);
+      assert_eq!(full_state["currentTick"], next_state["currentTick"]);
+    }
+
+    let stopped = viewer.recv_type("spectating").await?;
+    assert_eq!(stopped["id"], serde_json::Value::Null);
+
+    let update = viewer
+      .recv_type(if use_delta_updates { "delta" } else { "nextState" })
+      .await?;
+    assert_eq!(update["currentTick"], 2);
+
+    viewer.recv_type("gameEnded").await?;
+    server.stop().await;
+  }
+
+  Ok(())
+}
+
+#[actix_web::test]
+async fn view_for_cannot_end_the_game() -> anyhow::Result<()> {
+  let server = TestServer::start(VIEW_FOR_ENDS_GAME, two_player_settings()).await?;
+  let (_, mut alice) = server.connect_player("alice").await?;
+  let (_, mut bob) = server.connect_player("bob").await?;
+
+  for player in [&mut alice, &mut bob] {
+    player.send(&serde_json::json!({ "type": "register" })).await?;
+  }
+  alice.recv_type("init").await?;
+
+  // The context is read-only inside ViewFor, so the round can't end without a gameEnded message
+  let error = alice.recv_type("error").await?;
+  assert_eq!(error["errorCode"], 9); // GameEngineCrash
+
+  let reason = alice.recv_close().await?;
+  assert_eq!(reason.map(|reason| reason.code), Some(CloseCode::Error));
+
+  server.stop().await;
+  Ok(())
+}
+
+#[actix_web::test]
+async fn replay_records_full_states_when_sending_deltas() -> anyhow::Result<()> {
+  let replay_dir = std::env::temp_dir().join(format!("game-server-replays-{}", std::process::id()));
+  let settings = MediatorConfig {
+    use_delta_updates: true,
+    replay_dir: Some(replay_dir.clone()),
+    ..two_player_settings()
+  };
+
+  let server = TestServer::start(FOG_OF_WAR, settings).await?;
+  let (_, mut alice) = server.connect_player("alice").await?;
+  let (_, mut bob) = server.connect_player("bob").await?;
+
+  for player in [&mut alice, &mut bob] {
+    player.send(&serde_json::json!({ "type": "register" })).await?;
+  }
+
+  let starting = alice.recv_type("gameStarting").await?;
+  alice.recv_type("delta").await?;
+  alice.recv_type("gameEnded").await?;
+  server.stop().await;
+
+  // The file is written in the background, so wait for the last message to show up
+  let replay_file = replay_dir.join(format!("{}.jsonl", starting["matchId"].as_str().unwrap()));
+  let mut messages = Vec::new();
+  for _ in 0..50 {
+    let contents = std::fs::read_to_string(&replay_file).unwrap_or_default();
+    messages = contents
+      .lines()
+      .map(serde_json::from_str::<serde_json::Value>)
+      .collect::<Result<Vec<_>, _>>()?;
+    if messages.last().is_some_and(|last| last["type"] == "gameEnded") {
+      break;
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+  }
+  std::fs::remove_dir_all(&replay_dir)?;
+
+  let types: Vec<_> = messages
+    .iter()
+    .map(|message| message["type"].as_str().unwrap())
+    .collect();
+  assert_eq!(types, ["gameStarting", "init", "nextState", "nextState", "gameEnded"]);
+  for message in &messages[1..4] {
+    assert_eq!(message["gameState"]["playfield"][0][0], 0); // Full state, not a player view
+  }
+
+  Ok(())
+}
+
+#[actix_web::test]
+async fn lobby_wait_changes_the_running_countdown() -> anyhow::Result<()> {
+  let settings = MediatorConfig {
+    max_players_allowed: 4,
+    lobby_wait_secs: 60,
+    ..two_player_settings()
+  };
+
+  let server = TestServer::start(FIRST_PLAYER_WINS, settings).await?;
+  let (_, mut alice) = server.connect_player("alice").await?;
+  let (_, mut bob) = server.connect_player("bob").await?;
+
+  // No countdown to change yet
+  let response = server.get_mediator().send(SetLobbyWait { seconds: 50 }).await?;
+  assert_eq!(response.seconds_left, None);
+
+  for player in [&mut alice, &mut bob] {
+    player.send(&serde_json::json!({ "type": "register" })).await?;
+  }
+  let update = alice.recv_type("gameStartingSoon").await?;
+  assert_eq!(update["secondsLeft"], 50);
+
+  // Shortening the wait by at least the time left still leaves a second
+  let response = server.get_mediator().send(SetLobbyWait { seconds: 1 }).await?;
+  assert_eq!(response.lobby_wait_secs, 1);
+  assert_eq!(response.seconds_left, Some(1));
+
+  // Skip any tick sent before the change
+  while bob.recv_type("countdownTick").await?["secondsLeft"] != 1 {}
+  bob.recv_type("gameStarting").await?;
+
+  server.stop().await;
+  Ok(())
+}
+
+#[actix_web::test]
+async fn single_player_round_is_cancelled() -> anyhow::Result<()> {
+  let settings = MediatorConfig {
+    min_players_needed: 1,
+    start_when_full: false,
+    ..two_player_settings()
+  };
+
+  let server = TestServer::start(FIRST_PLAYER_WINS, settings).await?;
+  let (_, mut alice) = server.connect_player("alice").await?;
+  alice.send(&serde_json::json!({ "type": "register" })).await?;
+
+  let error = alice.recv_type("error").await?;
+  assert_eq!(error["errorCode"], 21); // NotEnoughPlayers
+
+  // Registration is open again, with alice still registered
+  let update = alice.recv_type("gameStartingSoon").await?;
+  assert_eq!(update["players"].as_object().map(|players| players.len()), Some(1));
+
+  server.stop().await;
+  Ok(())
+}
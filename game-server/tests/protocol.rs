@@ -0,0 +1,418 @@
+//
+// Round-trip and snapshot tests for the JSON wire format
+//   The serde tags must match the clients exactly, so any rename here is a breaking change
+//
+use proptest::prelude::*;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+use game_server::protocol::actions::{AttackAction, Direction, DropWeaponAction, MoveAction, PlayerActionEnum};
+use game_server::protocol::{
+  self, EncodedMessage, GameState, GameStateUpdate, PlayerAction, QueryRequest, SentAction, TaggedRequest, ToMsgpack,
+  WebsocketMessage,
+};
+
+//
+// Strategies for generating protocol values
+//
+fn any_uuid() -> impl Strategy<Value = Uuid> {
+  any::<u128>().prop_map(Uuid::from_u128)
+}
+
+fn any_tag() -> impl Strategy<Value = Option<String>> {
+  proptest::option::of(".{0,16}")
+}
+
+fn any_direction() -> impl Strategy<Value = Direction> {
+  prop_oneof![
+    Just(Direction::Up),
+    Just(Direction::Down),
+    Just(Direction::Left),
+    Just(Direction::Right),
+  ]
+}
+
+fn any_player_action() -> impl Strategy<Value = PlayerAction> {
+  let action = prop_oneof![
+    any_direction().prop_map(|direction| PlayerActionEnum::Move(MoveAction { direction })),
+    any_direction().prop_map(|direction| PlayerActionEnum::Attack(AttackAction { direction })),
+    Just(PlayerActionEnum::DropWeapon),
+  ];
+
+  (any_tag(), action).prop_map(|(tag, data)| TaggedRequest { tag, data })
+}
+
+fn any_query() -> impl Strategy<Value = QueryRequest> {
+  proptest::option::of("[a-zA-Z0-9-]{1,16}").prop_map(|request_id| QueryRequest { request_id })
+}
+
+fn any_websocket_message() -> impl Strategy<Value = WebsocketMessage> {
+  prop_oneof![
+    Just(WebsocketMessage::Register),
+    Just(WebsocketMessage::Unregister),
+    any_query().prop_map(WebsocketMessage::GetServerState),
+    any_query().prop_map(WebsocketMessage::GetRegisteredPlayers),
+    any_query().prop_map(WebsocketMessage::GetTimeRemaining),
+    any_query().prop_map(WebsocketMessage::GetGameConfig),
+    any_query().prop_map(WebsocketMessage::GetMyState),
+    any_query().prop_map(WebsocketMessage::GetMyActions),
+    (any_tag(), any_direction()).prop_map(|(tag, direction)| WebsocketMessage::Move(TaggedRequest {
+      tag,
+      data: MoveAction { direction }
+    })),
+    (any_tag(), any_direction()).prop_map(|(tag, direction)| WebsocketMessage::Attack(TaggedRequest {
+      tag,
+      data: AttackAction { direction }
+    })),
+    any_tag().prop_map(|tag| WebsocketMessage::DropWeapon(TaggedRequest {
+      tag,
+      data: DropWeaponAction {}
+    })),
+  ]
+}
+
+/// Game-specific fields, which must not clash with the fields the server knows about
+fn any_extra_fields() -> impl Strategy<Value = serde_json::Map<String, Value>> {
+  let value = prop_oneof![
+    any::<i64>().prop_map(Value::from),
+    any::<bool>().prop_map(Value::from),
+    ".{0,8}".prop_map(Value::from),
+  ];
+
+  proptest::collection::btree_map("extra[A-Z][a-z]{0,6}", value, 0..3).prop_map(|fields| fields.into_iter().collect())
+}
+
+/// Weapon or item lying in the arena
+fn any_object_on_ground() -> impl Strategy<Value = Value> {
+  ("[a-z0-9]{1,8}", 1..100u32, 1..100u32, any_extra_fields()).prop_map(|(id, row, col, extra)| {
+    let mut object = json!({ "id": id, "row": row, "col": col });
+    object.as_object_mut().unwrap().extend(extra);
+    object
+  })
+}
+
+/// The game state fields are private, so the state is built from JSON like the Lua code does
+fn any_game_state() -> impl Strategy<Value = GameState> {
+  let player = (
+    1..100u32,
+    1..100u32,
+    any::<i32>(),
+    proptest::option::of(any_extra_fields()),
+    any_extra_fields(),
+  )
+    .prop_map(|(row, col, health, weapon, extra)| {
+      let mut player = json!({ "row": row, "col": col, "health": health });
+      if let Some(weapon) = weapon {
+        player["weapon"] = Value::Object(weapon);
+      }
+      player.as_object_mut().unwrap().extend(extra);
+      player
+    });
+
+  (
+    proptest::collection::vec(proptest::collection::vec(0..4u32, 0..4), 0..4),
+    proptest::collection::hash_map(any_uuid(), player, 0..4),
+    proptest::collection::vec(any_object_on_ground(), 0..3),
+    proptest::collection::vec(any_object_on_ground(), 0..3),
+  )
+    .prop_map(|(playfield, players, weapons, items)| {
+      let state = json!({ "playfield": playfield, "players": players, "weapons": weapons, "items": items });
+      serde_json::from_value(state).expect("Generated game state should be valid")
+    })
+}
+
+fn any_actions_taken() -> impl Strategy<Value = HashMap<Uuid, PlayerAction>> {
+  proptest::collection::hash_map(any_uuid(), any_player_action(), 0..4)
+}
+
+/// Read a field from a serialized update back into its Rust type
+fn field<T: serde::de::DeserializeOwned>(json: &Value, name: &str) -> T {
+  serde_json::from_value(json[name].clone()).unwrap_or_else(|e| panic!("Failed to read \"{}\": {}", name, e))
+}
+
+//
+// Round-trip tests
+//
+proptest! {
+  #[test]
+  fn player_action_round_trip(action in any_player_action()) {
+    let json = serde_json::to_string(&action)?;
+    let parsed: PlayerAction = serde_json::from_str(&json)?;
+    prop_assert_eq!(parsed, action);
+  }
+
+  #[test]
+  fn tagged_request_keeps_the_tag(action in any_player_action()) {
+    let json = serde_json::to_value(&action)?;
+    prop_assert_eq!(json.get("tag").and_then(Value::as_str), action.tag.as_deref());
+    prop_assert_eq!(serde_json::from_value::<PlayerAction>(json)?.tag, action.tag);
+  }
+
+  #[test]
+  fn websocket_message_round_trip(message in any_websocket_message()) {
+    let json = serde_json::to_string(&message)?;
+    let parsed: WebsocketMessage = serde_json::from_str(&json)?;
+    prop_assert_eq!(parsed, message);
+  }
+
+  #[test]
+  fn game_state_round_trip(game_state in any_game_state()) {
+    let json = serde_json::to_string(&game_state)?;
+    let parsed: GameState = serde_json::from_str(&json)?;
+    prop_assert_eq!(parsed, game_state);
+  }
+
+  #[test]
+  fn init_round_trip(
+    match_id in any_uuid(),
+    game_state in any_game_state(),
+    ticks_left in any::<u32>(),
+    seconds_per_tick in any::<u32>(),
+    player_order in proptest::collection::vec(any_uuid(), 0..4),
+  ) {
+    let json = serde_json::to_value(GameStateUpdate::Init {
+      match_id,
+      game_state: &game_state,
+      ticks_left,
+      seconds_per_tick,
+      player_order: &player_order,
+      started_at: "2022-01-01T00:00:00+00:00",
+      protocol_version: 1,
+    })?;
+
+    prop_assert_eq!(&json["type"], "init");
+    prop_assert_eq!(field::<Uuid>(&json, "matchId"), match_id);
+    prop_assert_eq!(field::<GameState>(&json, "gameState"), game_state);
+    prop_assert_eq!(field::<u32>(&json, "ticksLeft"), ticks_left);
+    prop_assert_eq!(field::<u32>(&json, "secondsPerTick"), seconds_per_tick);
+    prop_assert_eq!(field::<Vec<Uuid>>(&json, "playerOrder"), player_order);
+  }
+
+  #[test]
+  fn next_state_round_trip(
+    match_id in any_uuid(),
+    game_state in any_game_state(),
+    actions_taken in any_actions_taken(),
+    current_tick in any::<u32>(),
+    ticks_left in any::<u32>(),
+  ) {
+    let state_hash = game_state.state_hash();
+    let json = serde_json::to_value(GameStateUpdate::NextState {
+      match_id,
+      game_state: &game_state,
+      actions_taken: &actions_taken,
+      current_tick,
+      ticks_left,
+      seconds_per_tick: 1,
+      state_hash: state_hash.as_deref(),
+    })?;
+
+    prop_assert_eq!(&json["type"], "nextState");
+    prop_assert_eq!(field::<Uuid>(&json, "matchId"), match_id);
+    prop_assert_eq!(field::<GameState>(&json, "gameState"), game_state);
+    prop_assert_eq!(field::<HashMap<Uuid, PlayerAction>>(&json, "actionsTaken"), actions_taken);
+    prop_assert_eq!(field::<u32>(&json, "currentTick"), current_tick);
+    prop_assert_eq!(field::<u32>(&json, "ticksLeft"), ticks_left);
+    prop_assert!(state_hash.is_some());
+    prop_assert_eq!(field::<Option<String>>(&json, "stateHash"), state_hash);
+  }
+
+  #[test]
+  fn encoded_msgpack_matches_direct_encoding(
+    match_id in any_uuid(),
+    game_state in any_game_state(),
+    actions_taken in any_actions_taken(),
+    ticks_left in any::<u32>(),
+  ) {
+    let state_hash = game_state.state_hash();
+    let update = GameStateUpdate::NextState {
+      match_id,
+      game_state: &game_state,
+      actions_taken: &actions_taken,
+      current_tick: 1,
+      ticks_left,
+      seconds_per_tick: 1,
+      state_hash: state_hash.as_deref(),
+    };
+    let expected = protocol::to_msgpack(&update)?;
+
+    // Broadcasts encode MessagePack lazily from the same message, which must give the same bytes
+    let encoded = EncodedMessage::from_fn(move |encode| {
+      encode(&GameStateUpdate::NextState {
+        match_id,
+        game_state: &game_state,
+        actions_taken: &actions_taken,
+        current_tick: 1,
+        ticks_left,
+        seconds_per_tick: 1,
+        state_hash: state_hash.as_deref(),
+      })
+    });
+    prop_assert_eq!(encoded.to_msgpack()?.to_vec(), expected);
+  }
+
+  #[test]
+  fn player_killed_round_trip(match_id in any_uuid(), id in any_uuid()) {
+    let json = serde_json::to_value(GameStateUpdate::PlayerKilled { match_id, id })?;
+    prop_assert_eq!(json, json!({ "type": "playerKilled", "matchId": match_id, "id": id }));
+  }
+
+  #[test]
+  fn game_ended_round_trip(
+    match_id in any_uuid(),
+    winners in proptest::collection::hash_set(any_uuid(), 0..4),
+    game_state in any_game_state(),
+    actions_taken in any_actions_taken(),
+    elimination_order in proptest::collection::vec(any_uuid(), 0..4),
+  ) {
+    let json = serde_json::to_value(GameStateUpdate::GameEnded {
+      match_id,
+      winners: &winners,
+      game_state: &game_state,
+      actions_taken: &actions_taken,
+      elimination_order: &elimination_order,
+      stats: &HashMap::new(),
+    })?;
+
+    prop_assert_eq!(&json["type"], "gameEnded");
+    prop_assert_eq!(field::<Uuid>(&json, "matchId"), match_id);
+    prop_assert_eq!(field::<HashSet<Uuid>>(&json, "winners"), winners);
+    prop_assert_eq!(field::<GameState>(&json, "gameState"), game_state);
+    prop_assert_eq!(field::<HashMap<Uuid, PlayerAction>>(&json, "actionsTaken"), actions_taken);
+    prop_assert_eq!(field::<Vec<Uuid>>(&json, "eliminationOrder"), elimination_order);
+  }
+
+  #[test]
+  fn server_shutdown_round_trip(reason in ".{0,32}") {
+    let json = serde_json::to_value(GameStateUpdate::ServerShutdown { reason: &reason })?;
+    prop_assert_eq!(json, json!({ "type": "serverShutdown", "reason": reason }));
+  }
+}
+
+//
+// Exact JSON for every action type
+//
+#[test]
+fn move_action_json() {
+  let action = PlayerAction::new(PlayerActionEnum::Move(MoveAction {
+    direction: Direction::Left,
+  }));
+  assert_eq!(
+    serde_json::to_string(&action).unwrap(),
+    r#"{"type":"move","direction":"left"}"#
+  );
+
+  let message = WebsocketMessage::Move(TaggedRequest::new_tagged(
+    MoveAction {
+      direction: Direction::Up,
+    },
+    "abc",
+  ));
+  assert_eq!(
+    serde_json::to_string(&message).unwrap(),
+    r#"{"type":"move","tag":"abc","direction":"up"}"#
+  );
+}
+
+#[test]
+fn missing_state_hash_is_left_out() {
+  let json = serde_json::to_value(GameStateUpdate::NextState {
+    match_id: Uuid::nil(),
+    game_state: &GameState::default(),
+    actions_taken: &HashMap::new(),
+    current_tick: 1,
+    ticks_left: 1,
+    seconds_per_tick: 1,
+    state_hash: None,
+  })
+  .unwrap();
+  assert!(json.get("stateHash").is_none());
+}
+
+#[test]
+fn sent_action_json() {
+  let action = PlayerAction::new(PlayerActionEnum::Move(MoveAction {
+    direction: Direction::Right,
+  }));
+  assert_eq!(
+    serde_json::to_string(&SentAction::new(3, action)).unwrap(),
+    r#"{"tick":3,"type":"move","direction":"right"}"#
+  );
+}
+
+#[test]
+fn attack_action_json() {
+  let action = PlayerAction::new_tagged(
+    PlayerActionEnum::Attack(AttackAction {
+      direction: Direction::Down,
+    }),
+    "abc",
+  );
+  assert_eq!(
+    serde_json::to_string(&action).unwrap(),
+    r#"{"tag":"abc","type":"attack","direction":"down"}"#
+  );
+
+  let message = WebsocketMessage::Attack(TaggedRequest::new(AttackAction {
+    direction: Direction::Right,
+  }));
+  assert_eq!(
+    serde_json::to_string(&message).unwrap(),
+    r#"{"type":"attack","direction":"right"}"#
+  );
+}
+
+#[test]
+fn drop_weapon_action_json() {
+  let action = PlayerAction::new(PlayerActionEnum::DropWeapon);
+  assert_eq!(serde_json::to_string(&action).unwrap(), r#"{"type":"dropWeapon"}"#);
+
+  let message = WebsocketMessage::DropWeapon(TaggedRequest::new_tagged(DropWeaponAction {}, "abc"));
+  assert_eq!(
+    serde_json::to_string(&message).unwrap(),
+    r#"{"type":"dropWeapon","tag":"abc"}"#
+  );
+}
+
+#[test]
+fn client_messages_parse() {
+  let cases = [
+    (r#"{"type":"register"}"#, WebsocketMessage::Register),
+    (r#"{"type":"unregister"}"#, WebsocketMessage::Unregister),
+    (
+      r#"{"type":"getServerState","requestId":"1"}"#,
+      WebsocketMessage::GetServerState(QueryRequest {
+        request_id: Some("1".into()),
+      }),
+    ),
+    (
+      r#"{"type":"move","direction":"up","tag":"abc"}"#,
+      WebsocketMessage::Move(TaggedRequest::new_tagged(
+        MoveAction {
+          direction: Direction::Up,
+        },
+        "abc",
+      )),
+    ),
+    (
+      r#"{"type":"attack","direction":"left"}"#,
+      WebsocketMessage::Attack(TaggedRequest::new(AttackAction {
+        direction: Direction::Left,
+      })),
+    ),
+    (
+      r#"{"type":"dropWeapon"}"#,
+      WebsocketMessage::DropWeapon(TaggedRequest::new(DropWeaponAction {})),
+    ),
+  ];
+
+  for (json, expected) in cases {
+    assert_eq!(
+      serde_json::from_str::<WebsocketMessage>(json).unwrap(),
+      expected,
+      "{}",
+      json
+    );
+  }
+}
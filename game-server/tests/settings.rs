@@ -0,0 +1,131 @@
+//
+// End-to-end tests for settings that are read from environment variables
+//   These run in their own test binary, since changing the environment would affect every other test
+//   Run with "cargo test --features testing"
+//
+use awc::ws::CloseCode;
+use game_server::actors::mediator_messages::GetRegisteredPlayers;
+use game_server::actors::MediatorConfig;
+use game_server::testing::TestServer;
+use std::env;
+use std::sync::Once;
+use std::time::{Duration, Instant};
+
+/// Empty arena that never ends the game early
+const EMPTY_ARENA: &str = r#"
+  local function emptyArena(ctx)
+    local rows, cols = ctx:getArenaSize()
+    local playfield = {}
+    for row = 1, rows do
+      playfield[row] = {}
+      for col = 1, cols do
+        playfield[row][col] = 0
+      end
+    end
+    return { playfield = playfield }
+  end
+
+  function Init(ctx, players)
+    return emptyArena(ctx)
+  end
+
+  function Update(ctx, actions)
+    return emptyArena(ctx)
+  end
+"#;
+
+/// Shortest round allowed, instead of the default 180 ticks
+const TICKS_PER_GAME: u32 = 30;
+
+/// Close players that stay silent in the lobby, while still pinging them every second
+const PLAYER_IDLE_TIMEOUT_SECONDS: u64 = 3;
+
+/// Set the environment once, before any test starts a server
+fn init_settings() {
+  static INIT: Once = Once::new();
+  INIT.call_once(|| {
+    env::set_var("TICKS_PER_GAME", TICKS_PER_GAME.to_string());
+    env::set_var("SECONDS_PER_TICK", "1");
+    env::set_var("PLAYER_IDLE_TIMEOUT_SECONDS", PLAYER_IDLE_TIMEOUT_SECONDS.to_string());
+    env::set_var("HEARTBEAT_INTERVAL_SECONDS", "1");
+  });
+}
+
+fn two_player_settings() -> MediatorConfig {
+  MediatorConfig {
+    min_players_needed: 2,
+    max_players_allowed: 2,
+    lobby_wait_secs: 1,
+    start_when_full: true,
+    bot_fill_secs: None,
+    connect_grace_secs: 5,
+    unregister_on_disconnect: false,
+    reconnect_replaces_old: false,
+    use_delta_updates: false,
+    registration_deltas: false,
+    replay_dir: None,
+  }
+}
+
+#[actix_web::test]
+async fn round_lasts_the_configured_number_of_ticks() -> anyhow::Result<()> {
+  init_settings();
+  let server = TestServer::start(EMPTY_ARENA, two_player_settings()).await?;
+  let (_, mut alice) = server.connect_player("alice").await?;
+  let (_, mut bob) = server.connect_player("bob").await?;
+  let mut viewer = server.connect_viewer().await?;
+
+  for player in [&mut alice, &mut bob] {
+    player.send(&serde_json::json!({ "type": "register" })).await?;
+  }
+
+  let init = viewer.recv_type("init").await?;
+  assert_eq!(init["ticksLeft"], TICKS_PER_GAME);
+  let started = Instant::now();
+
+  // Every tick counts down, until the game ends on its own
+  let mut ticks_left = TICKS_PER_GAME;
+  loop {
+    let message = viewer.recv().await?;
+    match message["type"].as_str() {
+      Some("nextState") => {
+        ticks_left -= 1;
+        assert_eq!(message["ticksLeft"], ticks_left);
+      },
+      Some("gameEnded") => break,
+      _ => {},
+    }
+  }
+
+  // Much sooner than the 180 second default
+  let elapsed = started.elapsed();
+  assert!(
+    elapsed < Duration::from_secs(TICKS_PER_GAME as u64 + 5),
+    "Round took {:?}",
+    elapsed
+  );
+
+  server.stop().await;
+  Ok(())
+}
+
+#[actix_web::test]
+async fn idle_player_is_unregistered_and_closed() -> anyhow::Result<()> {
+  init_settings();
+  let server = TestServer::start(EMPTY_ARENA, two_player_settings()).await?;
+  let (_, mut alice) = server.connect_player("alice").await?;
+  alice.send(&serde_json::json!({ "type": "register" })).await?;
+  alice.recv_type("waitingOnPlayers").await?;
+  let started = Instant::now();
+
+  // The client keeps answering the heartbeat pings, but never sends anything else
+  let reason = alice.recv_close().await?;
+  assert_eq!(reason.map(|reason| reason.code), Some(CloseCode::Away));
+  assert!(started.elapsed() >= Duration::from_secs(PLAYER_IDLE_TIMEOUT_SECONDS - 1));
+
+  let registered = server.get_mediator().send(GetRegisteredPlayers).await?;
+  assert!(registered.players.is_empty());
+
+  server.stop().await;
+  Ok(())
+}
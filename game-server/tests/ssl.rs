@@ -0,0 +1,45 @@
+//
+// Loading the SSL certificate and key files
+//   The fixtures are a self-signed certificate for "localhost" and its private key
+//
+use game_server::ssl::load_ssl_config;
+use std::path::{Path, PathBuf};
+
+fn fixture(name: &str) -> PathBuf {
+  Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+#[test]
+fn loads_the_certificate_and_key() {
+  load_ssl_config(&fixture("cert.pem"), &fixture("key.pem")).unwrap();
+}
+
+#[test]
+fn swapped_files_are_an_error() {
+  let error = load_ssl_config(&fixture("key.pem"), &fixture("cert.pem"))
+    .err()
+    .unwrap();
+  assert!(
+    error.to_string().contains("Could not locate any certificates"),
+    "{}",
+    error
+  );
+}
+
+#[test]
+fn missing_file_is_an_error() {
+  let error = load_ssl_config(&fixture("missing.pem"), &fixture("key.pem"))
+    .err()
+    .unwrap();
+  assert!(error.to_string().contains("Failed to open"), "{}", error);
+}
+
+#[test]
+fn certificate_file_without_certificates_is_an_error() {
+  let error = load_ssl_config(&fixture("key.pem"), &fixture("key.pem")).err().unwrap();
+  assert!(
+    error.to_string().contains("Could not locate any certificates"),
+    "{}",
+    error
+  );
+}
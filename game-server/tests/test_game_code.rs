@@ -0,0 +1,122 @@
+//
+// Regression tests that run the "test_game_code" executable on Lua code that used to crash the process
+//   The executable must exit with a normal game engine error, not abort
+//
+use std::env;
+use std::fs;
+use std::process::{Command, Output};
+
+/// Empty arena, with the given Lua code run inside Update()
+fn lua_with_update(update_code: &str) -> String {
+  format!(
+    r#"
+    local function emptyArena(ctx)
+      local rows, cols = ctx:getArenaSize()
+      local playfield = {{}}
+      for row = 1, rows do
+        playfield[row] = {{}}
+        for col = 1, cols do
+          playfield[row][col] = 0
+        end
+      end
+      return {{ playfield = playfield }}
+    end
+
+    function Init(ctx, players)
+      return emptyArena(ctx)
+    end
+
+    function Update(ctx, actions)
+      {}
+      return emptyArena(ctx)
+    end
+    "#,
+    update_code
+  )
+}
+
+/// Run the game code in "test_game_code", returning the exit status and all output
+fn run_game_code(name: &str, lua_code: &str) -> anyhow::Result<(Output, String)> {
+  let lua_file = env::temp_dir().join(format!("game-server-{}-{}.lua", name, std::process::id()));
+  fs::write(&lua_file, lua_code)?;
+
+  let output = Command::new(env!("CARGO_BIN_EXE_test_game_code"))
+    .arg("--lua-file")
+    .arg(&lua_file)
+    .args(["--max-engine-tries", "2", "--num-players", "2", "--seed", "1"])
+    .output()?;
+  fs::remove_file(&lua_file)?;
+
+  let text = format!(
+    "{}{}",
+    String::from_utf8_lossy(&output.stdout),
+    String::from_utf8_lossy(&output.stderr)
+  );
+  Ok((output, text))
+}
+
+#[test]
+fn recursive_update_is_a_game_engine_error() -> anyhow::Result<()> {
+  let lua_code = lua_with_update(
+    r#"
+    local function recurse(n)
+      return 1 + recurse(n + 1)
+    end
+    recurse(1)
+    "#,
+  );
+
+  let (output, text) = run_game_code("recursive-update", &lua_code)?;
+  assert_eq!(output.status.code(), Some(1), "{}", text);
+  assert!(text.contains("Game engine error"), "{}", text); // Logged by trap_errors
+  assert!(text.contains("(Attempt 2 / 2)"), "{}", text);
+  assert!(text.contains("Failed to run method Update"), "{}", text);
+  assert!(text.contains("stack overflow"), "{}", text);
+  Ok(())
+}
+
+#[test]
+fn recursion_through_pcall_is_a_game_engine_error() -> anyhow::Result<()> {
+  let lua_code = lua_with_update(
+    r#"
+    local function recurse(n)
+      local ok, err = pcall(recurse, n + 1)
+      if not ok then
+        error(err, 0)
+      end
+    end
+    recurse(1)
+    "#,
+  );
+
+  let (output, text) = run_game_code("recursive-pcall", &lua_code)?;
+  assert_eq!(output.status.code(), Some(1), "{}", text);
+  assert!(text.contains("(Attempt 2 / 2)"), "{}", text);
+  assert!(text.contains("Failed to run method Update"), "{}", text);
+  assert!(text.contains("stack overflow"), "{}", text);
+  Ok(())
+}
+
+#[test]
+fn pcall_with_many_results_does_not_abort() -> anyhow::Result<()> {
+  // Each level returns one more value than the level below, until the C stack runs out
+  let lua_code = lua_with_update(
+    r##"
+    local function recurse(n)
+      return pcall(recurse, n + 1)
+    end
+    assert(recurse(1))
+
+    local values = {}
+    for i = 1, 100 do
+      values[i] = i
+    end
+    assert(select("#", pcall(table.unpack, values)) == 101)
+    assert(select("#", xpcall(table.unpack, print, values)) == 101)
+    "##,
+  );
+
+  let (output, text) = run_game_code("pcall-results", &lua_code)?;
+  assert_eq!(output.status.code(), Some(0), "{}", text);
+  Ok(())
+}